@@ -0,0 +1,495 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct BitReduce(ObjectSubclass<imp::BitReduce>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstBitReduceDither")]
+pub enum Dither {
+    #[enum_value(name = "Round each sample to the nearest level, leaving clean bands", nick = "none")]
+    Off = 0,
+    #[enum_value(name = "Ordered dither with an 8x8 Bayer matrix", nick = "ordered")]
+    Ordered = 1,
+    #[enum_value(name = "Floyd-Steinberg error diffusion", nick = "error-diffusion")]
+    ErrorDiffusion = 2,
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "bitReduce",
+        gst::Rank::NONE,
+        BitReduce::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::Dither;
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "bitReduce",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Bit Reduce"),
+        )
+    });
+
+    const DEFAULT_BITS: u32 = 6;
+    const DEFAULT_DITHER: Dither = Dither::Off;
+
+    const FORMATS: [VideoFormat; 9] = [
+        VideoFormat::I420,
+        VideoFormat::Y42b,
+        VideoFormat::Y444,
+        VideoFormat::Nv12,
+        VideoFormat::Gray8,
+        VideoFormat::I42010le,
+        VideoFormat::I42210le,
+        VideoFormat::Y44410le,
+        VideoFormat::P01010le,
+    ];
+
+    /// The 8x8 Bayer matrix. Each entry is the rank of its threshold, from 0
+    /// to 63.
+    #[rustfmt::skip]
+    const BAYER: [[u8; 8]; 8] = [
+        [ 0, 32,  8, 40,  2, 34, 10, 42],
+        [48, 16, 56, 24, 50, 18, 58, 26],
+        [12, 44,  4, 36, 14, 46,  6, 38],
+        [60, 28, 52, 20, 62, 30, 54, 22],
+        [ 3, 35, 11, 43,  1, 33,  9, 41],
+        [51, 19, 59, 27, 49, 17, 57, 25],
+        [15, 47,  7, 39, 13, 45,  5, 37],
+        [63, 31, 55, 23, 61, 29, 53, 21],
+    ];
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        bits: u32,
+        dither: Dither,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                bits: DEFAULT_BITS,
+                dither: DEFAULT_DITHER,
+            }
+        }
+    }
+
+    /// Maps samples of one bit depth onto the `2^bits` levels that span the same
+    /// range, so black and white stay where they are.
+    struct Quantizer {
+        max: f32,
+        levels: f32,
+    }
+
+    impl Quantizer {
+        fn new(depth: u32, bits: u32) -> Self {
+            Self {
+                max: ((1 << depth) - 1) as f32,
+                levels: ((1 << bits) - 1) as f32,
+            }
+        }
+
+        /// Distance between neighbouring levels, in samples.
+        fn step(&self) -> f32 {
+            self.max / self.levels
+        }
+
+        fn quantize(&self, value: f32) -> u32 {
+            let level = (value / self.step()).round().clamp(0.0, self.levels);
+            (level * self.step()).round() as u32
+        }
+    }
+
+    /// Where one component of a frame lives in its plane, and how its samples
+    /// are packed.
+    struct Component {
+        plane: u32,
+        poffset: usize,
+        pixel_stride: usize,
+        width: usize,
+        height: usize,
+        depth: u32,
+        /// How far each sample is shifted up in its 16-bit word.
+        shift: u32,
+    }
+
+    impl Component {
+        fn new(frame: &gst_video::VideoFrameRef<&mut gst::BufferRef>, comp: usize) -> Self {
+            let format_info = frame.format_info();
+            Self {
+                plane: format_info.plane()[comp],
+                poffset: format_info.poffset()[comp] as usize,
+                pixel_stride: format_info.pixel_stride()[comp] as usize,
+                width: frame.comp_width(comp as u8) as usize,
+                height: frame.comp_height(comp as u8) as usize,
+                depth: format_info.depth()[comp],
+                shift: format_info.shift()[comp],
+            }
+        }
+
+        fn read(&self, line: &[u8], x: usize) -> u32 {
+            let i = self.poffset + x * self.pixel_stride;
+            if self.depth > 8 {
+                (u16::from_le_bytes([line[i], line[i + 1]]) >> self.shift) as u32
+            } else {
+                line[i] as u32
+            }
+        }
+
+        fn write(&self, line: &mut [u8], x: usize, value: u32) {
+            let i = self.poffset + x * self.pixel_stride;
+            if self.depth > 8 {
+                line[i..i + 2].copy_from_slice(&((value as u16) << self.shift).to_le_bytes());
+            } else {
+                line[i] = value as u8;
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct BitReduce {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for BitReduce {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecUInt::builder("bits")
+                        .nick("bits")
+                        .blurb("Effective bits left in every plane. Planes with no more bits than this are left alone")
+                        .minimum(1)
+                        .maximum(16)
+                        .default_value(DEFAULT_BITS)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("dither", DEFAULT_DITHER)
+                        .nick("dither")
+                        .blurb("How the samples are dithered before they are rounded to the levels")
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "bits" => settings.bits = value.get().expect("type checked upstream"),
+                "dither" => settings.dither = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "bits" => settings.bits.to_value(),
+                "dither" => settings.dither.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for BitReduce {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for BitReduce {
+        const NAME: &'static str = "bitReduce";
+        type Type = super::BitReduce;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for BitReduce {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+    }
+
+    impl ElementImpl for BitReduce {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Bit Reduce Tool",
+                    "Filter/Effect/Video",
+                    "Quantise each plane to fewer effective bits to test banding detection.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for BitReduce {
+        fn transform_frame_ip(
+            &self,
+            frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+
+            for comp in 0..frame.format_info().n_components() as usize {
+                let comp = Component::new(frame, comp);
+                if settings.bits >= comp.depth {
+                    continue;
+                }
+                let quantizer = Quantizer::new(comp.depth, settings.bits);
+                let stride = frame.plane_stride()[comp.plane as usize] as usize;
+                let data = frame.plane_data_mut(comp.plane).unwrap();
+                let lines = data.chunks_mut(stride).take(comp.height);
+                match settings.dither {
+                    Dither::Off => {
+                        for line in lines {
+                            for x in 0..comp.width {
+                                let value = quantizer.quantize(comp.read(line, x) as f32);
+                                comp.write(line, x, value);
+                            }
+                        }
+                    }
+                    Dither::Ordered => {
+                        // Spread the thresholds evenly across one step, centred on 0.
+                        for (y, line) in lines.enumerate() {
+                            for x in 0..comp.width {
+                                let threshold = (BAYER[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5;
+                                let value = comp.read(line, x) as f32 + threshold * quantizer.step();
+                                comp.write(line, x, quantizer.quantize(value));
+                            }
+                        }
+                    }
+                    Dither::ErrorDiffusion => {
+                        // Errors owed to this row and the next, with a spare
+                        // column on either side so the edges need no checks.
+                        let mut errors = vec![0.0f32; comp.width + 2];
+                        let mut next_errors = vec![0.0f32; comp.width + 2];
+                        for line in lines {
+                            for x in 0..comp.width {
+                                let value = comp.read(line, x) as f32 + errors[x + 1];
+                                let quantized = quantizer.quantize(value);
+                                comp.write(line, x, quantized);
+                                let error = value - quantized as f32;
+                                errors[x + 2] += error * 7.0 / 16.0;
+                                next_errors[x] += error * 3.0 / 16.0;
+                                next_errors[x + 1] += error * 5.0 / 16.0;
+                                next_errors[x + 2] += error / 16.0;
+                            }
+                            std::mem::swap(&mut errors, &mut next_errors);
+                            next_errors.fill(0.0);
+                        }
+                    }
+                }
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use test::Bencher;
+
+        fn run_plugin_tests(
+            settings: Settings,
+            format: VideoFormat,
+            width: u32,
+            height: u32,
+            data: Vec<u8>,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let _ = gst::init();
+
+            let plugin = BitReduce {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(format, width, height)
+                .build()
+                .unwrap();
+            assert_eq!(data.len(), info.size());
+            let buffer = gst::Buffer::from_slice(data);
+            let mut frame = gst_video::VideoFrame::from_buffer_writable(buffer, &info).unwrap();
+            plugin
+                .transform_frame_ip(&mut frame.as_mut_video_frame_ref())
+                .unwrap();
+            frame
+        }
+
+        fn settings(bits: u32, dither: Dither) -> Settings {
+            Settings { bits, dither }
+        }
+
+        #[test]
+        fn test_levels_span_full_range() {
+            let ramp = (0..=255).collect::<Vec<u8>>();
+            let frame = run_plugin_tests(settings(2, Dither::Off), VideoFormat::Gray8, 256, 1, ramp);
+            let out = frame.plane_data(0).unwrap();
+            assert_eq!(out[..3], [0, 0, 0]);
+            assert_eq!(out[42..44], [0, 85]);
+            assert_eq!(out[127..129], [85, 170]);
+            assert_eq!(out[212..214], [170, 255]);
+            assert_eq!(out[255], 255);
+        }
+
+        #[test]
+        fn test_10_bit_to_8_levels() {
+            // A ramp through every 10-bit code in the luma of a 1024x2 frame.
+            let info = gst_video::VideoInfo::builder(VideoFormat::I42010le, 1024, 2)
+                .build()
+                .unwrap();
+            let mut data = vec![0; info.size()];
+            for x in 0..1024u16 {
+                data[2 * x as usize..2 * x as usize + 2].copy_from_slice(&x.to_le_bytes());
+            }
+            let frame = run_plugin_tests(settings(8, Dither::Off), VideoFormat::I42010le, 1024, 2, data);
+            let luma = frame.plane_data(0).unwrap()[..2048]
+                .chunks_exact(2)
+                .map(|sample| u16::from_le_bytes([sample[0], sample[1]]))
+                .collect::<Vec<_>>();
+            let mut levels = luma.clone();
+            levels.dedup();
+            assert_eq!(levels.len(), 256);
+            assert!(luma.iter().zip(0..).all(|(&out, input)| (out as i32 - input).abs() <= 2));
+        }
+
+        #[test]
+        fn test_p010_keeps_white_and_shift() {
+            // Two rows of two luma samples and one UV pair, each shifted up 6 bits.
+            let data = [1023u16, 1023, 513, 0, 512, 512]
+                .iter()
+                .flat_map(|sample| (sample << 6).to_le_bytes())
+                .collect();
+            let frame = run_plugin_tests(settings(4, Dither::Off), VideoFormat::P01010le, 2, 2, data);
+            let luma = frame
+                .plane_data(0)
+                .unwrap()
+                .chunks_exact(2)
+                .map(|sample| u16::from_le_bytes([sample[0], sample[1]]) >> 6)
+                .collect::<Vec<_>>();
+            // White stays white, and 513 goes to the nearest 4-bit level, 8/15 of it.
+            assert_eq!(luma, [1023, 1023, 546, 0]);
+        }
+
+        #[test]
+        fn test_ordered_dither_keeps_average() {
+            // 64 is a quarter of the way from black to white, so a quarter of the
+            // Bayer thresholds tip it over to white.
+            let frame = run_plugin_tests(settings(1, Dither::Ordered), VideoFormat::Gray8, 8, 8, vec![64; 64]);
+            let out = frame.plane_data(0).unwrap();
+            assert!(out.iter().all(|&s| s == 0 || s == 255));
+            assert_eq!(out.iter().filter(|&&s| s == 255).count(), 16);
+        }
+
+        #[test]
+        fn test_error_diffusion_keeps_average() {
+            let frame = run_plugin_tests(
+                settings(1, Dither::ErrorDiffusion),
+                VideoFormat::Gray8,
+                32,
+                32,
+                vec![64; 32 * 32],
+            );
+            let out = frame.plane_data(0).unwrap();
+            assert!(out.iter().all(|&s| s == 0 || s == 255));
+            let mean = out.iter().map(|&s| s as f64).sum::<f64>() / out.len() as f64;
+            assert!((mean - 64.0).abs() < 4.0, "mean {mean}");
+        }
+
+        #[test]
+        fn test_enough_bits_leaves_frame_alone() {
+            let ramp = (0..=255).collect::<Vec<u8>>();
+            let frame = run_plugin_tests(settings(8, Dither::Ordered), VideoFormat::Gray8, 256, 1, ramp.clone());
+            assert_eq!(frame.plane_data(0).unwrap(), ramp);
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, dither: Dither, b: &mut Bencher) {
+            let _ = gst::init();
+            let settings = test::black_box(Settings {
+                bits: 5,
+                dither,
+            });
+
+            let plugin = BitReduce {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, width, height)
+                .build()
+                .unwrap();
+            let data_frame = vec![0; info.size()];
+            let buffer_frame = gst::Buffer::from_slice(data_frame);
+            let mut frame = test::black_box(
+                gst_video::VideoFrame::from_buffer_writable(buffer_frame, &info).unwrap(),
+            );
+
+            b.iter(|| plugin.transform_frame_ip(&mut frame.as_mut_video_frame_ref()));
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, Dither::Off, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, Dither::Off, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, Dither::Off, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p_error_diffusion(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, Dither::ErrorDiffusion, b);
+        }
+    }
+}
@@ -0,0 +1,643 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct BlockArtifact(ObjectSubclass<imp::BlockArtifact>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstBlockArtifactBlockSize")]
+pub enum BlockSize {
+    #[enum_value(name = "4x4 blocks", nick = "4")]
+    Four = 4,
+    #[enum_value(name = "8x8 blocks, as in JPEG and MPEG-2", nick = "8")]
+    Eight = 8,
+    #[enum_value(name = "16x16 blocks", nick = "16")]
+    Sixteen = 16,
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "blockArtifact",
+        gst::Rank::NONE,
+        BlockArtifact::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::BlockSize;
+    use crate::kernels;
+
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "blockArtifact",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Block Artifact"),
+        )
+    });
+
+    const DEFAULT_BLOCK_SIZE: BlockSize = BlockSize::Eight;
+    const DEFAULT_QUALITY: u32 = 50;
+    const DEFAULT_N_THREADS: u32 = 1;
+
+    const FORMATS: [VideoFormat; 5] = [
+        VideoFormat::I420,
+        VideoFormat::Y42b,
+        VideoFormat::Y444,
+        VideoFormat::Nv12,
+        VideoFormat::Gray8,
+    ];
+
+    /// The example luminance quantisation table of JPEG (ITU-T T.81, Annex K).
+    #[rustfmt::skip]
+    const LUMA_STEPS: [u8; 64] = [
+        16, 11, 10, 16, 24, 40, 51, 61,
+        12, 12, 14, 19, 26, 58, 60, 55,
+        14, 13, 16, 24, 40, 57, 69, 56,
+        14, 17, 22, 29, 51, 87, 80, 62,
+        18, 22, 37, 56, 68, 109, 103, 77,
+        24, 35, 55, 64, 81, 104, 113, 92,
+        49, 64, 78, 87, 103, 121, 120, 101,
+        72, 92, 95, 98, 112, 100, 103, 99,
+    ];
+
+    /// The example chrominance quantisation table of JPEG (ITU-T T.81, Annex K).
+    #[rustfmt::skip]
+    const CHROMA_STEPS: [u8; 64] = [
+        17, 18, 24, 47, 99, 99, 99, 99,
+        18, 21, 26, 66, 99, 99, 99, 99,
+        24, 26, 56, 99, 99, 99, 99, 99,
+        47, 66, 99, 99, 99, 99, 99, 99,
+        99, 99, 99, 99, 99, 99, 99, 99,
+        99, 99, 99, 99, 99, 99, 99, 99,
+        99, 99, 99, 99, 99, 99, 99, 99,
+        99, 99, 99, 99, 99, 99, 99, 99,
+    ];
+
+    #[derive(Debug, Clone)]
+    struct Settings {
+        block_size: BlockSize,
+        quality: u32,
+        /// Quantiser steps in row major order, replacing the scaled JPEG tables
+        /// for every plane when there is one for each coefficient of a block.
+        quant_matrix: Vec<u16>,
+        n_threads: u32,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                block_size: DEFAULT_BLOCK_SIZE,
+                quality: DEFAULT_QUALITY,
+                quant_matrix: vec![],
+                n_threads: DEFAULT_N_THREADS,
+            }
+        }
+    }
+
+    /// Steps for a block of `size` from one of the 8x8 JPEG tables, scaled by
+    /// `quality` the way libjpeg does. Other block sizes take the step of the
+    /// nearest frequency in the 8x8 table.
+    fn quality_steps(base: &[u8; 64], quality: u32, size: usize) -> Vec<f32> {
+        let quality = quality.clamp(1, 100);
+        let scale = if quality < 50 {
+            5000 / quality
+        } else {
+            200 - 2 * quality
+        };
+        (0..size * size)
+            .map(|i| {
+                let (u, v) = (i / size, i % size);
+                let base = base[(u * 8 / size).min(7) * 8 + (v * 8 / size).min(7)] as u32;
+                ((base * scale + 50) / 100).clamp(1, 255) as f32
+            })
+            .collect()
+    }
+
+    /// The orthonormal DCT of one block size, and the quantiser steps for luma
+    /// and chroma.
+    struct Quantizer {
+        size: usize,
+        /// `basis[u * size + x]` is DCT basis function `u` at sample `x`.
+        basis: Vec<f32>,
+        steps: [Vec<f32>; 2],
+    }
+
+    impl Quantizer {
+        fn new(settings: &Settings) -> Self {
+            let size = settings.block_size as usize;
+            let basis = (0..size * size)
+                .map(|i| {
+                    let (u, x) = (i / size, i % size);
+                    let scale = if u == 0 { 1.0 } else { 2.0 };
+                    let phase = std::f64::consts::PI * ((2 * x + 1) * u) as f64 / (2 * size) as f64;
+                    ((scale / size as f64).sqrt() * phase.cos()) as f32
+                })
+                .collect();
+            let steps = if settings.quant_matrix.len() == size * size {
+                let steps = settings.quant_matrix.iter().map(|&step| step as f32).collect::<Vec<_>>();
+                [steps.clone(), steps]
+            } else {
+                [
+                    quality_steps(&LUMA_STEPS, settings.quality, size),
+                    quality_steps(&CHROMA_STEPS, settings.quality, size),
+                ]
+            };
+            Self { size, basis, steps }
+        }
+
+        /// Transform a level shifted block, quantise its coefficients with
+        /// `steps` and transform it back. `scratch` is a block of working space.
+        fn quantize_block(&self, block: &mut [f32], steps: &[f32], scratch: &mut [f32]) {
+            let n = self.size;
+            let c = &self.basis;
+            for u in 0..n {
+                for x in 0..n {
+                    scratch[u * n + x] = (0..n).map(|y| c[u * n + y] * block[y * n + x]).sum();
+                }
+            }
+            for u in 0..n {
+                for v in 0..n {
+                    let coeff: f32 = (0..n).map(|x| scratch[u * n + x] * c[v * n + x]).sum();
+                    let step = steps[u * n + v];
+                    block[u * n + v] = (coeff / step).round() * step;
+                }
+            }
+            for y in 0..n {
+                for v in 0..n {
+                    scratch[y * n + v] = (0..n).map(|u| c[u * n + y] * block[u * n + v]).sum();
+                }
+            }
+            for y in 0..n {
+                for x in 0..n {
+                    block[y * n + x] = (0..n).map(|v| scratch[y * n + v] * c[v * n + x]).sum();
+                }
+            }
+        }
+
+        /// Quantise every block of one component along a band of `rows` rows,
+        /// which is a whole block high except at the bottom of the frame. Blocks
+        /// that run off the right or bottom of the component repeat its edge
+        /// samples, and only the samples inside it are written back.
+        #[allow(clippy::too_many_arguments)]
+        fn quantize_band(
+            &self,
+            band: &mut [u8],
+            stride: usize,
+            rows: usize,
+            width: usize,
+            poffset: usize,
+            pixel_stride: usize,
+            steps: &[f32],
+        ) {
+            let n = self.size;
+            let mut block = vec![0.0; n * n];
+            let mut scratch = vec![0.0; n * n];
+            let index = |x: usize, y: usize| y * stride + poffset + x * pixel_stride;
+            for left in (0..width).step_by(n) {
+                for y in 0..n {
+                    for x in 0..n {
+                        let sample = band[index((left + x).min(width - 1), y.min(rows - 1))];
+                        block[y * n + x] = sample as f32 - 128.0;
+                    }
+                }
+                self.quantize_block(&mut block, steps, &mut scratch);
+                for y in 0..rows {
+                    for x in 0..n.min(width - left) {
+                        band[index(left + x, y)] = (block[y * n + x] + 128.0).round().clamp(0.0, 255.0) as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct BlockArtifact {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for BlockArtifact {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecEnum::builder_with_default("block-size", DEFAULT_BLOCK_SIZE)
+                        .nick("block-size")
+                        .blurb("Width and height of the transform blocks")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("quality")
+                        .nick("quality")
+                        .blurb("JPEG style quality the standard tables are scaled to, from 1 (worst) to 100")
+                        .minimum(1)
+                        .maximum(100)
+                        .default_value(DEFAULT_QUALITY)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecString::builder("quant-matrix")
+                        .nick("quant-matrix")
+                        .blurb("Comma separated quantiser steps in row major order, one per coefficient of a block, used for every plane instead of the quality")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("n-threads")
+                        .nick("n-threads")
+                        .blurb("Number of threads to split each frame across, or 0 for one per CPU")
+                        .default_value(DEFAULT_N_THREADS)
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "block-size" => settings.block_size = value.get().expect("type checked upstream"),
+                "quality" => settings.quality = value.get().expect("type checked upstream"),
+                "quant-matrix" => {
+                    let matrix = value.get::<Option<String>>().expect("type checked upstream");
+                    settings.quant_matrix = self.parse_matrix(matrix.as_deref().unwrap_or(""));
+                }
+                "n-threads" => settings.n_threads = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "block-size" => settings.block_size.to_value(),
+                "quality" => settings.quality.to_value(),
+                "quant-matrix" => settings
+                    .quant_matrix
+                    .iter()
+                    .map(|step| step.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+                    .to_value(),
+                "n-threads" => settings.n_threads.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for BlockArtifact {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for BlockArtifact {
+        const NAME: &'static str = "blockArtifact";
+        type Type = super::BlockArtifact;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for BlockArtifact {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+    }
+
+    impl ElementImpl for BlockArtifact {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Block Artifact Tool",
+                    "Filter/Effect/Video",
+                    "Quantise the DCT of each block to test blockiness and no-reference quality metrics.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for BlockArtifact {
+        fn transform_frame_ip(
+            &self,
+            frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = self.settings.lock().unwrap().clone();
+            let quantizer = Quantizer::new(&settings);
+            let n_threads = kernels::thread_count(settings.n_threads);
+            let size = quantizer.size;
+            let format_info = frame.format_info();
+
+            // Each component is blocked on its own grid, so the chroma blocks of
+            // subsampled formats cover more of the picture than the luma ones.
+            let grids = (0..format_info.n_components() as usize)
+                .map(|comp| Grid {
+                    plane: format_info.plane()[comp] as usize,
+                    poffset: format_info.poffset()[comp] as usize,
+                    pixel_stride: format_info.pixel_stride()[comp] as usize,
+                    width: frame.comp_width(comp as u8) as usize,
+                    height: frame.comp_height(comp as u8) as usize,
+                    steps: &quantizer.steps[(comp > 0) as usize],
+                })
+                .collect::<Vec<_>>();
+
+            // Components sharing a plane are quantised band by band together, and
+            // every plane is handed to the same threads.
+            let strides = frame.plane_stride().to_vec();
+            let n_planes = frame.n_planes() as usize;
+            let planes = frame
+                .planes_data_mut()
+                .into_iter()
+                .take(n_planes)
+                .enumerate()
+                .map(|(plane, data)| {
+                    let grids = grids.iter().filter(|grid| grid.plane == plane);
+                    let height = grids.map(|grid| grid.height).max().unwrap_or(0);
+                    kernels::Rows {
+                        data,
+                        stride: size * strides[plane] as usize,
+                        rows: height.div_ceil(size),
+                    }
+                })
+                .collect();
+            kernels::for_each_plane_row(planes, n_threads, |plane, band, data| {
+                let stride = strides[plane] as usize;
+                for grid in grids.iter().filter(|grid| grid.plane == plane) {
+                    let rows = size.min(grid.height.saturating_sub(band * size));
+                    quantizer.quantize_band(
+                        data,
+                        stride,
+                        rows,
+                        grid.width,
+                        grid.poffset,
+                        grid.pixel_stride,
+                        grid.steps,
+                    );
+                }
+            });
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    /// Where the samples of one component are and which steps quantise them.
+    struct Grid<'a> {
+        plane: usize,
+        poffset: usize,
+        pixel_stride: usize,
+        width: usize,
+        height: usize,
+        steps: &'a [f32],
+    }
+
+    impl BlockArtifact {
+        /// Comma separated quantiser steps. Anything that isn't a step from 1 to
+        /// 65535 is skipped.
+        fn parse_matrix(&self, matrix: &str) -> Vec<u16> {
+            let steps = matrix
+                .split(',')
+                .map(str::trim)
+                .filter(|step| !step.is_empty())
+                .filter_map(|step| match step.parse() {
+                    Ok(step) if step > 0 => Some(step),
+                    _ => {
+                        gst::warning!(CAT, imp = self, "Ignoring quantiser step {step:?}");
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            if !steps.is_empty() && ![16, 64, 256].contains(&steps.len()) {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "A quantiser matrix of {} steps doesn't fit any block size, so the quality will be used",
+                    steps.len()
+                );
+            }
+            steps
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use test::Bencher;
+
+        fn run_plugin_tests(
+            settings: Settings,
+            format: VideoFormat,
+            width: u32,
+            height: u32,
+            luma: &[u8],
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let _ = gst::init();
+
+            let plugin = BlockArtifact {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(format, width, height)
+                .build()
+                .unwrap();
+            let mut data = vec![128; info.size()];
+            data[..luma.len()].copy_from_slice(luma);
+            let buffer = gst::Buffer::from_slice(data);
+            let mut frame = gst_video::VideoFrame::from_buffer_writable(buffer, &info).unwrap();
+            plugin
+                .transform_frame_ip(&mut frame.as_mut_video_frame_ref())
+                .unwrap();
+            frame
+        }
+
+        #[test]
+        fn test_flat_block_keeps_quantised_dc() {
+            let settings = Settings::default();
+            let frame = run_plugin_tests(settings, VideoFormat::Gray8, 8, 8, &[100; 64]);
+            assert_eq!(frame.plane_data(0).unwrap(), [100; 64]);
+
+            // At quality 1 the DC step is 255, so 8 * (100 - 128) rounds to -255.
+            let settings = Settings {
+                quality: 1,
+                ..Default::default()
+            };
+            let frame = run_plugin_tests(settings, VideoFormat::Gray8, 8, 8, &[100; 64]);
+            assert_eq!(frame.plane_data(0).unwrap(), [96; 64]);
+        }
+
+        #[test]
+        fn test_quality_100_is_near_lossless() {
+            let luma = (0..64).map(|i| (40 + (i % 8) * 9 + (i / 8) * 5) as u8).collect::<Vec<_>>();
+            let settings = Settings {
+                quality: 100,
+                ..Default::default()
+            };
+            let frame = run_plugin_tests(settings, VideoFormat::Gray8, 8, 8, &luma);
+            assert_eq!(frame.plane_data(0).unwrap(), luma);
+        }
+
+        #[test]
+        fn test_low_quality_makes_block_edges() {
+            // A smooth ramp across two blocks.
+            let luma = (0..8).flat_map(|_| (0..16).map(|x| (60 + x * 8) as u8)).collect::<Vec<_>>();
+            let settings = Settings {
+                quality: 5,
+                ..Default::default()
+            };
+            let frame = run_plugin_tests(settings, VideoFormat::Gray8, 16, 8, &luma);
+            let row = &frame.plane_data(0).unwrap()[..16];
+            let steps = row.windows(2).map(|pair| pair[1] as i32 - pair[0] as i32).collect::<Vec<_>>();
+            let edge = steps[7];
+            assert!(steps.iter().enumerate().all(|(i, &step)| i == 7 || step < edge), "{row:?}");
+        }
+
+        #[test]
+        fn test_dc_only_matrix_flattens_blocks() {
+            let mut quant_matrix = vec![255; 16];
+            quant_matrix[0] = 1;
+            let settings = Settings {
+                block_size: BlockSize::Four,
+                quant_matrix,
+                ..Default::default()
+            };
+            let luma = (0..32).map(|i| (100 + (i % 8) * 2 + (i / 8) * 2) as u8).collect::<Vec<_>>();
+            let frame = run_plugin_tests(settings, VideoFormat::Gray8, 8, 4, &luma);
+            let expected = (0..32).map(|i| if i % 8 < 4 { 106 } else { 114 }).collect::<Vec<u8>>();
+            assert_eq!(frame.plane_data(0).unwrap(), expected);
+        }
+
+        #[test]
+        fn test_partial_blocks_repeat_edges() {
+            // 6x6 in blocks of 4 leaves partial blocks on the right and bottom,
+            // which must only be written inside the frame.
+            let settings = Settings {
+                block_size: BlockSize::Four,
+                quality: 100,
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::Gray8, 6, 6)
+                .build()
+                .unwrap();
+            let stride = info.stride()[0] as usize;
+            let mut luma = vec![0; stride * 6];
+            for row in luma.chunks_mut(stride) {
+                row[..6].fill(90);
+            }
+            let frame = run_plugin_tests(settings, VideoFormat::Gray8, 6, 6, &luma);
+            assert_eq!(frame.plane_data(0).unwrap(), luma);
+        }
+
+        #[test]
+        fn test_matrix_steps_match_quality_tables() {
+            let steps = quality_steps(&LUMA_STEPS, 50, 8);
+            assert_eq!(steps, LUMA_STEPS.map(|step| step as f32));
+            assert!(quality_steps(&CHROMA_STEPS, 100, 16).iter().all(|&step| step == 1.0));
+            assert_eq!(
+                quality_steps(&LUMA_STEPS, 50, 4),
+                [16.0, 10.0, 24.0, 51.0, 14.0, 16.0, 40.0, 69.0, 18.0, 37.0, 68.0, 103.0, 49.0, 78.0, 103.0, 120.0]
+            );
+        }
+
+        #[test]
+        fn test_quant_matrix_property_round_trip() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::BlockArtifact>();
+            element.set_property("quant-matrix", "1, 2,0,x,3");
+            assert_eq!(element.property::<String>("quant-matrix"), "1,2,3");
+        }
+
+        #[test]
+        fn test_threads_match_single_thread() {
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 64, 48)
+                .build()
+                .unwrap();
+            let data = (0..info.size()).map(|i| (i * 37 % 251) as u8).collect::<Vec<_>>();
+            let run = |n_threads| {
+                let settings = Settings {
+                    quality: 20,
+                    n_threads,
+                    ..Default::default()
+                };
+                let frame = run_plugin_tests(settings, VideoFormat::I420, 64, 48, &data);
+                (0..3)
+                    .flat_map(|plane| frame.plane_data(plane).unwrap().to_vec())
+                    .collect::<Vec<_>>()
+            };
+            let expected = run(1);
+            assert_ne!(expected, data);
+            for n_threads in [2, 3, 5] {
+                assert_eq!(run(n_threads), expected, "{n_threads} threads");
+            }
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let _ = gst::init();
+            let settings = test::black_box(Settings::default());
+
+            let plugin = BlockArtifact {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, width, height)
+                .build()
+                .unwrap();
+            let data_frame = vec![0; info.size()];
+            let buffer_frame = gst::Buffer::from_slice(data_frame);
+            let mut frame = test::black_box(
+                gst_video::VideoFrame::from_buffer_writable(buffer_frame, &info).unwrap(),
+            );
+
+            b.iter(|| plugin.transform_frame_ip(&mut frame.as_mut_video_frame_ref()));
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
@@ -0,0 +1,689 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct BlockCorrupt(ObjectSubclass<imp::BlockCorrupt>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstBlockCorruptFill")]
+pub enum Fill {
+    #[enum_value(name = "Set every sample to 0", nick = "zero")]
+    Zero = 0,
+    #[enum_value(name = "Set every sample to 128", nick = "grey")]
+    Grey = 1,
+    #[enum_value(
+        name = "Copy the block from the previous output frame, or grey on the first frame",
+        nick = "previous"
+    )]
+    Previous = 2,
+    #[enum_value(name = "Seeded random bytes", nick = "random")]
+    Random = 3,
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "blockCorrupt",
+        gst::Rank::NONE,
+        BlockCorrupt::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::ops::Range;
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::Fill;
+    use crate::noise_inject::{frame_key, Rng};
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "blockCorrupt",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Block Corrupt"),
+        )
+    });
+
+    const DEFAULT_BLOCK_SIZE: u32 = 16;
+    const DEFAULT_BLOCK_RATE: f64 = 0.0;
+    const DEFAULT_FILL: Fill = Fill::Grey;
+    const DEFAULT_BER: f64 = 0.0;
+    const DEFAULT_SEED: u64 = 0;
+
+    const GREY: u8 = 128;
+
+    const FORMATS: [VideoFormat; 6] = [
+        VideoFormat::I420,
+        VideoFormat::Y42b,
+        VideoFormat::Y444,
+        VideoFormat::Nv12,
+        VideoFormat::Rgb,
+        VideoFormat::Gray8,
+    ];
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        /// Width and height of the blocks, in luma samples.
+        block_size: u32,
+        /// Chance of each block being corrupted.
+        block_rate: f64,
+        fill: Fill,
+        /// Chance of each bit of the visible samples being flipped.
+        ber: f64,
+        seed: u64,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                block_size: DEFAULT_BLOCK_SIZE,
+                block_rate: DEFAULT_BLOCK_RATE,
+                fill: DEFAULT_FILL,
+                ber: DEFAULT_BER,
+                seed: DEFAULT_SEED,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct State {
+        /// Frames seen since starting or the last flush.
+        frame: u64,
+        /// The last frame out, kept for the previous fill.
+        previous: Option<gst::Buffer>,
+    }
+
+    /// Where one component of a frame lives in its plane.
+    struct Component {
+        plane: u32,
+        poffset: usize,
+        pixel_stride: usize,
+        width: usize,
+        height: usize,
+        w_sub: u32,
+        h_sub: u32,
+    }
+
+    impl Component {
+        fn all(frame: &gst_video::VideoFrameRef<&mut gst::BufferRef>) -> Vec<Self> {
+            let format_info = frame.format_info();
+            (0..format_info.n_components() as usize)
+                .map(|comp| Self {
+                    plane: format_info.plane()[comp],
+                    poffset: format_info.poffset()[comp] as usize,
+                    pixel_stride: format_info.pixel_stride()[comp] as usize,
+                    width: frame.comp_width(comp as u8) as usize,
+                    height: frame.comp_height(comp as u8) as usize,
+                    w_sub: format_info.w_sub()[comp],
+                    h_sub: format_info.h_sub()[comp],
+                })
+                .collect()
+        }
+
+        /// The columns and rows of this component covered by the luma samples
+        /// `x` and `y`.
+        fn scaled(&self, x: Range<usize>, y: Range<usize>) -> (Range<usize>, Range<usize>) {
+            let scale = |range: Range<usize>, sub: u32, len: usize| {
+                (range.start >> sub).min(len)..range.end.div_ceil(1 << sub).min(len)
+            };
+            (scale(x, self.w_sub, self.width), scale(y, self.h_sub, self.height))
+        }
+    }
+
+    #[derive(Default)]
+    pub struct BlockCorrupt {
+        settings: Mutex<Settings>,
+        state: Mutex<State>,
+    }
+
+    impl ObjectImpl for BlockCorrupt {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecUInt::builder("block-size")
+                        .nick("block-size")
+                        .blurb("Width and height of the blocks, in luma samples")
+                        .minimum(1)
+                        .maximum(1024)
+                        .default_value(DEFAULT_BLOCK_SIZE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecDouble::builder("block-rate")
+                        .nick("block-rate")
+                        .blurb("Chance of each block being corrupted")
+                        .minimum(0.0)
+                        .maximum(1.0)
+                        .default_value(DEFAULT_BLOCK_RATE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("fill", DEFAULT_FILL)
+                        .nick("fill")
+                        .blurb("What corrupted blocks are filled with")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecDouble::builder("ber")
+                        .nick("ber")
+                        .blurb("Bit error rate: the chance of each bit of every visible sample being flipped")
+                        .minimum(0.0)
+                        .maximum(1.0)
+                        .default_value(DEFAULT_BER)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt64::builder("seed")
+                        .nick("seed")
+                        .blurb("Seed for the corrupted blocks, random fill and bit errors")
+                        .default_value(DEFAULT_SEED)
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "block-size" => settings.block_size = value.get().expect("type checked upstream"),
+                "block-rate" => settings.block_rate = value.get().expect("type checked upstream"),
+                "fill" => settings.fill = value.get().expect("type checked upstream"),
+                "ber" => settings.ber = value.get().expect("type checked upstream"),
+                "seed" => settings.seed = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "block-size" => settings.block_size.to_value(),
+                "block-rate" => settings.block_rate.to_value(),
+                "fill" => settings.fill.to_value(),
+                "ber" => settings.ber.to_value(),
+                "seed" => settings.seed.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for BlockCorrupt {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for BlockCorrupt {
+        const NAME: &'static str = "blockCorrupt";
+        type Type = super::BlockCorrupt;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for BlockCorrupt {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = State::default();
+            self.parent_start()
+        }
+
+        fn sink_event(&self, event: gst::Event) -> bool {
+            // The frames after a flush count from 0 again, and the last frame out
+            // is no longer the one before them.
+            if let gst::EventView::FlushStop(_) = event.view() {
+                *self.state.lock().unwrap() = State::default();
+            }
+            self.parent_sink_event(event)
+        }
+    }
+
+    impl ElementImpl for BlockCorrupt {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Block Corrupt Tool",
+                    "Filter/Effect/Video",
+                    "Lose blocks and flip bits like a failing decoder, to test error detection.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for BlockCorrupt {
+        fn set_info(
+            &self,
+            incaps: &gst::Caps,
+            in_info: &gst_video::VideoInfo,
+            outcaps: &gst::Caps,
+            out_info: &gst_video::VideoInfo,
+        ) -> Result<(), gst::LoggableError> {
+            // A frame of another size can't be copied from.
+            self.state.lock().unwrap().previous = None;
+            self.parent_set_info(incaps, in_info, outcaps, out_info)
+        }
+
+        fn transform_frame_ip(
+            &self,
+            frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let mut state = self.state.lock().unwrap();
+            let frame_index = state.frame;
+            state.frame += 1;
+            // Seeded from the frame itself rather than the count, so a seek back
+            // to a frame corrupts it the same way again.
+            let key = frame_key(frame.buffer());
+
+            let info = frame.info().clone();
+            let previous = match (settings.fill, &state.previous) {
+                (Fill::Previous, Some(previous)) => Some(
+                    gst_video::VideoFrameRef::from_buffer_ref_readable(previous.as_ref(), &info)
+                        .map_err(|_| gst::FlowError::Error)?,
+                ),
+                _ => None,
+            };
+
+            let components = Component::all(frame);
+            let size = settings.block_size as usize;
+            let columns = (info.width() as usize).div_ceil(size);
+            let rows = (info.height() as usize).div_ceil(size);
+            let mut blocks = vec![];
+            for block in 0..columns * rows {
+                let mut rng = Rng::new(settings.seed, key, block);
+                if rng.next_f64() >= settings.block_rate {
+                    continue;
+                }
+                let (left, top) = ((block % columns) * size, (block / columns) * size);
+                blocks.push((left, top));
+
+                for comp in &components {
+                    let (xs, ys) = comp.scaled(left..left + size, top..top + size);
+                    let stride = frame.plane_stride()[comp.plane as usize] as usize;
+                    let data = frame.plane_data_mut(comp.plane).unwrap();
+                    let source = previous.as_ref().map(|previous| {
+                        let stride = previous.plane_stride()[comp.plane as usize] as usize;
+                        (previous.plane_data(comp.plane).unwrap(), stride)
+                    });
+                    for y in ys.clone() {
+                        for x in xs.clone() {
+                            let i = comp.poffset + x * comp.pixel_stride;
+                            data[y * stride + i] = match (settings.fill, source) {
+                                (Fill::Zero, _) => 0,
+                                (Fill::Random, _) => rng.next_u64() as u8,
+                                (Fill::Previous, Some((source, source_stride))) => {
+                                    source[y * source_stride + i]
+                                }
+                                (Fill::Grey | Fill::Previous, _) => GREY,
+                            };
+                        }
+                    }
+                }
+            }
+            drop(previous);
+
+            // Bit errors come after the blocks, from a generator of their own.
+            let mut rng = Rng::new(settings.seed, key, columns * rows);
+            let bit_errors = flip_bits(frame, &components, settings.ber, &mut rng);
+
+            if settings.fill == Fill::Previous {
+                state.previous = Some(frame.buffer().copy_deep().map_err(|_| gst::FlowError::Error)?);
+            }
+            drop(state);
+
+            self.post_corruption(frame_index, frame.buffer(), settings.block_size, &blocks, bit_errors);
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    impl BlockCorrupt {
+        /// Post the top left corner, in luma samples, of every block corrupted in
+        /// a frame, and how many bits were flipped.
+        fn post_corruption(
+            &self,
+            frame: u64,
+            buffer: &gst::BufferRef,
+            block_size: u32,
+            blocks: &[(usize, usize)],
+            bit_errors: u64,
+        ) {
+            let blocks = blocks
+                .iter()
+                .map(|&(x, y)| gst::Array::new([x as u32, y as u32]));
+            let structure = gst::Structure::builder("blockcorrupt")
+                .field("frame", frame)
+                .field("offset", buffer.offset())
+                .field("pts", buffer.pts())
+                .field("block-size", block_size)
+                .field("blocks", gst::Array::new(blocks))
+                .field("bit-errors", bit_errors)
+                .build();
+            let obj = self.obj();
+            let _ = obj.post_message(gst::message::Element::builder(structure).src(&*obj).build());
+        }
+    }
+
+    /// Flip each bit of the visible samples with chance `ber`, returning how many
+    /// were flipped. The gaps between flipped bits are drawn from the geometric
+    /// distribution, so low rates only cost a draw per flip.
+    fn flip_bits(
+        frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        components: &[Component],
+        ber: f64,
+        rng: &mut Rng,
+    ) -> u64 {
+        if ber <= 0.0 {
+            return 0;
+        }
+        let ln_keep = (1.0 - ber).ln();
+        let mut gap = || {
+            if ber >= 1.0 {
+                0
+            } else {
+                ((1.0 - rng.next_f64()).ln() / ln_keep).floor() as u64
+            }
+        };
+
+        let mut flipped = 0;
+        let mut next = gap();
+        let mut first_bit = 0u64;
+        for comp in components {
+            let bits = (comp.width * comp.height * 8) as u64;
+            let stride = frame.plane_stride()[comp.plane as usize] as usize;
+            let data = frame.plane_data_mut(comp.plane).unwrap();
+            while next < first_bit + bits {
+                let bit = next - first_bit;
+                let sample = (bit / 8) as usize;
+                let (x, y) = (sample % comp.width, sample / comp.width);
+                data[y * stride + comp.poffset + x * comp.pixel_stride] ^= 1 << (bit % 8);
+                flipped += 1;
+                next = next.saturating_add(1).saturating_add(gap());
+            }
+            first_bit += bits;
+        }
+        flipped
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use test::Bencher;
+
+        /// A `blockCorrupt` with `settings`. The corruption is posted on the bus,
+        /// so the tests need a real element rather than a bare implementation.
+        fn new_element(settings: Settings) -> super::super::BlockCorrupt {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::BlockCorrupt>();
+            *element.imp().settings.lock().unwrap() = settings;
+            element
+        }
+
+        /// Run a frame through `plugin` in place and return it.
+        fn run_frame(
+            plugin: &BlockCorrupt,
+            format: VideoFormat,
+            width: u32,
+            height: u32,
+            data: Vec<u8>,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let info = gst_video::VideoInfo::builder(format, width, height)
+                .build()
+                .unwrap();
+            assert_eq!(data.len(), info.size());
+            let buffer = gst::Buffer::from_slice(data);
+            let mut frame = gst_video::VideoFrame::from_buffer_writable(buffer, &info).unwrap();
+            plugin
+                .transform_frame_ip(&mut frame.as_mut_video_frame_ref())
+                .unwrap();
+            frame
+        }
+
+        fn run_plugin_tests(
+            settings: Settings,
+            format: VideoFormat,
+            width: u32,
+            height: u32,
+            data: Vec<u8>,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let element = new_element(settings);
+            run_frame(element.imp(), format, width, height, data)
+        }
+
+        #[test]
+        fn test_every_block_zeroed() {
+            let settings = Settings {
+                block_rate: 1.0,
+                fill: Fill::Zero,
+                ..Default::default()
+            };
+            let frame = run_plugin_tests(settings, VideoFormat::I420, 32, 16, vec![77; 32 * 16 * 3 / 2]);
+            for plane in 0..3 {
+                assert!(frame.plane_data(plane).unwrap().iter().all(|&s| s == 0));
+            }
+        }
+
+        #[test]
+        fn test_grey_block_at_partial_edge() {
+            // 20x4 in blocks of 16 leaves a 4 wide block on the right. Find a seed
+            // that corrupts only that one.
+            let luma = (0..80).map(|i| i as u8).collect::<Vec<_>>();
+            let seed = (0..)
+                .find(|&seed| {
+                    let rng = |block| Rng::new(seed, 0, block).next_f64() < 0.5;
+                    !rng(0) && rng(1)
+                })
+                .unwrap();
+            let settings = Settings {
+                block_rate: 0.5,
+                seed,
+                ..Default::default()
+            };
+            let frame = run_plugin_tests(settings, VideoFormat::Gray8, 20, 4, luma.clone());
+            let expected = luma
+                .iter()
+                .enumerate()
+                .map(|(i, &s)| if i % 20 >= 16 { GREY } else { s })
+                .collect::<Vec<_>>();
+            assert_eq!(frame.plane_data(0).unwrap(), expected);
+        }
+
+        #[test]
+        fn test_nv12_chroma_follows_luma_block() {
+            let settings = Settings {
+                block_rate: 1.0,
+                block_size: 2,
+                fill: Fill::Zero,
+                ..Default::default()
+            };
+            let frame = run_plugin_tests(settings, VideoFormat::Nv12, 4, 2, vec![9; 12]);
+            assert_eq!(frame.plane_data(0).unwrap(), [0; 8]);
+            assert_eq!(frame.plane_data(1).unwrap(), [0; 4]);
+        }
+
+        #[test]
+        fn test_previous_fill_copies_last_output() {
+            let element = new_element(Settings {
+                fill: Fill::Previous,
+                ..Default::default()
+            });
+            let plugin = element.imp();
+            let frame = run_frame(plugin, VideoFormat::Gray8, 8, 8, vec![10; 64]);
+            assert_eq!(frame.plane_data(0).unwrap(), [10; 64]);
+
+            plugin.settings.lock().unwrap().block_rate = 1.0;
+            let frame = run_frame(plugin, VideoFormat::Gray8, 8, 8, vec![50; 64]);
+            assert_eq!(frame.plane_data(0).unwrap(), [10; 64]);
+
+            // Without a previous frame there is only grey.
+            let settings = *plugin.settings.lock().unwrap();
+            let frame = run_plugin_tests(settings, VideoFormat::Gray8, 8, 8, vec![50; 64]);
+            assert_eq!(frame.plane_data(0).unwrap(), [GREY; 64]);
+        }
+
+        #[test]
+        fn test_random_fill_is_seeded() {
+            let run = |seed| {
+                let settings = Settings {
+                    block_rate: 1.0,
+                    fill: Fill::Random,
+                    seed,
+                    ..Default::default()
+                };
+                let frame = run_plugin_tests(settings, VideoFormat::Gray8, 16, 16, vec![0; 256]);
+                frame.plane_data(0).unwrap().to_vec()
+            };
+            let first = run(1);
+            assert_eq!(first, run(1));
+            assert_ne!(first, run(2));
+        }
+
+        #[test]
+        fn test_blocks_keyed_by_offset() {
+            let element = new_element(Settings {
+                block_rate: 0.5,
+                block_size: 2,
+                fill: Fill::Zero,
+                ..Default::default()
+            });
+            let info = gst_video::VideoInfo::builder(VideoFormat::Gray8, 16, 16)
+                .build()
+                .unwrap();
+            let run = |offset| {
+                let mut buffer = gst::Buffer::from_slice(vec![77; 256]);
+                buffer.get_mut().unwrap().set_offset(offset);
+                let mut frame = gst_video::VideoFrame::from_buffer_writable(buffer, &info).unwrap();
+                element
+                    .imp()
+                    .transform_frame_ip(&mut frame.as_mut_video_frame_ref())
+                    .unwrap();
+                frame.plane_data(0).unwrap().to_vec()
+            };
+            // The same frame again, after others, is corrupted the same way.
+            let first = run(5);
+            assert_ne!(first, run(6));
+            assert_eq!(first, run(5));
+
+            assert_eq!(element.imp().state.lock().unwrap().frame, 3);
+            element.imp().sink_event(gst::event::FlushStart::new());
+            element.imp().sink_event(gst::event::FlushStop::new(true));
+            assert_eq!(element.imp().state.lock().unwrap().frame, 0);
+        }
+
+        #[test]
+        fn test_bit_errors_posted() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::BlockCorrupt>();
+            element.set_property("ber", 0.01);
+            element.set_property("block-rate", 0.25);
+            element.set_property("block-size", 8u32);
+            element.set_property("fill", Fill::Zero);
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+
+            let input = (0..64 * 64).map(|i| (i % 200 + 20) as u8).collect::<Vec<_>>();
+            let frame = run_frame(element.imp(), VideoFormat::Gray8, 64, 64, input.clone());
+            let message = bus.pop_filtered(&[gst::MessageType::Element]).unwrap();
+            let structure = message.structure().unwrap();
+            assert_eq!(structure.name(), "blockcorrupt");
+            assert_eq!(structure.get::<u32>("block-size").unwrap(), 8);
+
+            // Every sample inside a listed block was zeroed, then maybe had bits flipped.
+            let blocks = structure
+                .get::<gst::Array>("blocks")
+                .unwrap()
+                .iter()
+                .map(|block| {
+                    let block = block.get::<gst::Array>().unwrap();
+                    (block[0].get::<u32>().unwrap() as usize, block[1].get::<u32>().unwrap() as usize)
+                })
+                .collect::<Vec<_>>();
+            assert!((4..28).contains(&blocks.len()), "{} blocks", blocks.len());
+            let output = frame.plane_data(0).unwrap();
+            let in_block = |i: usize| blocks.iter().any(|&(x, y)| (x..x + 8).contains(&(i % 64)) && (y..y + 8).contains(&(i / 64)));
+            let flipped = (0..64 * 64)
+                .map(|i| {
+                    let original = if in_block(i) { 0 } else { input[i] };
+                    (original ^ output[i]).count_ones() as u64
+                })
+                .sum::<u64>();
+            assert_eq!(structure.get::<u64>("bit-errors").unwrap(), flipped);
+            assert!((250..410).contains(&flipped), "{flipped} bits flipped");
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let element = test::black_box(new_element(Settings {
+                block_rate: 0.01,
+                ber: 1e-6,
+                ..Default::default()
+            }));
+            let plugin = element.imp();
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, width, height)
+                .build()
+                .unwrap();
+            let data_frame = vec![0; info.size()];
+            let buffer_frame = gst::Buffer::from_slice(data_frame);
+            let mut frame = test::black_box(
+                gst_video::VideoFrame::from_buffer_writable(buffer_frame, &info).unwrap(),
+            );
+
+            b.iter(|| plugin.transform_frame_ip(&mut frame.as_mut_video_frame_ref()));
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
@@ -0,0 +1,634 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct BlurImpair(ObjectSubclass<imp::BlurImpair>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstBlurImpairKernel")]
+pub enum Kernel {
+    #[enum_value(
+        name = "Box of 2 * radius + 1 samples, with the strength as its radius",
+        nick = "box"
+    )]
+    Box = 0,
+    #[enum_value(
+        name = "Gaussian cut off at 3 sigma, with the strength as its sigma",
+        nick = "gaussian"
+    )]
+    Gaussian = 1,
+    #[enum_value(
+        name = "Box along one direction only, with the strength as its length",
+        nick = "motion"
+    )]
+    Motion = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstBlurImpairDirection")]
+pub enum Direction {
+    #[enum_value(name = "Along the rows", nick = "horizontal")]
+    Horizontal = 0,
+    #[enum_value(name = "Down the columns", nick = "vertical")]
+    Vertical = 1,
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "blurImpair",
+        gst::Rank::NONE,
+        BlurImpair::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_base::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::{Direction, Kernel};
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "blurImpair",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Blur Impair"),
+        )
+    });
+
+    const DEFAULT_KERNEL: Kernel = Kernel::Gaussian;
+    const DEFAULT_STRENGTH: f64 = 0.0;
+    const DEFAULT_DIRECTION: Direction = Direction::Horizontal;
+
+    /// The frequency response is posted at this many frequencies from 0 to
+    /// half a cycle per sample.
+    const RESPONSE_POINTS: usize = 33;
+
+    const FORMATS: [VideoFormat; 3] = [VideoFormat::I420, VideoFormat::Nv12, VideoFormat::Rgb];
+
+    /// The strength properties, in the order of the components they blur.
+    const STRENGTH_PROPERTIES: [(&str, &str); 3] = [
+        ("plane0-strength", "plane 0 (Y, or R of packed RGB)"),
+        ("plane1-strength", "plane 1 (U, or G of packed RGB)"),
+        ("plane2-strength", "plane 2 (V, or B of packed RGB)"),
+    ];
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        kernel: Kernel,
+        /// Radius, sigma or length of the kernel for each plane, in that plane's
+        /// own samples.
+        strengths: [f64; 3],
+        direction: Direction,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                kernel: DEFAULT_KERNEL,
+                strengths: [DEFAULT_STRENGTH; 3],
+                direction: DEFAULT_DIRECTION,
+            }
+        }
+    }
+
+    impl Settings {
+        /// The horizontal and vertical taps of component `comp`.
+        fn taps(&self, comp: usize) -> [Taps; 2] {
+            let strength = self.strengths[comp];
+            match self.kernel {
+                Kernel::Box => {
+                    let taps = Taps::uniform(2 * strength.round() as usize + 1);
+                    [taps.clone(), taps]
+                }
+                Kernel::Gaussian => {
+                    let taps = Taps::gaussian(strength);
+                    [taps.clone(), taps]
+                }
+                Kernel::Motion => {
+                    let taps = Taps::uniform(strength.round().max(1.0) as usize);
+                    match self.direction {
+                        Direction::Horizontal => [taps, Taps::uniform(1)],
+                        Direction::Vertical => [Taps::uniform(1), taps],
+                    }
+                }
+            }
+        }
+
+        fn is_noop(&self) -> bool {
+            (0..3).all(|comp| self.taps(comp).iter().all(|taps| taps.weights.len() == 1))
+        }
+    }
+
+    /// One axis of a separable kernel: `weights[k]` is applied to the sample
+    /// `first + k` along from the one being filtered.
+    #[derive(Debug, Clone, PartialEq)]
+    struct Taps {
+        first: isize,
+        weights: Vec<f32>,
+    }
+
+    impl Taps {
+        /// `len` equal weights, centred on the sample being filtered, or half a
+        /// sample after it when `len` is even.
+        fn uniform(len: usize) -> Self {
+            Self {
+                first: -((len as isize - 1) / 2),
+                weights: vec![1.0 / len as f32; len],
+            }
+        }
+
+        fn gaussian(sigma: f64) -> Self {
+            if sigma <= 0.0 {
+                return Self::uniform(1);
+            }
+            let radius = (3.0 * sigma).ceil() as isize;
+            let weights = (-radius..=radius)
+                .map(|k| (-(k * k) as f64 / (2.0 * sigma * sigma)).exp())
+                .collect::<Vec<_>>();
+            let total = weights.iter().sum::<f64>();
+            Self {
+                first: -radius,
+                weights: weights.iter().map(|w| (w / total) as f32).collect(),
+            }
+        }
+
+        /// Magnitude of the response at `frequency` cycles per sample. This is
+        /// exact for the taps applied, and for the box and motion kernels is
+        /// `|sin(pi f n) / (n sin(pi f))|` for `n` taps.
+        fn response(&self, frequency: f64) -> f64 {
+            let (re, im) = self
+                .weights
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(re, im), (k, &weight)| {
+                    let phase = std::f64::consts::TAU * frequency * (self.first + k as isize) as f64;
+                    (re + weight as f64 * phase.cos(), im - weight as f64 * phase.sin())
+                });
+            re.hypot(im)
+        }
+    }
+
+    #[derive(Default)]
+    pub struct BlurImpair {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for BlurImpair {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let noop = self.settings.lock().unwrap().is_noop();
+            self.obj().set_passthrough(noop);
+        }
+
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                let strengths = STRENGTH_PROPERTIES.iter().map(|(name, plane)| {
+                    glib::ParamSpecDouble::builder(name)
+                        .nick(name)
+                        .blurb(&format!(
+                            "Radius, sigma or length of the kernel for {plane}, in its own samples"
+                        ))
+                        .minimum(0.0)
+                        .maximum(64.0)
+                        .default_value(DEFAULT_STRENGTH)
+                        .mutable_playing()
+                        .build()
+                });
+                strengths
+                    .chain([
+                        glib::ParamSpecEnum::builder_with_default("kernel", DEFAULT_KERNEL)
+                            .nick("kernel")
+                            .blurb("Shape of the blur")
+                            .mutable_playing()
+                            .build(),
+                        glib::ParamSpecEnum::builder_with_default("motion-direction", DEFAULT_DIRECTION)
+                            .nick("motion-direction")
+                            .blurb("Direction of the motion blur")
+                            .mutable_playing()
+                            .build(),
+                    ])
+                    .collect()
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "kernel" => settings.kernel = value.get().expect("type checked upstream"),
+                "motion-direction" => settings.direction = value.get().expect("type checked upstream"),
+                name => {
+                    let index = STRENGTH_PROPERTIES
+                        .iter()
+                        .position(|(property, _)| *property == name)
+                        .unwrap_or_else(|| unimplemented!());
+                    settings.strengths[index] = value.get().expect("type checked upstream");
+                }
+            }
+            let noop = settings.is_noop();
+            drop(settings);
+            self.obj().set_passthrough(noop);
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "kernel" => settings.kernel.to_value(),
+                "motion-direction" => settings.direction.to_value(),
+                name => {
+                    let index = STRENGTH_PROPERTIES
+                        .iter()
+                        .position(|(property, _)| *property == name)
+                        .unwrap_or_else(|| unimplemented!());
+                    settings.strengths[index].to_value()
+                }
+            }
+        }
+    }
+
+    impl GstObjectImpl for BlurImpair {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for BlurImpair {
+        const NAME: &'static str = "blurImpair";
+        type Type = super::BlurImpair;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for BlurImpair {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::NeverInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            self.post_response();
+            self.parent_start()
+        }
+    }
+
+    impl ElementImpl for BlurImpair {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Blur Impair Tool",
+                    "Filter/Effect/Video",
+                    "Blur each plane with a known kernel to test sharpness and MTF measurements.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for BlurImpair {
+        fn transform_frame(
+            &self,
+            in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+            out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let format_info = in_frame.format_info();
+
+            // Packed RGB keeps every channel in one plane, so each channel is blurred
+            // as if it were a plane of its own.
+            for comp in 0..format_info.n_components() as usize {
+                let plane = format_info.plane()[comp];
+                let poffset = format_info.poffset()[comp] as usize;
+                let pixel_stride = format_info.pixel_stride()[comp] as usize;
+                let width = in_frame.comp_width(comp as u8) as usize;
+                let height = in_frame.comp_height(comp as u8) as usize;
+                let [horizontal, vertical] = settings.taps(comp);
+
+                let in_stride = in_frame.plane_stride()[plane as usize] as usize;
+                let out_stride = out_frame.plane_stride()[plane as usize] as usize;
+                let in_data = in_frame.plane_data(plane).unwrap();
+                let out_data = out_frame.plane_data_mut(plane).unwrap();
+
+                // Samples beyond the edges repeat the ones at the edge.
+                let clamp = |i: usize, offset: isize, len: usize| {
+                    (i as isize + offset).clamp(0, len as isize - 1) as usize
+                };
+
+                let mut rows = vec![0.0f32; width * height];
+                for (y, row) in rows.chunks_exact_mut(width.max(1)).enumerate() {
+                    let in_line = &in_data[y * in_stride..];
+                    for (x, out) in row.iter_mut().enumerate() {
+                        *out = horizontal
+                            .weights
+                            .iter()
+                            .enumerate()
+                            .map(|(k, weight)| {
+                                let i = clamp(x, horizontal.first + k as isize, width);
+                                weight * in_line[poffset + i * pixel_stride] as f32
+                            })
+                            .sum();
+                    }
+                }
+
+                for (y, out_line) in out_data.chunks_mut(out_stride).take(height).enumerate() {
+                    for x in 0..width {
+                        let value: f32 = vertical
+                            .weights
+                            .iter()
+                            .enumerate()
+                            .map(|(k, weight)| {
+                                let i = clamp(y, vertical.first + k as isize, height);
+                                weight * rows[i * width + x]
+                            })
+                            .sum();
+                        out_line[poffset + x * pixel_stride] = value.round().clamp(0.0, 255.0) as u8;
+                    }
+                }
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    impl BlurImpair {
+        /// Post the response of each plane's kernel along each axis, sampled from
+        /// 0 to 0.5 cycles per sample of that plane.
+        fn post_response(&self) {
+            let settings = *self.settings.lock().unwrap();
+            let frequencies = (0..RESPONSE_POINTS)
+                .map(|i| 0.5 * i as f64 / (RESPONSE_POINTS - 1) as f64)
+                .collect::<Vec<_>>();
+            let mut structure = gst::Structure::builder("blurimpair-response")
+                .field("kernel", settings.kernel)
+                .field("frequencies", gst::Array::new(frequencies.iter().copied()));
+            for comp in 0..3 {
+                let [horizontal, vertical] = settings.taps(comp);
+                for (axis, taps) in [("horizontal", horizontal), ("vertical", vertical)] {
+                    let response = frequencies.iter().map(|&f| taps.response(f));
+                    structure = structure.field(
+                        format!("plane{comp}-{axis}").as_str(),
+                        gst::Array::new(response),
+                    );
+                }
+            }
+            let obj = self.obj();
+            let _ = obj.post_message(gst::message::Element::builder(structure.build()).src(&*obj).build());
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use test::Bencher;
+
+        fn run_plugin_tests(
+            settings: Settings,
+            format: VideoFormat,
+            width: u32,
+            data_inframe: Vec<u8>,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let _ = gst::init();
+
+            const HEIGHT: u32 = 2;
+
+            let plugin = BlurImpair {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(format, width, HEIGHT)
+                .build()
+                .unwrap();
+            assert_eq!(data_inframe.len(), info.size());
+            let buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            let inframe = gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap();
+
+            let buffer_outframe = gst::Buffer::from_slice(vec![0; info.size()]);
+            let mut outframe = gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap();
+            plugin
+                .transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+                .unwrap();
+            outframe
+        }
+
+        /// An 8x2 I420 frame with an impulse in the first luma row, and flat chroma.
+        fn impulse_i420() -> Vec<u8> {
+            let mut data = vec![0; 16];
+            data[3] = 90;
+            data.extend([128; 8]);
+            data
+        }
+
+        fn luma_settings(kernel: Kernel, strength: f64) -> Settings {
+            Settings {
+                kernel,
+                strengths: [strength, 0.0, 0.0],
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_box() {
+            let outframe = run_plugin_tests(luma_settings(Kernel::Box, 1.0), VideoFormat::I420, 8, impulse_i420());
+            assert_eq!(
+                outframe.plane_data(0).unwrap(),
+                [0, 0, 20, 20, 20, 0, 0, 0, 0, 0, 10, 10, 10, 0, 0, 0]
+            );
+            assert_eq!(outframe.plane_data(1).unwrap(), [128; 4]);
+        }
+
+        #[test]
+        fn test_motion_only_blurs_one_direction() {
+            let outframe = run_plugin_tests(luma_settings(Kernel::Motion, 3.0), VideoFormat::I420, 8, impulse_i420());
+            assert_eq!(
+                outframe.plane_data(0).unwrap(),
+                [0, 0, 30, 30, 30, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+            );
+
+            let settings = Settings {
+                direction: Direction::Vertical,
+                ..luma_settings(Kernel::Motion, 2.0)
+            };
+            let outframe = run_plugin_tests(settings, VideoFormat::I420, 8, impulse_i420());
+            assert_eq!(
+                outframe.plane_data(0).unwrap(),
+                [0, 0, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+            );
+        }
+
+        #[test]
+        fn test_gaussian_keeps_flat_areas_and_total() {
+            let mut data = vec![77; 16];
+            data.extend([128; 8]);
+            let outframe = run_plugin_tests(luma_settings(Kernel::Gaussian, 1.5), VideoFormat::I420, 8, data);
+            assert_eq!(outframe.plane_data(0).unwrap(), [77; 16]);
+
+            let taps = Taps::gaussian(1.5);
+            assert_eq!(taps.first, -5);
+            assert!((taps.weights.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        }
+
+        #[test]
+        fn test_nv12_blurs_u_only() {
+            let settings = Settings {
+                kernel: Kernel::Box,
+                strengths: [0.0, 1.0, 0.0],
+                ..Default::default()
+            };
+            let mut data = vec![50; 8];
+            data.extend([10, 200, 40, 100]);
+            let outframe = run_plugin_tests(settings, VideoFormat::Nv12, 4, data);
+            assert_eq!(outframe.plane_data(0).unwrap(), [50; 8]);
+            assert_eq!(outframe.plane_data(1).unwrap(), [20, 200, 30, 100]);
+        }
+
+        #[test]
+        fn test_response() {
+            let taps = Taps::uniform(3);
+            assert!((taps.response(0.0) - 1.0).abs() < 1e-6);
+            assert!((taps.response(0.5) - 1.0 / 3.0).abs() < 1e-6);
+            assert!(taps.response(1.0 / 3.0).abs() < 1e-6);
+
+            // The motion blur is a box of any length, and shifting it by half a
+            // sample doesn't change the magnitude.
+            let taps = Taps::uniform(4);
+            let f = 0.1f64;
+            let dirichlet = (std::f64::consts::PI * f * 4.0).sin() / (4.0 * (std::f64::consts::PI * f).sin());
+            assert!((taps.response(f) - dirichlet).abs() < 1e-6);
+
+            // Cutting the Gaussian off at 3 sigma keeps it close to the continuous one.
+            let sigma = 2.0;
+            let gaussian = (-2.0 * (std::f64::consts::PI * sigma * f).powi(2)).exp();
+            assert!((Taps::gaussian(sigma).response(f) - gaussian).abs() < 2e-3);
+        }
+
+        #[test]
+        fn test_response_posted_on_start() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::BlurImpair>();
+            element.set_property("kernel", Kernel::Box);
+            element.set_property("plane0-strength", 1.0);
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+            element.imp().start().unwrap();
+
+            let message = bus.pop_filtered(&[gst::MessageType::Element]).unwrap();
+            let structure = message.structure().unwrap();
+            assert_eq!(structure.name(), "blurimpair-response");
+            let values = |field: &str| {
+                structure
+                    .get::<gst::Array>(field)
+                    .unwrap()
+                    .iter()
+                    .map(|value| value.get::<f64>().unwrap())
+                    .collect::<Vec<_>>()
+            };
+            let frequencies = values("frequencies");
+            assert_eq!(frequencies.len(), RESPONSE_POINTS);
+            assert_eq!(frequencies[RESPONSE_POINTS - 1], 0.5);
+            let response = values("plane0-vertical");
+            assert!((response[0] - 1.0).abs() < 1e-6);
+            assert!((response[RESPONSE_POINTS - 1] - 1.0 / 3.0).abs() < 1e-6);
+            assert!(values("plane1-horizontal").iter().all(|&r| (r - 1.0).abs() < 1e-6));
+        }
+
+        #[test]
+        fn test_passthrough_follows_settings() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::BlurImpair>();
+            assert!(element.is_passthrough());
+            element.set_property("plane2-strength", 0.8);
+            assert!(!element.is_passthrough());
+            // A box rounds its radius to whole samples.
+            element.set_property("kernel", Kernel::Box);
+            element.set_property("plane2-strength", 0.4);
+            assert!(element.is_passthrough());
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let _ = gst::init();
+            let settings = test::black_box(Settings {
+                strengths: [1.0, 0.5, 0.5],
+                ..Default::default()
+            });
+
+            let plugin = BlurImpair {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, width, height)
+                .build()
+                .unwrap();
+            let data_inframe = vec![0; info.size()];
+            let buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            let inframe = test::black_box(gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap());
+
+            let data_outframe = vec![0; info.size()];
+            let buffer_outframe = gst::Buffer::from_slice(data_outframe);
+            let mut outframe = test::black_box(gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap());
+
+            b.iter(|| {
+                plugin.transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+            });
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
@@ -0,0 +1,548 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct ColorMismatch(ObjectSubclass<imp::ColorMismatch>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstColorMismatchMatrix")]
+pub enum Matrix {
+    #[enum_value(
+        name = "The matrix in the caps, so there is no mismatch",
+        nick = "caps"
+    )]
+    Caps = 0,
+    #[enum_value(name = "ITU-R BT.601", nick = "bt601")]
+    Bt601 = 1,
+    #[enum_value(name = "ITU-R BT.709", nick = "bt709")]
+    Bt709 = 2,
+    #[enum_value(name = "ITU-R BT.2020", nick = "bt2020")]
+    Bt2020 = 3,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstColorMismatchRange")]
+pub enum Range {
+    #[enum_value(name = "The range in the caps, so there is no mismatch", nick = "caps")]
+    Caps = 0,
+    #[enum_value(name = "Full range, 0 to 255", nick = "full")]
+    Full = 1,
+    #[enum_value(
+        name = "Limited range, 16 to 235 for luma and 16 to 240 for chroma",
+        nick = "limited"
+    )]
+    Limited = 2,
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "colorMismatch",
+        gst::Rank::NONE,
+        ColorMismatch::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_base::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::{Matrix, Range};
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "colorMismatch",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Color Mismatch"),
+        )
+    });
+
+    const DEFAULT_MATRIX: Matrix = Matrix::Caps;
+    const DEFAULT_RANGE: Range = Range::Caps;
+
+    const FORMATS: [VideoFormat; 2] = [VideoFormat::I420, VideoFormat::Nv12];
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        /// What the frames are wrongly decoded as.
+        matrix: Matrix,
+        range: Range,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                matrix: DEFAULT_MATRIX,
+                range: DEFAULT_RANGE,
+            }
+        }
+    }
+
+    impl Settings {
+        fn is_noop(&self) -> bool {
+            self.matrix == Matrix::Caps && self.range == Range::Caps
+        }
+    }
+
+    /// The luma weights of a matrix and the range of the codes, which is all it
+    /// takes to go between 8-bit YUV and R'G'B'.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Colorimetry {
+        kr: f32,
+        kb: f32,
+        full_range: bool,
+    }
+
+    impl Colorimetry {
+        /// What the caps say. Anything without its own weights is treated as
+        /// BT.709, and an unknown range as limited.
+        fn from_info(info: &gst_video::VideoInfo) -> Self {
+            let colorimetry = info.colorimetry();
+            let matrix = match colorimetry.matrix() {
+                gst_video::VideoColorMatrix::Bt601 => Matrix::Bt601,
+                gst_video::VideoColorMatrix::Bt2020 => Matrix::Bt2020,
+                _ => Matrix::Bt709,
+            };
+            let full_range = colorimetry.range() == gst_video::VideoColorRange::Range0_255;
+            Self::new(matrix, full_range)
+        }
+
+        fn new(matrix: Matrix, full_range: bool) -> Self {
+            let (kr, kb) = match matrix {
+                Matrix::Bt601 => (0.299, 0.114),
+                Matrix::Caps | Matrix::Bt709 => (0.2126, 0.0722),
+                Matrix::Bt2020 => (0.2627, 0.0593),
+            };
+            Self { kr, kb, full_range }
+        }
+
+        /// These caps as misread with `settings`.
+        fn assumed(self, settings: &Settings) -> Self {
+            let mut assumed = self;
+            if settings.matrix != Matrix::Caps {
+                let matrix = Self::new(settings.matrix, self.full_range);
+                (assumed.kr, assumed.kb) = (matrix.kr, matrix.kb);
+            }
+            match settings.range {
+                Range::Caps => (),
+                Range::Full => assumed.full_range = true,
+                Range::Limited => assumed.full_range = false,
+            }
+            assumed
+        }
+
+        /// The scale and offset of the luma and chroma codes.
+        fn scales(&self) -> (f32, f32, f32) {
+            if self.full_range {
+                (255.0, 0.0, 255.0)
+            } else {
+                (219.0, 16.0, 224.0)
+            }
+        }
+
+        /// R'G'B' from 0 to 1, clipped as an RGB intermediate would be.
+        fn decode(&self, y: u8, u: u8, v: u8) -> [f32; 3] {
+            let (luma_scale, luma_offset, chroma_scale) = self.scales();
+            let y = (y as f32 - luma_offset) / luma_scale;
+            let cb = (u as f32 - 128.0) / chroma_scale;
+            let cr = (v as f32 - 128.0) / chroma_scale;
+            let r = y + 2.0 * (1.0 - self.kr) * cr;
+            let b = y + 2.0 * (1.0 - self.kb) * cb;
+            let g = (y - self.kr * r - self.kb * b) / (1.0 - self.kr - self.kb);
+            [r, g, b].map(|c| c.clamp(0.0, 1.0))
+        }
+
+        /// Unrounded YUV codes from R'G'B'.
+        fn encode(&self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+            let (luma_scale, luma_offset, chroma_scale) = self.scales();
+            let y = self.kr * r + (1.0 - self.kr - self.kb) * g + self.kb * b;
+            let cb = (b - y) / (2.0 * (1.0 - self.kb));
+            let cr = (r - y) / (2.0 * (1.0 - self.kr));
+            [
+                y * luma_scale + luma_offset,
+                cb * chroma_scale + 128.0,
+                cr * chroma_scale + 128.0,
+            ]
+        }
+    }
+
+    fn to_code(value: f32) -> u8 {
+        value.round().clamp(0.0, 255.0) as u8
+    }
+
+    #[derive(Default)]
+    pub struct ColorMismatch {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for ColorMismatch {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let noop = self.settings.lock().unwrap().is_noop();
+            self.obj().set_passthrough(noop);
+        }
+
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecEnum::builder_with_default("assumed-matrix", DEFAULT_MATRIX)
+                        .nick("assumed-matrix")
+                        .blurb("Matrix the frames are wrongly decoded with before being encoded again as the caps say")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("assumed-range", DEFAULT_RANGE)
+                        .nick("assumed-range")
+                        .blurb("Range the frames are wrongly decoded with before being encoded again as the caps say")
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "assumed-matrix" => settings.matrix = value.get().expect("type checked upstream"),
+                "assumed-range" => settings.range = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+            let noop = settings.is_noop();
+            drop(settings);
+            self.obj().set_passthrough(noop);
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "assumed-matrix" => settings.matrix.to_value(),
+                "assumed-range" => settings.range.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for ColorMismatch {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ColorMismatch {
+        const NAME: &'static str = "colorMismatch";
+        type Type = super::ColorMismatch;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for ColorMismatch {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+    }
+
+    impl ElementImpl for ColorMismatch {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Color Mismatch Tool",
+                    "Filter/Effect/Video",
+                    "Decode with the wrong matrix or range and encode again to test colorimetry checks.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for ColorMismatch {
+        fn transform_frame_ip(
+            &self,
+            frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let actual = Colorimetry::from_info(frame.info());
+            let assumed = actual.assumed(&settings);
+            if assumed == actual {
+                return Ok(gst::FlowSuccess::Ok);
+            }
+
+            let format_info = frame.format_info();
+            let width = frame.width() as usize;
+            let height = frame.height() as usize;
+            let chroma_width = frame.comp_width(1) as usize;
+            let chroma_height = frame.comp_height(1) as usize;
+
+            // The chroma goes through twice: once read to decode the luma around
+            // it, then written as the average of what the luma re-encodes to.
+            let chroma = |frame: &gst_video::VideoFrameRef<&mut gst::BufferRef>, comp: usize| {
+                let plane = format_info.plane()[comp];
+                let stride = frame.plane_stride()[plane as usize] as usize;
+                let data = frame.plane_data(plane).unwrap();
+                let (poffset, pixel_stride) = (
+                    format_info.poffset()[comp] as usize,
+                    format_info.pixel_stride()[comp] as usize,
+                );
+                (0..chroma_height * chroma_width)
+                    .map(|i| {
+                        data[(i / chroma_width) * stride
+                            + poffset
+                            + (i % chroma_width) * pixel_stride]
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let (us, vs) = (chroma(frame, 1), chroma(frame, 2));
+            let mut sums = vec![[0.0f32; 3]; chroma_width * chroma_height];
+
+            let stride = frame.plane_stride()[0] as usize;
+            let luma = frame.plane_data_mut(0).unwrap();
+            for (y, line) in luma.chunks_mut(stride).take(height).enumerate() {
+                for (x, sample) in line[..width].iter_mut().enumerate() {
+                    let c = (y / 2) * chroma_width + x / 2;
+                    let [new_y, new_u, new_v] =
+                        actual.encode(assumed.decode(*sample, us[c], vs[c]));
+                    *sample = to_code(new_y);
+                    let sum = &mut sums[c];
+                    sum[0] += new_u;
+                    sum[1] += new_v;
+                    sum[2] += 1.0;
+                }
+            }
+
+            for comp in 1..3 {
+                let plane = format_info.plane()[comp];
+                let poffset = format_info.poffset()[comp] as usize;
+                let pixel_stride = format_info.pixel_stride()[comp] as usize;
+                let stride = frame.plane_stride()[plane as usize] as usize;
+                let data = frame.plane_data_mut(plane).unwrap();
+                for (i, sum) in sums.iter().enumerate() {
+                    let index =
+                        (i / chroma_width) * stride + poffset + (i % chroma_width) * pixel_stride;
+                    data[index] = to_code(sum[comp - 1] / sum[2]);
+                }
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use test::Bencher;
+
+        /// Run a 2x2 frame of one colour with the given caps colorimetry through a
+        /// `colorMismatch`, and return its Y, U and V.
+        fn run_plugin_tests(
+            settings: Settings,
+            format: VideoFormat,
+            colorimetry: &str,
+            yuv: [u8; 3],
+        ) -> Vec<u8> {
+            let _ = gst::init();
+
+            let plugin = ColorMismatch {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(format, 2, 2)
+                .colorimetry(&colorimetry.parse().unwrap())
+                .build()
+                .unwrap();
+            let (offset, stride) = (info.offset(), info.stride()[0] as usize);
+            let mut data = vec![0; info.size()];
+            data[..2].fill(yuv[0]);
+            data[stride..stride + 2].fill(yuv[0]);
+            match format {
+                VideoFormat::Nv12 => {
+                    data[offset[1]] = yuv[1];
+                    data[offset[1] + 1] = yuv[2];
+                }
+                _ => {
+                    data[offset[1]] = yuv[1];
+                    data[offset[2]] = yuv[2];
+                }
+            }
+            let buffer = gst::Buffer::from_slice(data);
+            let mut frame = gst_video::VideoFrame::from_buffer_writable(buffer, &info).unwrap();
+            plugin
+                .transform_frame_ip(&mut frame.as_mut_video_frame_ref())
+                .unwrap();
+
+            let stride = frame.plane_stride()[0] as usize;
+            let luma = frame.plane_data(0).unwrap();
+            assert_eq!(luma[..2], luma[stride..stride + 2]);
+            assert_eq!(luma[0], luma[1]);
+            match format {
+                VideoFormat::Nv12 => {
+                    let chroma = frame.plane_data(1).unwrap();
+                    vec![luma[0], chroma[0], chroma[1]]
+                }
+                _ => vec![
+                    luma[0],
+                    frame.plane_data(1).unwrap()[0],
+                    frame.plane_data(2).unwrap()[0],
+                ],
+            }
+        }
+
+        fn range_settings(range: Range) -> Settings {
+            Settings {
+                range,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_full_range_read_as_limited_crushes() {
+            let settings = range_settings(Range::Limited);
+            for (input, expected) in [(0, 0), (16, 0), (128, 130), (235, 255), (255, 255)] {
+                let out =
+                    run_plugin_tests(settings, VideoFormat::I420, "1:4:0:1", [input, 128, 128]);
+                assert_eq!(out, [expected, 128, 128], "Y {input}");
+            }
+        }
+
+        #[test]
+        fn test_limited_range_read_as_full_washes_out() {
+            let settings = range_settings(Range::Full);
+            for (input, expected) in [(16, 30), (128, 126), (235, 218)] {
+                let out =
+                    run_plugin_tests(settings, VideoFormat::Nv12, "2:4:5:4", [input, 128, 128]);
+                assert_eq!(out, [expected, 128, 128], "Y {input}");
+            }
+        }
+
+        #[test]
+        fn test_matrix_mismatch_moves_colours_not_greys() {
+            let settings = Settings {
+                matrix: Matrix::Bt601,
+                ..Default::default()
+            };
+            let grey = run_plugin_tests(settings, VideoFormat::I420, "bt709", [100, 128, 128]);
+            assert_eq!(grey, [100, 128, 128]);
+
+            // BT.709 red, read as BT.601, loses luma and saturation.
+            let red = run_plugin_tests(settings, VideoFormat::I420, "bt709", [63, 102, 240]);
+            assert!((58..=60).contains(&red[0]), "{red:?}");
+            assert!((229..=231).contains(&red[2]), "{red:?}");
+        }
+
+        #[test]
+        fn test_matching_assumption_leaves_frame_alone() {
+            let settings = Settings {
+                matrix: Matrix::Bt709,
+                range: Range::Limited,
+            };
+            let out = run_plugin_tests(settings, VideoFormat::I420, "bt709", [63, 102, 240]);
+            assert_eq!(out, [63, 102, 240]);
+        }
+
+        #[test]
+        fn test_round_trip_without_mismatch() {
+            for full_range in [false, true] {
+                let colorimetry = Colorimetry::new(Matrix::Bt2020, full_range);
+                for rgb in [
+                    [0.0, 0.0, 0.0],
+                    [1.0, 0.0, 0.0],
+                    [0.2, 0.6, 0.9],
+                    [1.0, 1.0, 1.0],
+                ] {
+                    let [y, u, v] = colorimetry.encode(rgb).map(to_code);
+                    let round_trip = colorimetry.decode(y, u, v);
+                    for (a, b) in rgb.iter().zip(round_trip) {
+                        assert!((a - b).abs() < 0.02, "{rgb:?} became {round_trip:?}");
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn test_passthrough_follows_settings() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::ColorMismatch>();
+            assert!(element.is_passthrough());
+            element.set_property("assumed-range", Range::Full);
+            assert!(!element.is_passthrough());
+            element.set_property("assumed-range", Range::Caps);
+            assert!(element.is_passthrough());
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let _ = gst::init();
+            let settings = test::black_box(Settings {
+                matrix: Matrix::Bt601,
+                range: Range::Full,
+            });
+
+            let plugin = ColorMismatch {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, width, height)
+                .build()
+                .unwrap();
+            let data_frame = vec![128; info.size()];
+            let buffer_frame = gst::Buffer::from_slice(data_frame);
+            let mut frame = test::black_box(
+                gst_video::VideoFrame::from_buffer_writable(buffer_frame, &info).unwrap(),
+            );
+
+            b.iter(|| plugin.transform_frame_ip(&mut frame.as_mut_video_frame_ref()));
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
@@ -0,0 +1,736 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct DiffHeatmap(ObjectSubclass<imp::DiffHeatmap>) @extends gst_base::Aggregator, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDiffHeatmapLayout")]
+pub enum Layout {
+    #[enum_value(
+        name = "The luma difference only, at the size of the input",
+        nick = "luma"
+    )]
+    Luma = 0,
+    #[enum_value(
+        name = "The Y, U and V differences side by side, as planeExpand lays them out, with chroma scaled up to the luma size",
+        nick = "side-by-side"
+    )]
+    SideBySide = 1,
+    #[enum_value(
+        name = "The largest of the Y, U and V differences at each luma sample",
+        nick = "max"
+    )]
+    Max = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDiffHeatmapColormap")]
+pub enum Colormap {
+    #[enum_value(name = "Viridis, from dark purple to yellow", nick = "viridis")]
+    Viridis = 0,
+    #[enum_value(name = "Jet, from dark blue through green to dark red", nick = "jet")]
+    Jet = 1,
+    #[enum_value(name = "Grey, from black to white", nick = "grey")]
+    Grey = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstDiffHeatmapMode")]
+pub enum Mode {
+    #[enum_value(
+        name = "Colour by the size of the difference times the gain",
+        nick = "magnitude"
+    )]
+    Magnitude = 0,
+    #[enum_value(
+        name = "The top colour where the difference is above the threshold and the bottom one elsewhere",
+        nick = "threshold"
+    )]
+    Threshold = 1,
+}
+
+/// Nine evenly spaced points of matplotlib's viridis, which is close enough to
+/// interpolate between.
+const VIRIDIS: [[f64; 3]; 9] = [
+    [68.0, 1.0, 84.0],
+    [71.0, 45.0, 123.0],
+    [59.0, 82.0, 139.0],
+    [44.0, 114.0, 142.0],
+    [33.0, 145.0, 140.0],
+    [40.0, 174.0, 128.0],
+    [94.0, 201.0, 98.0],
+    [173.0, 220.0, 48.0],
+    [253.0, 231.0, 37.0],
+];
+
+impl Colormap {
+    /// The RGB colour of `t`, from 0 to 1.
+    pub fn colour(self, t: f64) -> [u8; 3] {
+        let t = t.clamp(0.0, 1.0);
+        let rgb = match self {
+            Colormap::Viridis => {
+                let position = t * (VIRIDIS.len() - 1) as f64;
+                let i = (position as usize).min(VIRIDIS.len() - 2);
+                let frac = position - i as f64;
+                std::array::from_fn(|c| {
+                    (VIRIDIS[i][c] + (VIRIDIS[i + 1][c] - VIRIDIS[i][c]) * frac) / 255.0
+                })
+            }
+            // MATLAB's jet, where each channel is a trapezium a little under half
+            // the range wide, centred at 3/4, 1/2 and 1/4.
+            Colormap::Jet => [3.0, 2.0, 1.0].map(|centre| 1.5 - (4.0 * t - centre).abs()),
+            Colormap::Grey => [t; 3],
+        };
+        rgb.map(|c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "diffHeatmap",
+        gst::Rank::NONE,
+        DiffHeatmap::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_base::prelude::*;
+    use gst_base::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::{Colormap, Layout, Mode};
+    use crate::quality_compare::{pairing, Pairing};
+    use crate::yuv_offset::{self, FORMATS};
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "diffHeatmap",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Diff Heatmap"),
+        )
+    });
+
+    const DEFAULT_LAYOUT: Layout = Layout::Luma;
+    const DEFAULT_COLORMAP: Colormap = Colormap::Viridis;
+    const DEFAULT_MODE: Mode = Mode::Magnitude;
+    const DEFAULT_GAIN: f64 = 1.0;
+    const DEFAULT_THRESHOLD: u32 = 0;
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        layout: Layout,
+        colormap: Colormap,
+        mode: Mode,
+        /// What the difference, as a fraction of the largest sample, is multiplied
+        /// by before it is coloured.
+        gain: f64,
+        /// In samples at the depth of the input.
+        threshold: u32,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                layout: DEFAULT_LAYOUT,
+                colormap: DEFAULT_COLORMAP,
+                mode: DEFAULT_MODE,
+                gain: DEFAULT_GAIN,
+                threshold: DEFAULT_THRESHOLD,
+            }
+        }
+    }
+
+    impl Settings {
+        /// The colour of every difference from 0 to `max`.
+        fn palette(&self, max: u32) -> Vec<[u8; 3]> {
+            (0..=max)
+                .map(|diff| {
+                    let t = match self.mode {
+                        Mode::Magnitude => diff as f64 * self.gain / max as f64,
+                        Mode::Threshold if diff > self.threshold => 1.0,
+                        Mode::Threshold => 0.0,
+                    };
+                    self.colormap.colour(t)
+                })
+                .collect()
+        }
+    }
+
+    /// The negotiated video on each pad.
+    #[derive(Debug, Default)]
+    struct State {
+        reference: Option<gst_video::VideoInfo>,
+        distorted: Option<gst_video::VideoInfo>,
+        output: Option<gst_video::VideoInfo>,
+    }
+
+    /// The RGB heatmap of video like `input`.
+    fn output_info(
+        input: &gst_video::VideoInfo,
+        layout: Layout,
+    ) -> Result<gst_video::VideoInfo, glib::BoolError> {
+        let panels = match layout {
+            Layout::SideBySide => 3,
+            Layout::Luma | Layout::Max => 1,
+        };
+        gst_video::VideoInfo::builder(VideoFormat::Rgb, input.width() * panels, input.height())
+            .fps(input.fps())
+            .par(input.par())
+            .build()
+    }
+
+    /// Draw the heatmap of two frames of the same format and size into an RGB
+    /// frame laid out for `settings`.
+    fn render(
+        reference: &impl VideoFrameExt,
+        distorted: &impl VideoFrameExt,
+        settings: &Settings,
+        out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+    ) {
+        let depth = reference.format_info().depth()[0];
+        let palette = settings.palette((1 << depth) - 1);
+        let width = reference.width() as usize;
+        let height = reference.height() as usize;
+
+        let diffs = (0..3)
+            .map(|comp| {
+                let a = yuv_offset::component_samples(reference, comp);
+                let b = yuv_offset::component_samples(distorted, comp);
+                let diff = a
+                    .iter()
+                    .zip(&b)
+                    .map(|(a, b)| a.abs_diff(*b))
+                    .collect::<Vec<_>>();
+                let comp_width = reference.comp_width(comp as u8) as usize;
+                let comp_height = reference.comp_height(comp as u8) as usize;
+                (comp_width, comp_height, diff)
+            })
+            .collect::<Vec<_>>();
+        // The difference in a component at a luma position, so chroma is scaled up
+        // by repeating it.
+        let diff_at = |comp: usize, x: usize, y: usize| {
+            let (comp_width, comp_height, diff) = &diffs[comp];
+            diff[(y * comp_height / height) * comp_width + x * comp_width / width] as usize
+        };
+
+        let stride = out_frame.plane_stride()[0] as usize;
+        let data = out_frame.plane_data_mut(0).unwrap();
+        for (y, line) in data.chunks_mut(stride).take(height).enumerate() {
+            let mut put =
+                |x: usize, diff: usize| line[3 * x..3 * x + 3].copy_from_slice(&palette[diff]);
+            for x in 0..width {
+                match settings.layout {
+                    Layout::Luma => put(x, diff_at(0, x, y)),
+                    Layout::SideBySide => {
+                        for comp in 0..3 {
+                            put(comp * width + x, diff_at(comp, x, y));
+                        }
+                    }
+                    Layout::Max => put(x, (0..3).map(|comp| diff_at(comp, x, y)).max().unwrap()),
+                }
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct DiffHeatmap {
+        settings: Mutex<Settings>,
+        state: Mutex<State>,
+    }
+
+    impl ObjectImpl for DiffHeatmap {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+            for name in ["ref", "dist"] {
+                let templ = obj.pad_template(name).unwrap();
+                let pad = gst::PadBuilder::<gst_base::AggregatorPad>::from_template(&templ).build();
+                obj.add_pad(&pad).unwrap();
+            }
+        }
+
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecEnum::builder_with_default("layout", DEFAULT_LAYOUT)
+                        .nick("layout")
+                        .blurb("Which differences to show and how to lay them out")
+                        .mutable_ready()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("colormap", DEFAULT_COLORMAP)
+                        .nick("colormap")
+                        .blurb("Colours to show the differences in")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("mode", DEFAULT_MODE)
+                        .nick("mode")
+                        .blurb("Whether to colour the differences by size or by whether they are above the threshold")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecDouble::builder("gain")
+                        .nick("gain")
+                        .blurb("Multiplier of the difference as a fraction of the largest sample, in magnitude mode")
+                        .minimum(0.0)
+                        .maximum(f64::MAX)
+                        .default_value(DEFAULT_GAIN)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("threshold")
+                        .nick("threshold")
+                        .blurb("Largest difference, in samples at the input depth, shown in the bottom colour in threshold mode")
+                        .maximum(u16::MAX as u32)
+                        .default_value(DEFAULT_THRESHOLD)
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "layout" => settings.layout = value.get().expect("type checked upstream"),
+                "colormap" => settings.colormap = value.get().expect("type checked upstream"),
+                "mode" => settings.mode = value.get().expect("type checked upstream"),
+                "gain" => settings.gain = value.get().expect("type checked upstream"),
+                "threshold" => settings.threshold = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "layout" => settings.layout.to_value(),
+                "colormap" => settings.colormap.to_value(),
+                "mode" => settings.mode.to_value(),
+                "gain" => settings.gain.to_value(),
+                "threshold" => settings.threshold.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for DiffHeatmap {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for DiffHeatmap {
+        const NAME: &'static str = "diffHeatmap";
+        type Type = super::DiffHeatmap;
+        type ParentType = gst_base::Aggregator;
+    }
+
+    impl ElementImpl for DiffHeatmap {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Diff Heatmap Tool",
+                    "Filter/Analyzer/Video",
+                    "Show where distorted video differs from a reference as a false colour heatmap.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format(VideoFormat::Rgb)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let sink_pad_template = |name| {
+                    gst::PadTemplate::with_gtype(
+                        name,
+                        gst::PadDirection::Sink,
+                        gst::PadPresence::Always,
+                        &caps,
+                        gst_base::AggregatorPad::static_type(),
+                    )
+                    .unwrap()
+                };
+
+                vec![
+                    src_pad_template,
+                    sink_pad_template("ref"),
+                    sink_pad_template("dist"),
+                ]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl AggregatorImpl for DiffHeatmap {
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = State::default();
+            self.parent_start()
+        }
+
+        fn sink_event(&self, pad: &gst_base::AggregatorPad, event: gst::Event) -> bool {
+            if let gst::EventView::Caps(caps) = event.view() {
+                let Ok(info) = gst_video::VideoInfo::from_caps(caps.caps()) else {
+                    return false;
+                };
+                let mut state = self.state.lock().unwrap();
+                if pad.name() == "ref" {
+                    state.reference = Some(info);
+                } else {
+                    state.distorted = Some(info);
+                    drop(state);
+                    // The output is the size of the distorted video.
+                    self.obj().static_pad("src").unwrap().mark_reconfigure();
+                }
+            }
+            self.parent_sink_event(pad, event)
+        }
+
+        fn update_src_caps(&self, caps: &gst::Caps) -> Result<gst::Caps, gst::FlowError> {
+            let layout = self.settings.lock().unwrap().layout;
+            let state = self.state.lock().unwrap();
+            let distorted = state
+                .distorted
+                .as_ref()
+                .ok_or(gst_base::AGGREGATOR_FLOW_NEED_DATA)?;
+            let output = output_info(distorted, layout)
+                .and_then(|info| info.to_caps())
+                .map_err(|_| gst::FlowError::NotNegotiated)?;
+            if !caps.can_intersect(&output) {
+                return Err(gst::FlowError::NotNegotiated);
+            }
+            Ok(output)
+        }
+
+        fn negotiated_src_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
+            let info = gst_video::VideoInfo::from_caps(caps)
+                .map_err(|_| gst::loggable_error!(CAT, "Invalid output caps {}", caps))?;
+            self.state.lock().unwrap().output = Some(info);
+            self.parent_negotiated_src_caps(caps)
+        }
+
+        fn aggregate(&self, _timeout: bool) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let reference_pad = self.sink_pad("ref");
+            let distorted_pad = self.sink_pad("dist");
+
+            let Some(distorted) = distorted_pad.peek_buffer() else {
+                if distorted_pad.is_eos() {
+                    return Err(gst::FlowError::Eos);
+                }
+                return Ok(gst::FlowSuccess::Ok);
+            };
+            let reference = reference_pad.peek_buffer();
+
+            match pairing(
+                reference.as_ref().map(|buffer| buffer.pts()),
+                distorted.pts(),
+            ) {
+                Pairing::DropReference => {
+                    reference_pad.drop_buffer();
+                    Ok(gst::FlowSuccess::Ok)
+                }
+                Pairing::Compare => {
+                    reference_pad.drop_buffer();
+                    distorted_pad.drop_buffer();
+                    let output = self.heatmap(&reference.unwrap(), &distorted)?;
+                    self.obj().finish_buffer(output)
+                }
+                Pairing::PassThrough => {
+                    gst::debug!(
+                        CAT,
+                        imp = self,
+                        "No reference frame for {:?}",
+                        distorted.pts()
+                    );
+                    distorted_pad.drop_buffer();
+                    Ok(gst::FlowSuccess::Ok)
+                }
+            }
+        }
+    }
+
+    impl DiffHeatmap {
+        fn sink_pad(&self, name: &str) -> gst_base::AggregatorPad {
+            self.obj().static_pad(name).unwrap().downcast().unwrap()
+        }
+
+        /// The heatmap of a distorted frame against its reference, with the
+        /// timestamps of the distorted frame.
+        fn heatmap(
+            &self,
+            reference: &gst::Buffer,
+            distorted: &gst::Buffer,
+        ) -> Result<gst::Buffer, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let state = self.state.lock().unwrap();
+            let (Some(reference_info), Some(distorted_info), Some(out_info)) =
+                (&state.reference, &state.distorted, &state.output)
+            else {
+                return Err(gst::FlowError::NotNegotiated);
+            };
+            if (
+                reference_info.format(),
+                reference_info.width(),
+                reference_info.height(),
+            ) != (
+                distorted_info.format(),
+                distorted_info.width(),
+                distorted_info.height(),
+            ) {
+                gst::element_imp_error!(
+                    self,
+                    gst::StreamError::Format,
+                    [
+                        "Reference is {}x{} {:?} but distorted is {}x{} {:?}",
+                        reference_info.width(),
+                        reference_info.height(),
+                        reference_info.format(),
+                        distorted_info.width(),
+                        distorted_info.height(),
+                        distorted_info.format()
+                    ]
+                );
+                return Err(gst::FlowError::NotNegotiated);
+            }
+            // The layout can only change in READY, but the caps might not have caught up.
+            let expected = output_info(distorted_info, settings.layout)
+                .map_err(|_| gst::FlowError::NotNegotiated)?;
+            if out_info.width() != expected.width() {
+                return Err(gst::FlowError::NotNegotiated);
+            }
+
+            let reference_frame = gst_video::VideoFrameRef::from_buffer_ref_readable(
+                reference.as_ref(),
+                reference_info,
+            )
+            .map_err(|_| gst::FlowError::Error)?;
+            let distorted_frame = gst_video::VideoFrameRef::from_buffer_ref_readable(
+                distorted.as_ref(),
+                distorted_info,
+            )
+            .map_err(|_| gst::FlowError::Error)?;
+
+            let mut output =
+                gst::Buffer::with_size(out_info.size()).map_err(|_| gst::FlowError::Error)?;
+            {
+                let output = output.get_mut().unwrap();
+                output.set_pts(distorted.pts());
+                output.set_duration(distorted.duration());
+                output.set_offset(distorted.offset());
+                let mut out_frame =
+                    gst_video::VideoFrameRef::from_buffer_ref_writable(output, out_info)
+                        .map_err(|_| gst::FlowError::Error)?;
+                render(
+                    &reference_frame,
+                    &distorted_frame,
+                    &settings,
+                    &mut out_frame,
+                );
+            }
+            Ok(output)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// An I420 frame with each plane filled from `yuv`, which repeats.
+        fn frame(
+            info: &gst_video::VideoInfo,
+            yuv: [&[u8]; 3],
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Readable> {
+            let mut data = vec![0; info.size()];
+            for (plane, values) in yuv.iter().enumerate() {
+                let stride = info.stride()[plane] as usize;
+                let width = info.comp_width(plane as u8) as usize;
+                for row in 0..info.comp_height(plane as u8) as usize {
+                    for x in 0..width {
+                        data[info.offset()[plane] + row * stride + x] =
+                            values[(row * width + x) % values.len()];
+                    }
+                }
+            }
+            gst_video::VideoFrame::from_buffer_readable(gst::Buffer::from_slice(data), info)
+                .unwrap()
+        }
+
+        /// Render the heatmap of two 4x2 I420 frames, returning the RGB of each
+        /// output row.
+        fn run_render(
+            settings: Settings,
+            reference: [&[u8]; 3],
+            distorted: [&[u8]; 3],
+        ) -> Vec<Vec<[u8; 3]>> {
+            let _ = gst::init();
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2)
+                .build()
+                .unwrap();
+            let out_info = output_info(&info, settings.layout).unwrap();
+            let reference = frame(&info, reference);
+            let distorted = frame(&info, distorted);
+            let buffer = gst::Buffer::with_size(out_info.size()).unwrap();
+            let mut out_frame =
+                gst_video::VideoFrame::from_buffer_writable(buffer, &out_info).unwrap();
+            render(
+                &reference,
+                &distorted,
+                &settings,
+                &mut out_frame.as_mut_video_frame_ref(),
+            );
+
+            let stride = out_frame.plane_stride()[0] as usize;
+            let width = out_info.width() as usize;
+            let data = out_frame.plane_data(0).unwrap();
+            (0..2)
+                .map(|row| {
+                    data[row * stride..row * stride + 3 * width]
+                        .chunks_exact(3)
+                        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                        .collect()
+                })
+                .collect()
+        }
+
+        fn grey(level: u8) -> [u8; 3] {
+            [level; 3]
+        }
+
+        #[test]
+        fn test_colormaps() {
+            assert_eq!(Colormap::Viridis.colour(0.0), [68, 1, 84]);
+            assert_eq!(Colormap::Viridis.colour(0.5), [33, 145, 140]);
+            assert_eq!(Colormap::Viridis.colour(1.0), [253, 231, 37]);
+            assert_eq!(Colormap::Viridis.colour(2.0), [253, 231, 37]);
+            assert_eq!(Colormap::Jet.colour(0.0), [0, 0, 128]);
+            assert_eq!(Colormap::Jet.colour(0.5), [128, 255, 128]);
+            assert_eq!(Colormap::Jet.colour(1.0), [128, 0, 0]);
+            assert_eq!(Colormap::Grey.colour(0.2), [51, 51, 51]);
+        }
+
+        #[test]
+        fn test_luma_magnitude() {
+            let settings = Settings {
+                colormap: Colormap::Grey,
+                gain: 5.0,
+                ..Default::default()
+            };
+            let rows = run_render(
+                settings,
+                [&[100], &[128], &[128]],
+                [&[100, 101, 90, 200, 110, 100, 100, 100], &[0], &[0]],
+            );
+            // 5 times the difference, until it saturates.
+            assert_eq!(rows[0], [grey(0), grey(5), grey(50), grey(255)]);
+            assert_eq!(rows[1], [grey(50), grey(0), grey(0), grey(0)]);
+        }
+
+        #[test]
+        fn test_threshold() {
+            let settings = Settings {
+                colormap: Colormap::Jet,
+                mode: Mode::Threshold,
+                threshold: 10,
+                ..Default::default()
+            };
+            let rows = run_render(
+                settings,
+                [&[100], &[128], &[128]],
+                [&[110, 111, 89, 90], &[128], &[128]],
+            );
+            let (low, high) = (Colormap::Jet.colour(0.0), Colormap::Jet.colour(1.0));
+            assert_eq!(rows[0], [low, high, high, low]);
+        }
+
+        #[test]
+        fn test_side_by_side() {
+            let settings = Settings {
+                layout: Layout::SideBySide,
+                colormap: Colormap::Grey,
+                gain: 255.0,
+                ..Default::default()
+            };
+            let rows = run_render(
+                settings,
+                [&[0], &[0], &[0]],
+                [&[0, 1, 0, 0], &[0, 1], &[1, 0]],
+            );
+            let (off, on) = (grey(0), grey(255));
+            for row in rows {
+                assert_eq!(row, [off, on, off, off, off, off, on, on, on, on, off, off]);
+            }
+        }
+
+        #[test]
+        fn test_max() {
+            let settings = Settings {
+                layout: Layout::Max,
+                colormap: Colormap::Grey,
+                ..Default::default()
+            };
+            let rows = run_render(
+                settings,
+                [&[0], &[0], &[0]],
+                [&[51, 0, 0, 0], &[0, 102], &[153, 0]],
+            );
+            for row in rows {
+                assert_eq!(row, [grey(153), grey(153), grey(102), grey(102)]);
+            }
+        }
+
+        #[test]
+        fn test_heatmap_keeps_timestamps() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::DiffHeatmap>();
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2)
+                .build()
+                .unwrap();
+            {
+                let mut state = element.imp().state.lock().unwrap();
+                state.reference = Some(info.clone());
+                state.distorted = Some(info.clone());
+                state.output = Some(output_info(&info, DEFAULT_LAYOUT).unwrap());
+            }
+            let mut distorted = gst::Buffer::from_slice(vec![0; info.size()]);
+            {
+                let distorted = distorted.get_mut().unwrap();
+                distorted.set_pts(gst::ClockTime::from_mseconds(40));
+                distorted.set_duration(gst::ClockTime::from_mseconds(20));
+            }
+            let reference = gst::Buffer::from_slice(vec![0; info.size()]);
+
+            let output = element.imp().heatmap(&reference, &distorted).unwrap();
+            assert_eq!(output.pts(), Some(gst::ClockTime::from_mseconds(40)));
+            assert_eq!(output.duration(), Some(gst::ClockTime::from_mseconds(20)));
+            assert_eq!(
+                output.size(),
+                output_info(&info, DEFAULT_LAYOUT).unwrap().size()
+            );
+        }
+    }
+}
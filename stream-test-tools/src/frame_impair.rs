@@ -0,0 +1,663 @@
+use gst::glib;
+use gst::prelude::*;
+
+use std::collections::BTreeSet;
+
+glib::wrapper! {
+    pub struct FrameImpair(ObjectSubclass<imp::FrameImpair>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstFrameImpairTimestamps")]
+pub enum Timestamps {
+    #[enum_value(
+        name = "Every buffer keeps its own timestamps, so drops leave gaps, duplicates repeat and swaps go backwards",
+        nick = "original"
+    )]
+    Original = 0,
+    #[enum_value(
+        name = "Buffers take the timestamps of the slot they are output in, and duplicates share their slot",
+        nick = "slot"
+    )]
+    Slot = 1,
+    #[enum_value(
+        name = "Buffers are restamped back to back from the first timestamp, as a capture device would",
+        nick = "restamp"
+    )]
+    Restamp = 2,
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "frameImpair",
+        gst::Rank::NONE,
+        FrameImpair::static_type(),
+    )
+}
+
+/// Split a comma separated list of frame numbers into the frames, and the
+/// entries that aren't frame numbers. The other elements that act on listed
+/// frames share it.
+pub fn parse_frames(list: &str) -> (BTreeSet<u64>, Vec<&str>) {
+    let mut frames = BTreeSet::new();
+    let mut invalid = vec![];
+    for entry in list.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match entry.parse() {
+            Ok(frame) => {
+                frames.insert(frame);
+            }
+            Err(_) => invalid.push(entry),
+        }
+    }
+    (frames, invalid)
+}
+
+/// The inverse of [`parse_frames`], as the frame list properties read back.
+pub fn format_frames(frames: &BTreeSet<u64>) -> String {
+    frames
+        .iter()
+        .map(|frame| frame.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_base::prelude::*;
+    use gst_base::subclass::base_transform::GenerateOutputSuccess;
+    use gst_video::subclass::prelude::*;
+
+    use std::collections::{BTreeSet, VecDeque};
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::{format_frames, parse_frames, Timestamps};
+    use crate::noise_inject::Rng;
+
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "frameImpair",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Frame Impair"),
+        )
+    });
+
+    const DEFAULT_RATE: f64 = 0.0;
+    const DEFAULT_FREEZE_LENGTH: u32 = 5;
+    const DEFAULT_SEED: u64 = 0;
+    const DEFAULT_TIMESTAMPS: Timestamps = Timestamps::Slot;
+
+    /// What can happen to an input frame, in the order they are checked.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Action {
+        Drop,
+        Duplicate,
+        Freeze,
+        Swap,
+    }
+
+    impl Action {
+        const ALL: [Action; 4] = [Action::Drop, Action::Duplicate, Action::Freeze, Action::Swap];
+
+        fn name(self) -> &'static str {
+            match self {
+                Action::Drop => "drop",
+                Action::Duplicate => "duplicate",
+                Action::Freeze => "freeze",
+                Action::Swap => "swap",
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Settings {
+        /// Input frames, counted from 0, that each action is applied to.
+        frames: [BTreeSet<u64>; 4],
+        /// Chance of each action happening to any other frame.
+        rates: [f64; 4],
+        freeze_length: u32,
+        seed: u64,
+        timestamps: Timestamps,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                frames: Default::default(),
+                rates: [DEFAULT_RATE; 4],
+                freeze_length: DEFAULT_FREEZE_LENGTH,
+                seed: DEFAULT_SEED,
+                timestamps: DEFAULT_TIMESTAMPS,
+            }
+        }
+    }
+
+    impl Settings {
+        fn action(&self, frame: u64) -> Option<Action> {
+            Action::ALL
+                .into_iter()
+                .enumerate()
+                .find(|&(index, _)| {
+                    self.frames[index].contains(&frame)
+                        || Rng::new(self.seed, frame, index).next_f64() < self.rates[index]
+                })
+                .map(|(_, action)| action)
+        }
+    }
+
+    #[derive(Default)]
+    struct State {
+        /// Input frames seen since the last flush.
+        frame: u64,
+        /// The frame being repeated by a freeze, and how many more frames it replaces.
+        frozen: Option<gst::Buffer>,
+        freeze_remaining: u32,
+        /// The first frame of a swap, waiting for the one it swaps with.
+        held: Option<(u64, gst::Buffer)>,
+        /// Buffers ready to be pushed by `generate_output`.
+        pending: VecDeque<gst::Buffer>,
+        /// Where the next buffer starts, when restamping.
+        next_pts: Option<gst::ClockTime>,
+        /// Length of a frame from the caps, for buffers without a duration.
+        frame_duration: Option<gst::ClockTime>,
+    }
+
+    #[derive(Default)]
+    pub struct FrameImpair {
+        settings: Mutex<Settings>,
+        state: Mutex<State>,
+    }
+
+    impl ObjectImpl for FrameImpair {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                let lists = Action::ALL.into_iter().map(|action| {
+                    let name = format!("{}-frames", action.name());
+                    glib::ParamSpecString::builder(&name)
+                        .nick(&name)
+                        .blurb(&format!(
+                            "Comma separated input frame numbers, counted from 0, to {}",
+                            action.name()
+                        ))
+                        .mutable_playing()
+                        .build()
+                });
+                let rates = Action::ALL.into_iter().map(|action| {
+                    let name = format!("{}-rate", action.name());
+                    glib::ParamSpecDouble::builder(&name)
+                        .nick(&name)
+                        .blurb(&format!("Chance of any other frame being a {}", action.name()))
+                        .minimum(0.0)
+                        .maximum(1.0)
+                        .default_value(DEFAULT_RATE)
+                        .mutable_playing()
+                        .build()
+                });
+                lists
+                    .chain(rates)
+                    .chain([
+                        glib::ParamSpecUInt::builder("freeze-length")
+                            .nick("freeze-length")
+                            .blurb("Number of frames after a frozen one that are replaced by it")
+                            .minimum(1)
+                            .default_value(DEFAULT_FREEZE_LENGTH)
+                            .mutable_playing()
+                            .build(),
+                        glib::ParamSpecUInt64::builder("seed")
+                            .nick("seed")
+                            .blurb("Seed for the frames picked by the rates")
+                            .default_value(DEFAULT_SEED)
+                            .mutable_playing()
+                            .build(),
+                        glib::ParamSpecEnum::builder_with_default("timestamps", DEFAULT_TIMESTAMPS)
+                            .nick("timestamps")
+                            .blurb("How the output buffers are timestamped")
+                            .mutable_playing()
+                            .build(),
+                    ])
+                    .collect()
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "freeze-length" => settings.freeze_length = value.get().expect("type checked upstream"),
+                "seed" => settings.seed = value.get().expect("type checked upstream"),
+                "timestamps" => settings.timestamps = value.get().expect("type checked upstream"),
+                name => {
+                    let (action, kind) = name.split_once('-').unwrap();
+                    let index = Action::ALL
+                        .iter()
+                        .position(|a| a.name() == action)
+                        .unwrap_or_else(|| unimplemented!());
+                    match kind {
+                        "frames" => {
+                            let list = value.get::<Option<String>>().expect("type checked upstream");
+                            settings.frames[index] = self.parse_frames(list.as_deref().unwrap_or(""));
+                        }
+                        "rate" => settings.rates[index] = value.get().expect("type checked upstream"),
+                        _ => unimplemented!(),
+                    }
+                }
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "freeze-length" => settings.freeze_length.to_value(),
+                "seed" => settings.seed.to_value(),
+                "timestamps" => settings.timestamps.to_value(),
+                name => {
+                    let (action, kind) = name.split_once('-').unwrap();
+                    let index = Action::ALL
+                        .iter()
+                        .position(|a| a.name() == action)
+                        .unwrap_or_else(|| unimplemented!());
+                    match kind {
+                        "frames" => format_frames(&settings.frames[index]).to_value(),
+                        "rate" => settings.rates[index].to_value(),
+                        _ => unimplemented!(),
+                    }
+                }
+            }
+        }
+    }
+
+    impl GstObjectImpl for FrameImpair {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for FrameImpair {
+        const NAME: &'static str = "frameImpair";
+        type Type = super::FrameImpair;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for FrameImpair {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            self.reset();
+            self.parent_start()
+        }
+
+        fn sink_event(&self, event: gst::Event) -> bool {
+            match event.view() {
+                gst::EventView::Eos(_) => {
+                    // A swap that never found its second frame just goes out late.
+                    let timestamps = self.settings.lock().unwrap().timestamps;
+                    let mut state = self.state.lock().unwrap();
+                    let held = state.held.take();
+                    let held = held.map(|(_, held)| stamp(&mut state, timestamps, held.clone(), &held));
+                    drop(state);
+                    if let Some(buffer) = held {
+                        if let Err(err) = self.obj().src_pad().push(buffer) {
+                            gst::debug!(CAT, imp = self, "Failed to push the held frame: {err:?}");
+                        }
+                    }
+                }
+                gst::EventView::FlushStop(_) => self.reset(),
+                _ => (),
+            }
+            self.parent_sink_event(event)
+        }
+
+        fn submit_input_buffer(
+            &self,
+            _is_discont: bool,
+            inbuf: gst::Buffer,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = self.settings.lock().unwrap().clone();
+            let mut state = self.state.lock().unwrap();
+            let frame = state.frame;
+            state.frame += 1;
+            if state.next_pts.is_none() {
+                state.next_pts = inbuf.pts();
+            }
+
+            // Frames replaced by a freeze, or swapped with the one before them, don't
+            // get an action of their own.
+            if state.freeze_remaining > 0 {
+                state.freeze_remaining -= 1;
+                let frozen = state.frozen.clone().unwrap();
+                self.post_action("frozen", frame, &inbuf, |s| s);
+                let buffer = stamp(&mut state, settings.timestamps, frozen, &inbuf);
+                state.pending.push_back(buffer);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+            if let Some((held_frame, held)) = state.held.take() {
+                self.post_action("swap", held_frame, &held, |s| {
+                    s.field("swapped-frame", frame)
+                        .field("swapped-offset", inbuf.offset())
+                });
+                let first = stamp(&mut state, settings.timestamps, inbuf.clone(), &held);
+                let second = stamp(&mut state, settings.timestamps, held, &inbuf);
+                state.pending.extend([first, second]);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+
+            match settings.action(frame) {
+                None => {
+                    let buffer = stamp(&mut state, settings.timestamps, inbuf.clone(), &inbuf);
+                    state.pending.push_back(buffer);
+                }
+                Some(Action::Drop) => self.post_action("drop", frame, &inbuf, |s| s),
+                Some(Action::Duplicate) => {
+                    self.post_action("duplicate", frame, &inbuf, |s| s);
+                    let copies = duplicate(&mut state, settings.timestamps, &inbuf);
+                    state.pending.extend(copies);
+                }
+                Some(Action::Freeze) => {
+                    self.post_action("freeze", frame, &inbuf, |s| {
+                        s.field("length", settings.freeze_length)
+                    });
+                    let buffer = stamp(&mut state, settings.timestamps, inbuf.clone(), &inbuf);
+                    state.pending.push_back(buffer);
+                    state.frozen = Some(inbuf);
+                    state.freeze_remaining = settings.freeze_length;
+                }
+                Some(Action::Swap) => state.held = Some((frame, inbuf)),
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+
+        fn generate_output(&self) -> Result<GenerateOutputSuccess, gst::FlowError> {
+            match self.state.lock().unwrap().pending.pop_front() {
+                Some(buffer) => Ok(GenerateOutputSuccess::Buffer(buffer)),
+                None => Ok(GenerateOutputSuccess::NoOutput),
+            }
+        }
+    }
+
+    impl ElementImpl for FrameImpair {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Frame Impair Tool",
+                    "Filter/Effect/Video",
+                    "Drop, duplicate, freeze or swap frames to test temporal analysers.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new().build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new().build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for FrameImpair {
+        fn set_info(
+            &self,
+            incaps: &gst::Caps,
+            in_info: &gst_video::VideoInfo,
+            outcaps: &gst::Caps,
+            out_info: &gst_video::VideoInfo,
+        ) -> Result<(), gst::LoggableError> {
+            let fps = in_info.fps();
+            self.state.lock().unwrap().frame_duration = (fps.numer() > 0).then(|| {
+                gst::ClockTime::SECOND
+                    .mul_div_floor(fps.denom() as u64, fps.numer() as u64)
+                    .unwrap()
+            });
+            self.parent_set_info(incaps, in_info, outcaps, out_info)
+        }
+    }
+
+    impl FrameImpair {
+        fn reset(&self) {
+            let mut state = self.state.lock().unwrap();
+            *state = State {
+                frame_duration: state.frame_duration,
+                ..Default::default()
+            };
+        }
+
+        /// Comma separated frame numbers. Anything that isn't one is skipped.
+        fn parse_frames(&self, list: &str) -> BTreeSet<u64> {
+            let (frames, invalid) = parse_frames(list);
+            for frame in invalid {
+                gst::warning!(CAT, imp = self, "Ignoring frame number {frame:?}");
+            }
+            frames
+        }
+
+        /// Post what happened to input frame `frame` on the bus.
+        fn post_action(
+            &self,
+            action: &str,
+            frame: u64,
+            buffer: &gst::BufferRef,
+            extra: impl FnOnce(gst::structure::Builder) -> gst::structure::Builder,
+        ) {
+            gst::debug!(CAT, imp = self, "{action} frame {frame}");
+            let structure = gst::Structure::builder("frameimpair")
+                .field("action", action)
+                .field("frame", frame)
+                .field("offset", buffer.offset())
+                .field("pts", buffer.pts());
+            let structure = extra(structure).build();
+            let obj = self.obj();
+            let _ = obj.post_message(gst::message::Element::builder(structure).src(&*obj).build());
+        }
+    }
+
+    /// `content` with the timestamps it should go out with in the place of `slot`.
+    fn stamp(
+        state: &mut State,
+        policy: Timestamps,
+        content: gst::Buffer,
+        slot: &gst::BufferRef,
+    ) -> gst::Buffer {
+        let duration = slot.duration().or(state.frame_duration);
+        let (pts, duration) = match policy {
+            Timestamps::Original => return content,
+            Timestamps::Slot => (slot.pts(), duration),
+            Timestamps::Restamp => {
+                let pts = state.next_pts;
+                state.next_pts = pts.zip(duration).map(|(pts, duration)| pts + duration);
+                (pts, duration)
+            }
+        };
+        let mut buffer = content;
+        let buffer_mut = buffer.make_mut();
+        buffer_mut.set_pts(pts);
+        buffer_mut.set_dts(pts);
+        buffer_mut.set_duration(duration);
+        buffer
+    }
+
+    /// The two copies of a duplicated frame. In its own slot they split the
+    /// frame's duration between them.
+    fn duplicate(state: &mut State, policy: Timestamps, slot: &gst::Buffer) -> [gst::Buffer; 2] {
+        let duration = slot.duration().or(state.frame_duration);
+        match (policy, slot.pts(), duration) {
+            (Timestamps::Slot, Some(pts), Some(duration)) => {
+                let half = duration / 2;
+                let mut first = slot.copy();
+                let first_mut = first.make_mut();
+                first_mut.set_pts(pts);
+                first_mut.set_dts(pts);
+                first_mut.set_duration(half);
+                let mut second = slot.copy();
+                let second_mut = second.make_mut();
+                second_mut.set_pts(pts + half);
+                second_mut.set_dts(pts + half);
+                second_mut.set_duration(duration - half);
+                [first, second]
+            }
+            _ => [
+                stamp(state, policy, slot.clone(), slot),
+                stamp(state, policy, slot.copy(), slot),
+            ],
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const FRAME: gst::ClockTime = gst::ClockTime::from_mseconds(40);
+
+        /// Push `frames` 40ms frames through a `frameImpair` with the given
+        /// properties, and return the offset and PTS in ms of each buffer out,
+        /// and the actions posted on the bus.
+        fn run_plugin_tests(
+            properties: &[(&str, &dyn ToValue)],
+            frames: u64,
+        ) -> (Vec<(u64, u64)>, Vec<gst::Structure>) {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::FrameImpair>();
+            for (name, value) in properties {
+                element.set_property_from_value(name, &value.to_value());
+            }
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+
+            let plugin = element.imp();
+            let mut out = vec![];
+            for frame in 0..frames {
+                let mut buffer = gst::Buffer::with_size(1).unwrap();
+                let buffer_mut = buffer.get_mut().unwrap();
+                buffer_mut.set_offset(frame);
+                buffer_mut.set_pts(FRAME * frame);
+                buffer_mut.set_duration(FRAME);
+                plugin.submit_input_buffer(false, buffer).unwrap();
+                while let Ok(GenerateOutputSuccess::Buffer(buffer)) = plugin.generate_output() {
+                    out.push((buffer.offset(), buffer.pts().unwrap().mseconds()));
+                }
+            }
+
+            let mut actions = vec![];
+            while let Some(message) = bus.pop_filtered(&[gst::MessageType::Element]) {
+                actions.push(message.structure().unwrap().to_owned());
+            }
+            (out, actions)
+        }
+
+        fn action_offsets(actions: &[gst::Structure]) -> Vec<(String, u64)> {
+            actions
+                .iter()
+                .map(|s| (s.get::<String>("action").unwrap(), s.get::<u64>("offset").unwrap()))
+                .collect()
+        }
+
+        #[test]
+        fn test_drop_frames() {
+            let (out, actions) = run_plugin_tests(&[("drop-frames", &"1, 3")], 5);
+            assert_eq!(out, [(0, 0), (2, 80), (4, 160)]);
+            assert_eq!(
+                action_offsets(&actions),
+                [("drop".to_string(), 1), ("drop".to_string(), 3)]
+            );
+        }
+
+        #[test]
+        fn test_duplicate_shares_slot() {
+            let (out, _) = run_plugin_tests(&[("duplicate-frames", &"1")], 3);
+            assert_eq!(out, [(0, 0), (1, 40), (1, 60), (2, 80)]);
+        }
+
+        #[test]
+        fn test_duplicate_restamp() {
+            let (out, _) = run_plugin_tests(
+                &[("duplicate-frames", &"1"), ("timestamps", &Timestamps::Restamp)],
+                3,
+            );
+            assert_eq!(out, [(0, 0), (1, 40), (1, 80), (2, 120)]);
+        }
+
+        #[test]
+        fn test_drop_restamp_closes_gap() {
+            let (out, _) = run_plugin_tests(
+                &[("drop-frames", &"0,2"), ("timestamps", &Timestamps::Restamp)],
+                4,
+            );
+            assert_eq!(out, [(1, 0), (3, 40)]);
+        }
+
+        #[test]
+        fn test_freeze() {
+            let (out, actions) =
+                run_plugin_tests(&[("freeze-frames", &"1"), ("freeze-length", &2u32)], 5);
+            assert_eq!(out, [(0, 0), (1, 40), (1, 80), (1, 120), (4, 160)]);
+            assert_eq!(
+                action_offsets(&actions),
+                [
+                    ("freeze".to_string(), 1),
+                    ("frozen".to_string(), 2),
+                    ("frozen".to_string(), 3)
+                ]
+            );
+            assert_eq!(actions[0].get::<u32>("length").unwrap(), 2);
+        }
+
+        #[test]
+        fn test_swap() {
+            let (out, actions) = run_plugin_tests(&[("swap-frames", &"1")], 4);
+            assert_eq!(out, [(0, 0), (2, 40), (1, 80), (3, 120)]);
+            assert_eq!(action_offsets(&actions), [("swap".to_string(), 1)]);
+            assert_eq!(actions[0].get::<u64>("swapped-offset").unwrap(), 2);
+
+            let (out, _) = run_plugin_tests(
+                &[("swap-frames", &"1"), ("timestamps", &Timestamps::Original)],
+                4,
+            );
+            assert_eq!(out, [(0, 0), (2, 80), (1, 40), (3, 120)]);
+        }
+
+        #[test]
+        fn test_frames_property_round_trip() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::FrameImpair>();
+            element.set_property("drop-frames", "7, 3,x,3");
+            assert_eq!(element.property::<String>("drop-frames"), "3,7");
+        }
+
+        #[test]
+        fn test_rate_is_seeded() {
+            let run = |seed: u64| {
+                run_plugin_tests(&[("drop-rate", &0.25), ("seed", &seed)], 400).0
+            };
+            let first = run(5);
+            assert_eq!(first, run(5));
+            assert_ne!(first, run(6));
+            let dropped = 400 - first.len();
+            assert!((70..130).contains(&dropped), "{dropped} dropped");
+        }
+    }
+}
@@ -0,0 +1,433 @@
+//! Row kernels shared by the elements, and the scoped threads used to spread
+//! them over the rows of a frame.
+//!
+//! Every SIMD kernel has a scalar twin that it must match byte for byte. The
+//! scalar versions handle the tails that don't fill a vector, and the tests
+//! check the two against each other.
+
+use std::simd::prelude::*;
+
+/// Samples offset at once by [`offset_clamp_u8`] and [`offset_wrap_u8`].
+const OFFSET_LANES: usize = 32;
+
+/// Bytes handled at once by [`split_rgb_row`]. Three vectors of 16 bytes keep
+/// the RGB phase the same at the start of every chunk.
+const RGB_CHUNK: usize = 48;
+
+/// Byte masks keeping one of R, G or B of a chunk of packed RGB.
+const RGB_MASKS: [[u8; RGB_CHUNK]; 3] = rgb_masks();
+
+const fn rgb_masks() -> [[u8; RGB_CHUNK]; 3] {
+    let mut masks = [[0; RGB_CHUNK]; 3];
+    let mut i = 0;
+    while i < RGB_CHUNK {
+        masks[i % 3][i] = 0xff;
+        i += 1;
+    }
+    masks
+}
+
+/// Turn an `n-threads` property into a thread count. 0 means one per CPU.
+pub fn thread_count(n_threads: u32) -> usize {
+    match n_threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n as usize,
+    }
+}
+
+/// The rows of one plane for [`for_each_plane_row`]: the first `rows` rows of
+/// `data`, which start `stride` bytes apart. The last row may be cut short by
+/// the end of `data`.
+pub struct Rows<'a> {
+    pub data: &'a mut [u8],
+    pub stride: usize,
+    pub rows: usize,
+}
+
+/// The rows of an input and an output plane for [`for_each_plane_row_pair`],
+/// each with its own stride.
+pub struct RowPairs<'a> {
+    pub input: &'a [u8],
+    pub in_stride: usize,
+    pub output: &'a mut [u8],
+    pub out_stride: usize,
+    pub rows: usize,
+}
+
+/// Some contiguous rows of one plane, all handled by the same thread.
+struct Slice<'a> {
+    plane: usize,
+    first: usize,
+    rows: usize,
+    stride: usize,
+    lines: &'a mut [u8],
+}
+
+/// Like [`Slice`], with the matching rows of an input plane.
+struct PairSlice<'a> {
+    plane: usize,
+    first: usize,
+    rows: usize,
+    in_stride: usize,
+    in_lines: &'a [u8],
+    out_stride: usize,
+    out_lines: &'a mut [u8],
+}
+
+/// Call `run` with each list of slices in `work` on its own thread, all inside
+/// one scope. The first list runs on the calling thread, so a single list
+/// never starts one.
+fn run_on_threads<T: Send>(work: Vec<Vec<T>>, run: impl Fn(Vec<T>) + Sync) {
+    let mut work = work.into_iter().filter(|slices| !slices.is_empty());
+    let Some(first) = work.next() else {
+        return;
+    };
+    let run = &run;
+    std::thread::scope(|scope| {
+        for slices in work {
+            scope.spawn(move || run(slices));
+        }
+        run(first);
+    });
+}
+
+/// Call `f` with the plane, index and bytes of every row of every plane.
+///
+/// Each plane is split into `n_threads` contiguous slices, and each thread
+/// takes one slice of every plane, so a whole frame costs at most
+/// `n_threads - 1` new threads however many planes it has. `f` must not care
+/// which order the rows come in.
+pub fn for_each_plane_row<F>(planes: Vec<Rows<'_>>, n_threads: usize, f: F)
+where
+    F: Fn(usize, usize, &mut [u8]) + Sync,
+{
+    let n_threads = n_threads.max(1);
+    let mut work = (0..n_threads).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (plane, Rows { data, stride, rows }) in planes.into_iter().enumerate() {
+        let rows = rows.min(data.len().div_ceil(stride));
+        let slice_rows = rows.div_ceil(n_threads).max(1);
+        for (slice, lines) in data.chunks_mut(slice_rows * stride).enumerate() {
+            let first = slice * slice_rows;
+            if first >= rows {
+                break;
+            }
+            work[slice].push(Slice {
+                plane,
+                first,
+                rows: rows - first,
+                stride,
+                lines,
+            });
+        }
+    }
+
+    run_on_threads(work, |slices| {
+        for slice in slices {
+            let lines = slice.lines.chunks_mut(slice.stride).take(slice.rows);
+            for (row, line) in lines.enumerate() {
+                f(slice.plane, slice.first + row, line);
+            }
+        }
+    });
+}
+
+/// Like [`for_each_plane_row`], but walks the rows of an input and an output
+/// plane together.
+pub fn for_each_plane_row_pair<F>(planes: Vec<RowPairs<'_>>, n_threads: usize, f: F)
+where
+    F: Fn(usize, usize, &[u8], &mut [u8]) + Sync,
+{
+    let n_threads = n_threads.max(1);
+    let mut work = (0..n_threads).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (plane, pair) in planes.into_iter().enumerate() {
+        let rows = pair
+            .rows
+            .min(pair.input.len().div_ceil(pair.in_stride))
+            .min(pair.output.len().div_ceil(pair.out_stride));
+        let slice_rows = rows.div_ceil(n_threads).max(1);
+        for (slice, (in_lines, out_lines)) in pair
+            .input
+            .chunks(slice_rows * pair.in_stride)
+            .zip(pair.output.chunks_mut(slice_rows * pair.out_stride))
+            .enumerate()
+        {
+            let first = slice * slice_rows;
+            if first >= rows {
+                break;
+            }
+            work[slice].push(PairSlice {
+                plane,
+                first,
+                rows: rows - first,
+                in_stride: pair.in_stride,
+                in_lines,
+                out_stride: pair.out_stride,
+                out_lines,
+            });
+        }
+    }
+
+    run_on_threads(work, |slices| {
+        for slice in slices {
+            let lines = slice
+                .in_lines
+                .chunks(slice.in_stride)
+                .zip(slice.out_lines.chunks_mut(slice.out_stride))
+                .take(slice.rows);
+            for (row, (in_line, out_line)) in lines.enumerate() {
+                f(slice.plane, slice.first + row, in_line, out_line);
+            }
+        }
+    });
+}
+
+/// [`for_each_plane_row`] for a single plane.
+pub fn for_each_row<F>(data: &mut [u8], stride: usize, rows: usize, n_threads: usize, f: F)
+where
+    F: Fn(usize, &mut [u8]) + Sync,
+{
+    let plane = Rows { data, stride, rows };
+    for_each_plane_row(vec![plane], n_threads, |_, row, line| f(row, line));
+}
+
+/// [`for_each_plane_row_pair`] for a single plane.
+pub fn for_each_row_pair<F>(
+    input: &[u8],
+    in_stride: usize,
+    output: &mut [u8],
+    out_stride: usize,
+    rows: usize,
+    n_threads: usize,
+    f: F,
+) where
+    F: Fn(usize, &[u8], &mut [u8]) + Sync,
+{
+    let plane = RowPairs {
+        input,
+        in_stride,
+        output,
+        out_stride,
+        rows,
+    };
+    for_each_plane_row_pair(vec![plane], n_threads, |_, row, in_line, out_line| {
+        f(row, in_line, out_line)
+    });
+}
+
+/// Replace every sample of a run of 8-bit samples with its entry in `lut`, which
+/// must have 256 entries. There is no SIMD version: without AVX2 a gather is
+/// slower than looking the samples up one at a time.
+pub fn lut_u8(samples: &mut [u8], lut: &[u16]) {
+    for sample in samples {
+        *sample = lut[*sample as usize] as u8;
+    }
+}
+
+/// Add `offset` to every sample of a run of contiguous 8-bit samples, clamping
+/// the result to `lo..=hi`.
+pub fn offset_clamp_u8(samples: &mut [u8], offset: i16, lo: u8, hi: u8) {
+    let offset_v = Simd::<i16, OFFSET_LANES>::splat(offset);
+    let lo_v = Simd::splat(lo as i16);
+    let hi_v = Simd::splat(hi as i16);
+    let mut chunks = samples.chunks_exact_mut(OFFSET_LANES);
+    for chunk in &mut chunks {
+        let value = Simd::<u8, OFFSET_LANES>::from_slice(chunk).cast::<i16>() + offset_v;
+        value.simd_clamp(lo_v, hi_v).cast::<u8>().copy_to_slice(chunk);
+    }
+    offset_clamp_u8_scalar(chunks.into_remainder(), offset, lo, hi);
+}
+
+pub fn offset_clamp_u8_scalar(samples: &mut [u8], offset: i16, lo: u8, hi: u8) {
+    for sample in samples {
+        *sample = (*sample as i16 + offset).clamp(lo as i16, hi as i16) as u8;
+    }
+}
+
+/// Add `offset` to every sample of a run of contiguous 8-bit samples, wrapping
+/// around modulo 256.
+pub fn offset_wrap_u8(samples: &mut [u8], offset: u8) {
+    let offset_v = Simd::<u8, OFFSET_LANES>::splat(offset);
+    let mut chunks = samples.chunks_exact_mut(OFFSET_LANES);
+    for chunk in &mut chunks {
+        (Simd::<u8, OFFSET_LANES>::from_slice(chunk) + offset_v).copy_to_slice(chunk);
+    }
+    offset_wrap_u8_scalar(chunks.into_remainder(), offset);
+}
+
+pub fn offset_wrap_u8_scalar(samples: &mut [u8], offset: u8) {
+    for sample in samples {
+        *sample = sample.wrapping_add(offset);
+    }
+}
+
+/// Spread a row of packed RGB across three rows of the same length laid end to
+/// end in `out_line`: the first keeps only R, the second G and the third B.
+/// Anything in `out_line` past those is zeroed.
+pub fn split_rgb_row(in_line: &[u8], out_line: &mut [u8]) {
+    let len = in_line.len();
+    for (channel, out) in out_line[..3 * len].chunks_exact_mut(len.max(1)).enumerate() {
+        let mask = &RGB_MASKS[channel];
+        let mut in_chunks = in_line.chunks_exact(RGB_CHUNK);
+        let mut out_chunks = out.chunks_exact_mut(RGB_CHUNK);
+        for (in_chunk, out_chunk) in (&mut in_chunks).zip(&mut out_chunks) {
+            for i in (0..RGB_CHUNK).step_by(16) {
+                let kept = u8x16::from_slice(&in_chunk[i..]) & u8x16::from_slice(&mask[i..]);
+                kept.copy_to_slice(&mut out_chunk[i..]);
+            }
+        }
+        // Every chunk is a multiple of three bytes long, so the tail starts on R.
+        for (i, (input, output)) in in_chunks
+            .remainder()
+            .iter()
+            .zip(out_chunks.into_remainder())
+            .enumerate()
+        {
+            *output = if i % 3 == channel { *input } else { 0 };
+        }
+    }
+    out_line[3 * len..].fill(0);
+}
+
+/// The pixel at a time version of [`split_rgb_row`] that it is checked against.
+#[cfg(test)]
+pub fn split_rgb_row_scalar(in_line: &[u8], out_line: &mut [u8]) {
+    let width = in_line.len() / 3;
+    out_line.fill(0);
+    for (pix_idx, in_p) in in_line.chunks_exact(3).enumerate() {
+        out_line[3 * pix_idx] = in_p[0];
+        out_line[3 * width + 3 * pix_idx + 1] = in_p[1];
+        out_line[6 * width + 3 * pix_idx + 2] = in_p[2];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic bytes that hit every value and don't line up with the lanes.
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 97 + 13) as u8).collect()
+    }
+
+    #[test]
+    fn test_offset_clamp_u8_matches_scalar() {
+        for (offset, lo, hi) in [(20, 16, 235), (-20, 16, 240), (255, 0, 255), (-255, 0, 255)] {
+            for len in 0..(4 * OFFSET_LANES + 5) {
+                let mut simd = pattern(len);
+                let mut scalar = simd.clone();
+                offset_clamp_u8(&mut simd, offset, lo, hi);
+                offset_clamp_u8_scalar(&mut scalar, offset, lo, hi);
+                assert_eq!(simd, scalar, "offset {offset}, length {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_offset_wrap_u8_matches_scalar() {
+        for offset in [0, 1, 20, 236, 255] {
+            for len in 0..(4 * OFFSET_LANES + 5) {
+                let mut simd = pattern(len);
+                let mut scalar = simd.clone();
+                offset_wrap_u8(&mut simd, offset);
+                offset_wrap_u8_scalar(&mut scalar, offset);
+                assert_eq!(simd, scalar, "offset {offset}, length {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_rgb_row_matches_scalar() {
+        for width in 0..(3 * RGB_CHUNK) {
+            let in_line = pattern(3 * width);
+            // Leave some padding after the three rows to check it gets cleared.
+            let mut simd = vec![0xaa; 9 * width + 7];
+            let mut scalar = simd.clone();
+            split_rgb_row(&in_line, &mut simd);
+            split_rgb_row_scalar(&in_line, &mut scalar);
+            assert_eq!(simd, scalar, "width {width}");
+        }
+    }
+
+    #[test]
+    fn test_for_each_row_visits_every_row_once() {
+        const STRIDE: usize = 5;
+        for rows in 0..20 {
+            for n_threads in 1..8 {
+                // Anything after the last row asked for is left alone.
+                let mut data = vec![0; rows * STRIDE + 2];
+                for_each_row(&mut data, STRIDE, rows, n_threads, |row, line| {
+                    for byte in line {
+                        *byte += row as u8 + 1;
+                    }
+                });
+                let expected = (0..rows)
+                    .flat_map(|row| [row as u8 + 1; STRIDE])
+                    .chain([0, 0])
+                    .collect::<Vec<_>>();
+                assert_eq!(data, expected, "{rows} rows on {n_threads} threads");
+            }
+        }
+    }
+
+    #[test]
+    fn test_for_each_row_short_last_row() {
+        for n_threads in 1..8 {
+            let mut data = vec![0; 3 * 5 + 2];
+            for_each_row(&mut data, 5, 10, n_threads, |row, line| {
+                line.fill(row as u8 + 1);
+            });
+            assert_eq!(data, [1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 4, 4]);
+        }
+    }
+
+    #[test]
+    fn test_for_each_plane_row_visits_every_plane() {
+        for n_threads in 1..8 {
+            let mut luma = vec![0; 6 * 4];
+            let mut chroma = vec![0; 3 * 2];
+            let planes = vec![
+                Rows {
+                    data: &mut luma,
+                    stride: 6,
+                    rows: 4,
+                },
+                Rows {
+                    data: &mut chroma,
+                    stride: 3,
+                    rows: 2,
+                },
+            ];
+            for_each_plane_row(planes, n_threads, |plane, row, line| {
+                line.fill(10 * plane as u8 + row as u8);
+            });
+            let expected = (0..4).flat_map(|row| [row; 6]).collect::<Vec<_>>();
+            assert_eq!(luma, expected, "{n_threads} threads");
+            assert_eq!(chroma, [10, 10, 10, 11, 11, 11], "{n_threads} threads");
+        }
+    }
+
+    #[test]
+    fn test_for_each_row_pair_matches_single_thread() {
+        let input = pattern(7 * 33);
+        let run = |n_threads| {
+            let mut output = vec![0; 9 * 30];
+            for_each_row_pair(
+                &input,
+                7,
+                &mut output,
+                9,
+                usize::MAX,
+                n_threads,
+                |row, in_line, out_line| {
+                    out_line[..7].copy_from_slice(in_line);
+                    out_line[7] = row as u8;
+                },
+            );
+            output
+        };
+        let expected = run(1);
+        for n_threads in 2..12 {
+            assert_eq!(run(n_threads), expected, "{n_threads} threads");
+        }
+    }
+}
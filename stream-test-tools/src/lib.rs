@@ -1,16 +1,50 @@
 #![feature(test)]
 #![feature(try_find)]
 #![feature(iter_advance_by)]
+#![feature(portable_simd)]
 extern crate test;
 
 use gst::glib;
 
+mod kernels;
+mod ssim;
+mod scope;
 mod yuv_offset;
 mod plane_expand;
+mod rgb_offset;
+mod noise_inject;
+mod plane_shift;
+mod frame_impair;
+mod timestamp_impair;
+mod block_artifact;
+mod blur_impair;
+mod block_corrupt;
+mod bit_reduce;
+mod color_mismatch;
+mod quality_compare;
+mod diff_heatmap;
+mod plane_stats;
+mod waveform;
+mod vectorscope;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     yuv_offset::register(plugin)?;
     plane_expand::register(plugin)?;
+    rgb_offset::register(plugin)?;
+    noise_inject::register(plugin)?;
+    plane_shift::register(plugin)?;
+    frame_impair::register(plugin)?;
+    timestamp_impair::register(plugin)?;
+    block_artifact::register(plugin)?;
+    blur_impair::register(plugin)?;
+    block_corrupt::register(plugin)?;
+    bit_reduce::register(plugin)?;
+    color_mismatch::register(plugin)?;
+    quality_compare::register(plugin)?;
+    diff_heatmap::register(plugin)?;
+    plane_stats::register(plugin)?;
+    waveform::register(plugin)?;
+    vectorscope::register(plugin)?;
     Ok(())
 }
 
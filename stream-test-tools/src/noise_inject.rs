@@ -0,0 +1,544 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct NoiseInject(ObjectSubclass<imp::NoiseInject>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstNoiseInjectDistribution")]
+pub enum Distribution {
+    #[enum_value(name = "Gaussian noise, with the strength as its sigma", nick = "gaussian")]
+    Gaussian = 0,
+    #[enum_value(
+        name = "Uniform noise in [-strength, strength], with variance strength^2/3",
+        nick = "uniform"
+    )]
+    Uniform = 1,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstNoiseInjectMode")]
+pub enum Mode {
+    #[enum_value(name = "The same noise on every frame", nick = "static")]
+    Static = 0,
+    #[enum_value(
+        name = "New noise on every frame, seeded from the buffer offset or PTS",
+        nick = "temporal"
+    )]
+    Temporal = 1,
+}
+
+/// SplitMix64. It is small and fast, and is written out here so that the noise
+/// for a given seed can never change underneath a test suite. The other
+/// elements that make seeded decisions use it too.
+pub struct Rng(u64);
+
+impl Rng {
+    /// A generator for one component of one frame, or for any other independent
+    /// stream of decisions about it.
+    pub fn new(seed: u64, frame: u64, comp: usize) -> Self {
+        let mut rng = Self(
+            seed ^ frame.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+                ^ (comp as u64).wrapping_mul(0xd1b5_4a32_d192_ed03),
+        );
+        rng.next_u64();
+        rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn sample(&mut self, distribution: Distribution, strength: f64) -> f64 {
+        match distribution {
+            Distribution::Gaussian => {
+                // Box-Muller, keeping u1 away from 0 so the log is finite.
+                let u1 = 1.0 - self.next_f64();
+                let u2 = self.next_f64();
+                strength * (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+            }
+            Distribution::Uniform => strength * (2.0 * self.next_f64() - 1.0),
+        }
+    }
+}
+
+/// The number that a frame's seeded decisions are keyed by: the buffer offset
+/// if upstream sets one, otherwise the PTS. Either way a seek back to the same
+/// frame makes the same decisions.
+pub fn frame_key(buffer: &gst::BufferRef) -> u64 {
+    if buffer.offset() != gst::BUFFER_OFFSET_NONE {
+        buffer.offset()
+    } else {
+        buffer.pts().map_or(0, |pts| pts.nseconds())
+    }
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "noiseInject",
+        gst::Rank::NONE,
+        NoiseInject::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::{frame_key, Distribution, Mode, Rng};
+    use crate::yuv_offset;
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "noiseInject",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Noise Inject"),
+        )
+    });
+
+    const DEFAULT_STRENGTH: f64 = 0.0;
+    const DEFAULT_SEED: u64 = 0;
+    const DEFAULT_DISTRIBUTION: Distribution = Distribution::Gaussian;
+    const DEFAULT_MODE: Mode = Mode::Temporal;
+
+    /// The planar and semi-planar formats of `yuv_offset::FORMATS`.
+    fn formats() -> Vec<VideoFormat> {
+        yuv_offset::formats_where(|info| info.n_planes() > 1)
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        y: f64,
+        u: f64,
+        v: f64,
+        distribution: Distribution,
+        seed: u64,
+        mode: Mode,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                y: DEFAULT_STRENGTH,
+                u: DEFAULT_STRENGTH,
+                v: DEFAULT_STRENGTH,
+                distribution: DEFAULT_DISTRIBUTION,
+                seed: DEFAULT_SEED,
+                mode: DEFAULT_MODE,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct NoiseInject {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for NoiseInject {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecDouble::builder("y")
+                        .nick("y")
+                        .blurb("Sigma or amplitude of the noise added to the Y plane, in code values")
+                        .minimum(0.0)
+                        .maximum(65535.0)
+                        .default_value(DEFAULT_STRENGTH)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecDouble::builder("u")
+                        .nick("u")
+                        .blurb("Sigma or amplitude of the noise added to the U plane, in code values")
+                        .minimum(0.0)
+                        .maximum(65535.0)
+                        .default_value(DEFAULT_STRENGTH)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecDouble::builder("v")
+                        .nick("v")
+                        .blurb("Sigma or amplitude of the noise added to the V plane, in code values")
+                        .minimum(0.0)
+                        .maximum(65535.0)
+                        .default_value(DEFAULT_STRENGTH)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("distribution", DEFAULT_DISTRIBUTION)
+                        .nick("distribution")
+                        .blurb("Distribution the noise is drawn from")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt64::builder("seed")
+                        .nick("seed")
+                        .blurb("Seed for the noise generator")
+                        .default_value(DEFAULT_SEED)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("mode", DEFAULT_MODE)
+                        .nick("mode")
+                        .blurb("Whether the noise stays the same or changes from frame to frame")
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "y" => settings.y = value.get().expect("type checked upstream"),
+                "u" => settings.u = value.get().expect("type checked upstream"),
+                "v" => settings.v = value.get().expect("type checked upstream"),
+                "distribution" => settings.distribution = value.get().expect("type checked upstream"),
+                "seed" => settings.seed = value.get().expect("type checked upstream"),
+                "mode" => settings.mode = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "y" => settings.y.to_value(),
+                "u" => settings.u.to_value(),
+                "v" => settings.v.to_value(),
+                "distribution" => settings.distribution.to_value(),
+                "seed" => settings.seed.to_value(),
+                "mode" => settings.mode.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for NoiseInject {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for NoiseInject {
+        const NAME: &'static str = "noiseInject";
+        type Type = super::NoiseInject;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for NoiseInject {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::NeverInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = true;
+    }
+
+    impl ElementImpl for NoiseInject {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Noise Injection Tool",
+                    "Filter/Effect/Video",
+                    "Add deterministic Gaussian or uniform noise to test tools for analysing videos.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(formats())
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(formats())
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for NoiseInject {
+        fn transform_frame(
+            &self,
+            in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+            out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let strengths = [settings.y, settings.u, settings.v];
+            let frame = match settings.mode {
+                Mode::Static => 0,
+                Mode::Temporal => frame_key(in_frame.buffer()),
+            };
+
+            let format_info = in_frame.format_info();
+            let depth = format_info.depth()[0];
+            let max = ((1 << depth) - 1) as f64;
+            let little_endian = format_info.is_le();
+
+            for plane in 0..in_frame.n_planes() {
+                let in_stride = in_frame.plane_stride()[plane as usize] as usize;
+                let out_stride = out_frame.plane_stride()[plane as usize] as usize;
+                let in_plane = in_frame.plane_data(plane).unwrap();
+                let out_plane = out_frame.plane_data_mut(plane).unwrap();
+                let len = in_plane.len().min(out_plane.len());
+                out_plane[..len].copy_from_slice(&in_plane[..len]);
+
+                for comp in 0..format_info.n_components() as usize {
+                    if format_info.plane()[comp] != plane || strengths[comp] == 0.0 {
+                        continue;
+                    }
+                    let poffset = format_info.poffset()[comp] as usize;
+                    let pixel_stride = format_info.pixel_stride()[comp] as usize;
+                    let shift = format_info.shift()[comp];
+                    let width = in_frame.comp_width(comp as u8) as usize;
+                    let height = in_frame.comp_height(comp as u8) as usize;
+                    let mut rng = Rng::new(settings.seed, frame, comp);
+                    let mut noisy = |value: u16| {
+                        let noise = rng.sample(settings.distribution, strengths[comp]);
+                        (value as f64 + noise).round().clamp(0.0, max) as u16
+                    };
+
+                    for row in 0..height {
+                        let in_line = &in_plane[row * in_stride..];
+                        let out_line = &mut out_plane[row * out_stride..];
+                        for x in 0..width {
+                            let i = poffset + x * pixel_stride;
+                            if depth > 8 {
+                                let bytes = [in_line[i], in_line[i + 1]];
+                                let raw = if little_endian {
+                                    u16::from_le_bytes(bytes)
+                                } else {
+                                    u16::from_be_bytes(bytes)
+                                };
+                                let out = noisy(raw >> shift) << shift;
+                                let bytes = if little_endian {
+                                    out.to_le_bytes()
+                                } else {
+                                    out.to_be_bytes()
+                                };
+                                out_line[i..i + 2].copy_from_slice(&bytes);
+                            } else {
+                                out_line[i] = noisy(in_line[i] as u16) as u8;
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use test::Bencher;
+
+        fn run_plugin_tests(
+            settings: Settings,
+            format: VideoFormat,
+            fill: u8,
+            offset: u64,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let _ = gst::init();
+
+            let plugin = NoiseInject {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(format, 64, 64)
+                .build()
+                .unwrap();
+            let mut buffer_inframe = gst::Buffer::from_slice(vec![fill; info.size()]);
+            buffer_inframe.get_mut().unwrap().set_offset(offset);
+            let inframe = gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap();
+
+            let buffer_outframe = gst::Buffer::from_slice(vec![0; info.size()]);
+            let mut outframe = gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap();
+            plugin
+                .transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+                .unwrap();
+            outframe
+        }
+
+        /// Mean and variance of the luma plane of a 64x64 8-bit frame.
+        fn luma_stats(outframe: &gst_video::VideoFrame<gst_video::video_frame::Writable>) -> (f64, f64) {
+            let luma = &outframe.plane_data(0).unwrap()[..64 * 64];
+            let n = luma.len() as f64;
+            let mean = luma.iter().map(|&x| x as f64).sum::<f64>() / n;
+            let variance = luma.iter().map(|&x| (x as f64 - mean).powi(2)).sum::<f64>() / n;
+            (mean, variance)
+        }
+
+        #[test]
+        fn test_formats_leave_out_packed() {
+            let _ = gst::init();
+            let formats = formats();
+            assert_eq!(formats.len(), yuv_offset::FORMATS.len() - 3);
+            for format in [VideoFormat::Yuy2, VideoFormat::Uyvy, VideoFormat::V210] {
+                assert!(!formats.contains(&format), "{format:?}");
+            }
+        }
+
+        #[test]
+        fn test_gaussian_variance() {
+            let settings = Settings {
+                y: 4.0,
+                ..Default::default()
+            };
+            let outframe = run_plugin_tests(settings, VideoFormat::I420, 128, 0);
+            let (mean, variance) = luma_stats(&outframe);
+            assert!((mean - 128.0).abs() < 0.5, "mean {mean}");
+            // Rounding to whole code values adds another 1/12 to the variance.
+            assert!((variance - (16.0 + 1.0 / 12.0)).abs() < 1.6, "variance {variance}");
+            assert!(outframe.plane_data(1).unwrap().iter().all(|&x| x == 128));
+        }
+
+        #[test]
+        fn test_uniform_variance() {
+            let settings = Settings {
+                y: 6.0,
+                distribution: Distribution::Uniform,
+                ..Default::default()
+            };
+            let outframe = run_plugin_tests(settings, VideoFormat::I420, 128, 0);
+            let (mean, variance) = luma_stats(&outframe);
+            assert!((mean - 128.0).abs() < 0.5, "mean {mean}");
+            assert!((variance - 12.0).abs() < 1.2, "variance {variance}");
+            assert!(outframe.plane_data(0).unwrap().iter().all(|&x| (122..=134).contains(&x)));
+        }
+
+        #[test]
+        fn test_temporal_noise_is_repeatable() {
+            let settings = Settings {
+                y: 4.0,
+                u: 2.0,
+                v: 2.0,
+                seed: 1234,
+                ..Default::default()
+            };
+            let first = run_plugin_tests(settings, VideoFormat::Nv12, 128, 7);
+            let repeat = run_plugin_tests(settings, VideoFormat::Nv12, 128, 7);
+            let next = run_plugin_tests(settings, VideoFormat::Nv12, 128, 8);
+            assert_eq!(first.plane_data(0).unwrap(), repeat.plane_data(0).unwrap());
+            assert_eq!(first.plane_data(1).unwrap(), repeat.plane_data(1).unwrap());
+            assert_ne!(first.plane_data(0).unwrap(), next.plane_data(0).unwrap());
+            assert_ne!(first.plane_data(1).unwrap(), next.plane_data(1).unwrap());
+        }
+
+        #[test]
+        fn test_static_noise_ignores_offset() {
+            let settings = Settings {
+                y: 4.0,
+                mode: Mode::Static,
+                ..Default::default()
+            };
+            let first = run_plugin_tests(settings, VideoFormat::I420, 128, 7);
+            let next = run_plugin_tests(settings, VideoFormat::I420, 128, 8);
+            assert_eq!(first.plane_data(0).unwrap(), next.plane_data(0).unwrap());
+        }
+
+        #[test]
+        fn test_seed_changes_noise() {
+            let settings = Settings {
+                y: 4.0,
+                ..Default::default()
+            };
+            let other_seed = Settings { seed: 1, ..settings };
+            let first = run_plugin_tests(settings, VideoFormat::I420, 128, 0);
+            let other = run_plugin_tests(other_seed, VideoFormat::I420, 128, 0);
+            assert_ne!(first.plane_data(0).unwrap(), other.plane_data(0).unwrap());
+        }
+
+        #[test]
+        fn test_noise_clips_at_format_max() {
+            let settings = Settings {
+                y: 100.0,
+                ..Default::default()
+            };
+            // 0xff in every byte of a 10-bit frame is already above the 10-bit max.
+            let outframe = run_plugin_tests(settings, VideoFormat::I42010le, 0xff, 0);
+            assert!(outframe.plane_data(0).unwrap().chunks_exact(2).all(|x| {
+                u16::from_le_bytes([x[0], x[1]]) <= 1023
+            }));
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let _ = gst::init();
+            let settings = test::black_box(Settings {
+                y: 4.0,
+                u: 4.0,
+                v: 4.0,
+                ..Default::default()
+            });
+
+            let plugin = NoiseInject {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, width, height)
+                .build()
+                .unwrap();
+            let data_inframe = vec![0; info.size()];
+            let buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            let inframe = test::black_box(gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap());
+
+            let data_outframe = vec![0; info.size()];
+            let buffer_outframe = gst::Buffer::from_slice(data_outframe);
+            let mut outframe = test::black_box(gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap());
+
+            b.iter(|| {
+                plugin.transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+            });
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
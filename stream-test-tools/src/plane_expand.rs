@@ -17,11 +17,16 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
 mod imp {
 
     use gst::glib;
+    use gst::prelude::*;
     use gst_video::subclass::prelude::*;
     use gst_video::{VideoFormat, VideoFrameExt};
 
+    use std::sync::Mutex;
+
     use once_cell::sync::Lazy;
 
+    use crate::kernels;
+
     #[allow(dead_code)]
     static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
         gst::DebugCategory::new(
@@ -31,12 +36,61 @@ mod imp {
         )
     });
 
+    const DEFAULT_N_THREADS: u32 = 1;
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        n_threads: u32,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                n_threads: DEFAULT_N_THREADS,
+            }
+        }
+    }
 
-    #[allow(dead_code)]
     #[derive(Default)]
-    pub struct PlaneExpand { }
+    pub struct PlaneExpand {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for PlaneExpand {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![glib::ParamSpecUInt::builder("n-threads")
+                    .nick("n-threads")
+                    .blurb("Number of threads to split each frame across, or 0 for one per CPU")
+                    .default_value(DEFAULT_N_THREADS)
+                    .mutable_playing()
+                    .build()]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            match pspec.name() {
+                "n-threads" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let n_threads = value.get().expect("type checked upstream");
+                    settings.n_threads = n_threads;
+                }
+                _ => unimplemented!(),
+            }
+        }
 
-    impl ObjectImpl for PlaneExpand {}
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            match pspec.name() {
+                "n-threads" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.n_threads.to_value()
+                }
+                _ => unimplemented!(),
+            }
+        }
+    }
 
     impl GstObjectImpl for PlaneExpand {}
 
@@ -143,10 +197,11 @@ mod imp {
         ) -> Result<gst::FlowSuccess, gst::FlowError> {
 
             assert_eq!(in_frame.width()*3, out_frame.width());
+            let n_threads = kernels::thread_count(self.settings.lock().unwrap().n_threads);
 
             match in_frame.n_planes() {
-                1 => single_plane_split(in_frame, out_frame),
-                3 => three_plane_split(in_frame, out_frame),
+                1 => single_plane_split(in_frame, out_frame, n_threads),
+                3 => three_plane_split(in_frame, out_frame, n_threads),
                 4 => todo!() ,
                 _ => unreachable!()
             }
@@ -154,8 +209,11 @@ mod imp {
         }
     }
 
-    fn single_plane_split(in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>, out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>) -> Result<gst::FlowSuccess, gst::FlowError> { 
-
+    fn single_plane_split(
+        in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+        out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        n_threads: usize,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
         let in_width = in_frame.width() as usize;
         let out_width = out_frame.width() as usize;
         let in_stride = in_frame.plane_stride()[0] as usize;
@@ -168,47 +226,52 @@ mod imp {
         assert!(in_line_bytes <= in_stride);
         assert!(out_line_bytes <= out_stride);
 
-        for (in_line, out_line) in in_data
-                .chunks_exact(in_stride)
-                .zip(out_data.chunks_exact_mut(out_stride))
-            {
-                out_line.fill(0);
-                for (pix_idx, in_p) in in_line[..in_line_bytes].chunks_exact(3).enumerate() {
-                    out_line[3*(pix_idx)] = in_p[0];
-                    out_line[3*in_width+3*(pix_idx)+1] = in_p[1];
-                    out_line[6*in_width+3*(pix_idx)+2] = in_p[2];
-                }
-        }
+        kernels::for_each_row_pair(
+            in_data,
+            in_stride,
+            out_data,
+            out_stride,
+            in_frame.height() as usize,
+            n_threads,
+            |_, in_line, out_line| kernels::split_rgb_row(&in_line[..in_line_bytes], out_line),
+        );
         Ok(gst::FlowSuccess::Ok)
     }
 
-    fn three_plane_split(in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>, out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>) -> Result<gst::FlowSuccess, gst::FlowError> {
+    fn three_plane_split(
+        in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+        out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        n_threads: usize,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
         let in_plane_stride = in_frame.plane_stride();
         let out_plane_stride = out_frame.plane_stride().to_vec();
-    
-        for plane in 0..3 {
-    
-            let offset = match plane  {
-                0 => 0,
-                1 => in_frame.comp_width(1).try_into().unwrap(),
-                2 => (in_frame.comp_width(1)+in_frame.comp_width(2)).try_into().unwrap(),
-                _ => unreachable!()
-            };
-    
-            let in_plane = in_frame.plane_data(plane).unwrap();
-            let in_lines = in_plane.chunks_exact(in_plane_stride[plane as usize] as usize);
-    
-            let out_plane = out_frame.plane_data_mut(plane).unwrap();
-            let out_lines = out_plane.chunks_exact_mut(out_plane_stride[plane as usize] as usize);
-    
-            for (in_line, out_line) in std::iter::zip(in_lines,out_lines) {
-                out_line.fill(125);
-                out_line[offset..in_line.len()+offset].copy_from_slice(in_line);
-            }
-        }
+        let offsets = [
+            0,
+            in_frame.comp_width(1) as usize,
+            (in_frame.comp_width(1) + in_frame.comp_width(2)) as usize,
+        ];
+
+        let planes = out_frame
+            .planes_data_mut()
+            .into_iter()
+            .take(3)
+            .enumerate()
+            .map(|(plane, output)| kernels::RowPairs {
+                input: in_frame.plane_data(plane as u32).unwrap(),
+                in_stride: in_plane_stride[plane] as usize,
+                output,
+                out_stride: out_plane_stride[plane] as usize,
+                rows: in_frame.comp_height(plane as u8) as usize,
+            })
+            .collect();
+        kernels::for_each_plane_row_pair(planes, n_threads, |plane, _, in_line, out_line| {
+            let offset = offsets[plane];
+            out_line.fill(125);
+            out_line[offset..in_line.len() + offset].copy_from_slice(in_line);
+        });
         Ok(gst::FlowSuccess::Ok)
     }
-    
+
     #[cfg(test)]
     mod tests {
         use super::*;
@@ -242,7 +305,7 @@ mod imp {
             const WIDTH: u32 = 1;
             const HEIGHT: u32 = 2;
         
-            let plugin = PlaneExpand {};
+            let plugin = PlaneExpand::default();
         
             let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::Rgb, WIDTH, HEIGHT)
                 .build()
@@ -263,10 +326,12 @@ mod imp {
             outframe
         }
         
-        fn setup_benchmarks_rgb(width: u32, height: u32, b: &mut Bencher) {
+        fn setup_benchmarks_rgb(width: u32, height: u32, n_threads: u32, b: &mut Bencher) {
             let _ = gst::init();
            
-            let plugin = PlaneExpand {};
+            let plugin = PlaneExpand {
+                settings: Mutex::new(Settings { n_threads }),
+            };
 
             let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::Rgb, width, height)
                 .build()
@@ -291,10 +356,12 @@ mod imp {
         }
 
 
-        fn setup_benchmarks_yuv(width: u32, height: u32, b: &mut Bencher) {
+        fn setup_benchmarks_yuv(width: u32, height: u32, n_threads: u32, b: &mut Bencher) {
             let _ = gst::init();
            
-            let plugin = PlaneExpand {};
+            let plugin = PlaneExpand {
+                settings: Mutex::new(Settings { n_threads }),
+            };
 
             let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::I420, width, height)
                 .build()
@@ -318,34 +385,111 @@ mod imp {
             });
         }
 
+        /// Split an awkwardly sized frame of deterministic noise on `n_threads` threads.
+        fn run_plugin_noise_tests(
+            format: VideoFormat,
+            n_threads: u32,
+        ) -> (
+            gst_video::VideoFrame<gst_video::video_frame::Readable>,
+            gst_video::VideoFrame<gst_video::video_frame::Writable>,
+        ) {
+            let _ = gst::init();
+
+            let plugin = PlaneExpand {
+                settings: Mutex::new(Settings { n_threads }),
+            };
+
+            let info = gst_video::VideoInfo::builder(format, 67, 41).build().unwrap();
+            let data_inframe = (0..info.size()).map(|i| (i * 97 + 13) as u8).collect::<Vec<_>>();
+            let buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            let inframe = gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap();
+
+            let info = gst_video::VideoInfo::builder(format, 67 * 3, 41).build().unwrap();
+            let data_outframe = vec![0xaa; info.size()];
+            let buffer_outframe = gst::Buffer::from_slice(data_outframe);
+            let mut outframe =
+                gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap();
+            plugin
+                .transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+                .unwrap();
+            (inframe, outframe)
+        }
+
+        #[test]
+        fn test_rgb_simd_matches_scalar() {
+            let (inframe, outframe) = run_plugin_noise_tests(VideoFormat::Rgb, 1);
+            let in_stride = inframe.plane_stride()[0] as usize;
+            let out_stride = outframe.plane_stride()[0] as usize;
+            let in_data = inframe.plane_data(0).unwrap();
+            let out_data = outframe.plane_data(0).unwrap();
+            for (in_line, out_line) in in_data
+                .chunks_exact(in_stride)
+                .zip(out_data.chunks_exact(out_stride))
+            {
+                let mut expected = vec![0xaa; out_stride];
+                kernels::split_rgb_row_scalar(&in_line[..67 * 3], &mut expected);
+                assert_eq!(out_line, expected);
+            }
+        }
+
+        #[test]
+        fn test_threads_match_single_thread() {
+            for format in [VideoFormat::Rgb, VideoFormat::I420] {
+                let (_, expected) = run_plugin_noise_tests(format, 1);
+                for n_threads in [0, 2, 5, 64] {
+                    let (_, outframe) = run_plugin_noise_tests(format, n_threads);
+                    for plane in 0..outframe.n_planes() {
+                        assert_eq!(
+                            outframe.plane_data(plane).unwrap(),
+                            expected.plane_data(plane).unwrap(),
+                            "{format:?} on {n_threads} threads"
+                        );
+                    }
+                }
+            }
+        }
+
+        #[bench]
+        fn bench_plugin_4k_yuv_threads(b: &mut Bencher) {
+            setup_benchmarks_yuv(4096, 2160, 0, b);
+        }
+
+        #[bench]
+        fn bench_plugin_4k_rgb_threads(b: &mut Bencher) {
+            setup_benchmarks_rgb(4096, 2160, 0, b);
+        }
+
         #[bench]
         fn bench_plugin_4k_yuv(b: &mut Bencher) {
-            setup_benchmarks_yuv(4096, 2160, b);
+            setup_benchmarks_yuv(4096, 2160, 1, b);
         }
 
         #[bench]
         fn bench_plugin_1080p_yuv(b: &mut Bencher) {
-            setup_benchmarks_yuv(1920, 1080, b);
+            setup_benchmarks_yuv(1920, 1080, 1, b);
         }
 
         #[bench]
         fn bench_plugin_720p_yuv(b: &mut Bencher) {
-            setup_benchmarks_yuv(1280, 720, b);
+            setup_benchmarks_yuv(1280, 720, 1, b);
         }
 
         #[bench]
         fn bench_plugin_4k_rgb(b: &mut Bencher) {
-            setup_benchmarks_rgb(4096, 2160, b);
+            setup_benchmarks_rgb(4096, 2160, 1, b);
         }
 
         #[bench]
         fn bench_plugin_1080p_rgb(b: &mut Bencher) {
-            setup_benchmarks_rgb(1920, 1080, b);
+            setup_benchmarks_rgb(1920, 1080, 1, b);
         }
 
         #[bench]
         fn bench_plugin_720p_rgb(b: &mut Bencher) {
-            setup_benchmarks_rgb(1280, 720, b);
+            setup_benchmarks_rgb(1280, 720, 1, b);
         }
 
     }
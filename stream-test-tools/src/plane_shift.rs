@@ -0,0 +1,579 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct PlaneShift(ObjectSubclass<imp::PlaneShift>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstPlaneShiftResample")]
+pub enum Resample {
+    #[enum_value(name = "Take the nearest sample", nick = "nearest")]
+    Nearest = 0,
+    #[enum_value(name = "Interpolate between the four nearest samples", nick = "bilinear")]
+    Bilinear = 1,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstPlaneShiftEdge")]
+pub enum Edge {
+    #[enum_value(name = "Repeat the samples at the edge of the plane", nick = "clamp")]
+    Clamp = 0,
+    #[enum_value(name = "Bring in the samples from the opposite edge", nick = "wrap")]
+    Wrap = 1,
+    #[enum_value(name = "Use the fill value", nick = "fill")]
+    Fill = 2,
+}
+
+impl Edge {
+    /// Where sample `index` of a row or column of `len` samples comes from, or
+    /// `None` if it should be the fill value.
+    fn resolve(self, index: isize, len: usize) -> Option<usize> {
+        let len = len as isize;
+        match self {
+            Edge::Clamp => Some(index.clamp(0, len - 1) as usize),
+            Edge::Wrap => Some(index.rem_euclid(len) as usize),
+            Edge::Fill => (0..len).contains(&index).then_some(index as usize),
+        }
+    }
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "planeShift",
+        gst::Rank::NONE,
+        PlaneShift::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_base::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::{Edge, Resample};
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "planeShift",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Plane Shift"),
+        )
+    });
+
+    const DEFAULT_SHIFT: f64 = 0.0;
+    const DEFAULT_RESAMPLE: Resample = Resample::Bilinear;
+    const DEFAULT_EDGE: Edge = Edge::Clamp;
+    const DEFAULT_FILL: u32 = 0;
+
+    /// Bilinear weights are fixed point, in 256ths of a sample.
+    const WEIGHT_ONE: u32 = 256;
+
+    const FORMATS: [VideoFormat; 2] = [VideoFormat::I420, VideoFormat::Rgb];
+
+    /// The shift properties, in the order of the components they move.
+    const SHIFT_PROPERTIES: [(&str, &str); 6] = [
+        ("plane0-dx", "Horizontal shift of plane 0 (Y, or R of packed RGB)"),
+        ("plane0-dy", "Vertical shift of plane 0 (Y, or R of packed RGB)"),
+        ("plane1-dx", "Horizontal shift of plane 1 (U, or G of packed RGB)"),
+        ("plane1-dy", "Vertical shift of plane 1 (U, or G of packed RGB)"),
+        ("plane2-dx", "Horizontal shift of plane 2 (V, or B of packed RGB)"),
+        ("plane2-dy", "Vertical shift of plane 2 (V, or B of packed RGB)"),
+    ];
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        /// `(dx, dy)` of each plane, in that plane's own samples. Positive values
+        /// move the picture right and down.
+        shifts: [(f64, f64); 3],
+        resample: Resample,
+        edge: Edge,
+        fill: u32,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                shifts: [(DEFAULT_SHIFT, DEFAULT_SHIFT); 3],
+                resample: DEFAULT_RESAMPLE,
+                edge: DEFAULT_EDGE,
+                fill: DEFAULT_FILL,
+            }
+        }
+    }
+
+    impl Settings {
+        fn is_noop(&self) -> bool {
+            self.shifts.iter().all(|&(dx, dy)| dx == 0.0 && dy == 0.0)
+        }
+    }
+
+    /// How one axis of a plane is resampled: each output sample `i` is taken
+    /// from input samples `i + offset` and `i + offset + 1`, weighted by
+    /// `WEIGHT_ONE - weight` and `weight`.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Axis {
+        offset: isize,
+        weight: u32,
+    }
+
+    impl Axis {
+        fn new(shift: f64, resample: Resample) -> Self {
+            // The output at `i` shows the input at `i - shift`.
+            let position = -shift;
+            match resample {
+                Resample::Nearest => Self {
+                    offset: (position + 0.5).floor() as isize,
+                    weight: 0,
+                },
+                Resample::Bilinear => {
+                    let offset = position.floor();
+                    let weight = ((position - offset) * WEIGHT_ONE as f64).round() as u32;
+                    // A fraction that rounds up to a whole sample is the next sample.
+                    if weight == WEIGHT_ONE {
+                        Self {
+                            offset: offset as isize + 1,
+                            weight: 0,
+                        }
+                    } else {
+                        Self {
+                            offset: offset as isize,
+                            weight,
+                        }
+                    }
+                }
+            }
+        }
+
+        /// The input index of both taps of every output sample, followed by the
+        /// second tap of the last one.
+        fn taps(&self, len: usize, edge: Edge) -> Vec<Option<usize>> {
+            (0..=len)
+                .map(|i| edge.resolve(i as isize + self.offset, len))
+                .collect()
+        }
+    }
+
+    #[derive(Default)]
+    pub struct PlaneShift {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for PlaneShift {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let noop = self.settings.lock().unwrap().is_noop();
+            self.obj().set_passthrough(noop);
+        }
+
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                let shifts = SHIFT_PROPERTIES.iter().map(|(name, blurb)| {
+                    glib::ParamSpecDouble::builder(name)
+                        .nick(name)
+                        .blurb(&format!("{blurb}, in its own samples"))
+                        .minimum(-4096.0)
+                        .maximum(4096.0)
+                        .default_value(DEFAULT_SHIFT)
+                        .mutable_playing()
+                        .build()
+                });
+                shifts
+                    .chain([
+                        glib::ParamSpecEnum::builder_with_default("resample", DEFAULT_RESAMPLE)
+                            .nick("resample")
+                            .blurb("How shifts by part of a sample are resampled")
+                            .mutable_playing()
+                            .build(),
+                        glib::ParamSpecEnum::builder_with_default("edge", DEFAULT_EDGE)
+                            .nick("edge")
+                            .blurb("What is shifted in from beyond the edges of each plane")
+                            .mutable_playing()
+                            .build(),
+                        glib::ParamSpecUInt::builder("fill")
+                            .nick("fill")
+                            .blurb("Sample value shifted in from beyond the edges, when edge is fill")
+                            .maximum(255)
+                            .default_value(DEFAULT_FILL)
+                            .mutable_playing()
+                            .build(),
+                    ])
+                    .collect()
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "resample" => settings.resample = value.get().expect("type checked upstream"),
+                "edge" => settings.edge = value.get().expect("type checked upstream"),
+                "fill" => settings.fill = value.get().expect("type checked upstream"),
+                name => {
+                    let index = SHIFT_PROPERTIES
+                        .iter()
+                        .position(|(property, _)| *property == name)
+                        .unwrap_or_else(|| unimplemented!());
+                    let shift = &mut settings.shifts[index / 2];
+                    let value = value.get().expect("type checked upstream");
+                    if index % 2 == 0 {
+                        shift.0 = value;
+                    } else {
+                        shift.1 = value;
+                    }
+                }
+            }
+            let noop = settings.is_noop();
+            drop(settings);
+            self.obj().set_passthrough(noop);
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "resample" => settings.resample.to_value(),
+                "edge" => settings.edge.to_value(),
+                "fill" => settings.fill.to_value(),
+                name => {
+                    let index = SHIFT_PROPERTIES
+                        .iter()
+                        .position(|(property, _)| *property == name)
+                        .unwrap_or_else(|| unimplemented!());
+                    let (dx, dy) = settings.shifts[index / 2];
+                    if index % 2 == 0 {
+                        dx.to_value()
+                    } else {
+                        dy.to_value()
+                    }
+                }
+            }
+        }
+    }
+
+    impl GstObjectImpl for PlaneShift {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PlaneShift {
+        const NAME: &'static str = "planeShift";
+        type Type = super::PlaneShift;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for PlaneShift {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::NeverInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+    }
+
+    impl ElementImpl for PlaneShift {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Plane Shift Tool",
+                    "Filter/Effect/Converter/Video",
+                    "Move each plane by a known amount to test chroma siting detectors.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for PlaneShift {
+        fn transform_frame(
+            &self,
+            in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+            out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let format_info = in_frame.format_info();
+
+            // Packed RGB keeps every channel in one plane, so each channel is shifted
+            // as if it were a plane of its own.
+            for comp in 0..format_info.n_components() as usize {
+                let plane = format_info.plane()[comp];
+                let poffset = format_info.poffset()[comp] as usize;
+                let pixel_stride = format_info.pixel_stride()[comp] as usize;
+                let width = in_frame.comp_width(comp as u8) as usize;
+                let height = in_frame.comp_height(comp as u8) as usize;
+
+                let (dx, dy) = settings.shifts[comp];
+                let x_axis = Axis::new(dx, settings.resample);
+                let y_axis = Axis::new(dy, settings.resample);
+                let columns = x_axis
+                    .taps(width, settings.edge)
+                    .into_iter()
+                    .map(|x| x.map(|x| poffset + x * pixel_stride))
+                    .collect::<Vec<_>>();
+                let rows = y_axis.taps(height, settings.edge);
+
+                let in_stride = in_frame.plane_stride()[plane as usize] as usize;
+                let out_stride = out_frame.plane_stride()[plane as usize] as usize;
+                let in_data = in_frame.plane_data(plane).unwrap();
+                let out_data = out_frame.plane_data_mut(plane).unwrap();
+                let in_line = |row: Option<usize>| row.map(|row| &in_data[row * in_stride..]);
+
+                for (y, out_line) in out_data.chunks_mut(out_stride).take(height).enumerate() {
+                    let above = in_line(rows[y]);
+                    let below = in_line(rows[y + 1]);
+                    let sample = |line: Option<&[u8]>, x: usize| match (line, columns[x]) {
+                        (Some(line), Some(i)) => line[i] as u32,
+                        _ => settings.fill,
+                    };
+
+                    for x in 0..width {
+                        let top = (WEIGHT_ONE - x_axis.weight) * sample(above, x)
+                            + x_axis.weight * sample(above, x + 1);
+                        let bottom = (WEIGHT_ONE - x_axis.weight) * sample(below, x)
+                            + x_axis.weight * sample(below, x + 1);
+                        let value = (WEIGHT_ONE - y_axis.weight) * top + y_axis.weight * bottom;
+                        out_line[poffset + x * pixel_stride] =
+                            ((value + WEIGHT_ONE * WEIGHT_ONE / 2) / (WEIGHT_ONE * WEIGHT_ONE)) as u8;
+                    }
+                }
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use test::Bencher;
+
+        /// The 4x2 luma used by the I420 tests.
+        const LUMA: [u8; 8] = [0, 10, 20, 30, 40, 50, 60, 70];
+
+        fn run_plugin_tests(
+            settings: Settings,
+            format: VideoFormat,
+            data_inframe: Vec<u8>,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let _ = gst::init();
+
+            const WIDTH: u32 = 4;
+            const HEIGHT: u32 = 2;
+
+            let plugin = PlaneShift {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(format, WIDTH, HEIGHT)
+                .build()
+                .unwrap();
+            assert_eq!(data_inframe.len(), info.size());
+            let buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            let inframe = gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap();
+
+            let buffer_outframe = gst::Buffer::from_slice(vec![0; info.size()]);
+            let mut outframe = gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap();
+            plugin
+                .transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+                .unwrap();
+            outframe
+        }
+
+        fn run_plugin_i420_tests(
+            settings: Settings,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            // The 2x1 chroma planes are [100, 101] and [102, 103], each padded to 4 bytes.
+            let data_inframe = LUMA.iter().copied().chain([100, 101, 0, 0, 102, 103, 0, 0]).collect();
+            run_plugin_tests(settings, VideoFormat::I420, data_inframe)
+        }
+
+        fn luma_settings(dx: f64, dy: f64, edge: Edge) -> Settings {
+            Settings {
+                shifts: [(dx, dy), (0.0, 0.0), (0.0, 0.0)],
+                edge,
+                fill: 255,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_whole_sample_clamp() {
+            let outframe = run_plugin_i420_tests(luma_settings(1.0, 0.0, Edge::Clamp));
+            assert_eq!(outframe.plane_data(0).unwrap(), [0, 0, 10, 20, 40, 40, 50, 60]);
+        }
+
+        #[test]
+        fn test_whole_sample_wrap() {
+            let outframe = run_plugin_i420_tests(luma_settings(-1.0, 1.0, Edge::Wrap));
+            assert_eq!(outframe.plane_data(0).unwrap(), [50, 60, 70, 40, 10, 20, 30, 0]);
+        }
+
+        #[test]
+        fn test_whole_sample_fill() {
+            let outframe = run_plugin_i420_tests(luma_settings(2.0, -1.0, Edge::Fill));
+            assert_eq!(outframe.plane_data(0).unwrap(), [255, 255, 40, 50, 255, 255, 255, 255]);
+        }
+
+        #[test]
+        fn test_bilinear_half_sample() {
+            let outframe = run_plugin_i420_tests(luma_settings(0.5, 0.0, Edge::Clamp));
+            assert_eq!(outframe.plane_data(0).unwrap(), [0, 5, 15, 25, 40, 45, 55, 65]);
+
+            // Half way between the rows, and a quarter of the way along.
+            let outframe = run_plugin_i420_tests(luma_settings(-0.25, 0.5, Edge::Clamp));
+            assert_eq!(outframe.plane_data(0).unwrap(), [3, 13, 23, 30, 23, 33, 43, 50]);
+        }
+
+        #[test]
+        fn test_nearest_rounds_shift() {
+            let settings = Settings {
+                resample: Resample::Nearest,
+                ..luma_settings(0.4, 0.0, Edge::Clamp)
+            };
+            let outframe = run_plugin_i420_tests(settings);
+            assert_eq!(outframe.plane_data(0).unwrap(), LUMA);
+
+            let settings = Settings {
+                resample: Resample::Nearest,
+                ..luma_settings(0.6, 0.0, Edge::Clamp)
+            };
+            let outframe = run_plugin_i420_tests(settings);
+            assert_eq!(outframe.plane_data(0).unwrap(), [0, 0, 10, 20, 40, 40, 50, 60]);
+        }
+
+        #[test]
+        fn test_chroma_only() {
+            let settings = Settings {
+                shifts: [(0.0, 0.0), (1.0, 0.0), (0.0, 0.0)],
+                ..Default::default()
+            };
+            let outframe = run_plugin_i420_tests(settings);
+            assert_eq!(outframe.plane_data(0).unwrap(), LUMA);
+            assert_eq!(outframe.plane_data(1).unwrap(), [100, 100, 0, 0]);
+            assert_eq!(outframe.plane_data(2).unwrap(), [102, 103, 0, 0]);
+        }
+
+        #[test]
+        fn test_rgb_shifts_channels_separately() {
+            let settings = Settings {
+                shifts: [(0.0, 0.0), (1.0, 0.0), (-1.0, 0.0)],
+                edge: Edge::Fill,
+                fill: 9,
+                ..Default::default()
+            };
+            // A 4x2 RGB frame has a 12 byte stride, so there is no padding.
+            let data_inframe = (0..24).collect::<Vec<u8>>();
+            let outframe = run_plugin_tests(settings, VideoFormat::Rgb, data_inframe);
+            #[rustfmt::skip]
+            let expected = [
+                0, 9, 5, 3, 1, 8, 6, 4, 11, 9, 7, 9,
+                12, 9, 17, 15, 13, 20, 18, 16, 23, 21, 19, 9,
+            ];
+            assert_eq!(outframe.plane_data(0).unwrap(), expected);
+        }
+
+        #[test]
+        fn test_axis_rounds_up_to_next_sample() {
+            let axis = Axis::new(-0.999, Resample::Bilinear);
+            assert_eq!(axis, Axis { offset: 1, weight: 0 });
+        }
+
+        #[test]
+        fn test_passthrough_follows_settings() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::PlaneShift>();
+            assert!(element.is_passthrough());
+            element.set_property("plane1-dy", 0.5);
+            assert!(!element.is_passthrough());
+            assert_eq!(element.property::<f64>("plane1-dy"), 0.5);
+            assert_eq!(element.property::<f64>("plane1-dx"), 0.0);
+            element.set_property("plane1-dy", 0.0);
+            assert!(element.is_passthrough());
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let _ = gst::init();
+            let settings = test::black_box(Settings {
+                shifts: [(0.0, 0.0), (0.5, 0.25), (-0.5, 0.25)],
+                ..Default::default()
+            });
+
+            let plugin = PlaneShift {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, width, height)
+                .build()
+                .unwrap();
+            let data_inframe = vec![0; info.size()];
+            let buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            let inframe = test::black_box(gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap());
+
+            let data_outframe = vec![0; info.size()];
+            let buffer_outframe = gst::Buffer::from_slice(data_outframe);
+            let mut outframe = test::black_box(gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap());
+
+            b.iter(|| {
+                plugin.transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+            });
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct PlaneStats(ObjectSubclass<imp::PlaneStats>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstPlaneStatsHistogram")]
+pub enum Histogram {
+    #[enum_value(name = "No histogram", nick = "none")]
+    Off = 0,
+    #[enum_value(name = "256 bins", nick = "256")]
+    Bins256 = 256,
+    #[enum_value(name = "1024 bins", nick = "1024")]
+    Bins1024 = 1024,
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "planeStats",
+        gst::Rank::NONE,
+        PlaneStats::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_base::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::VideoFrameExt;
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::Histogram;
+    use crate::yuv_offset::{self, FORMATS};
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "planeStats",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Plane Stats"),
+        )
+    });
+
+    const DEFAULT_INTERVAL: u32 = 1;
+    const DEFAULT_HISTOGRAM: Histogram = Histogram::Off;
+
+    /// Names used for each component in the messages.
+    const COMPONENT_NAMES: [&str; 3] = ["y", "u", "v"];
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        /// Post the statistics of every this many frames.
+        interval: u32,
+        histogram: Histogram,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                interval: DEFAULT_INTERVAL,
+                histogram: DEFAULT_HISTOGRAM,
+            }
+        }
+    }
+
+    /// The statistics of one component of a frame.
+    #[derive(Debug, Clone, PartialEq)]
+    struct Stats {
+        min: u32,
+        max: u32,
+        mean: f64,
+        /// Of the whole population of samples, not an estimate from a sample of it.
+        stddev: f64,
+        histogram: Vec<u32>,
+    }
+
+    impl Stats {
+        /// The statistics of `samples`, which are `depth` bits, with a histogram of
+        /// `bins` equal bins over the whole range, or none if `bins` is 0.
+        fn new(samples: &[u16], depth: u32, bins: usize) -> Self {
+            let mut histogram = vec![0; bins];
+            let (mut min, mut max) = (u16::MAX, 0);
+            let (mut sum, mut squares) = (0u64, 0u128);
+            for &sample in samples {
+                min = min.min(sample);
+                max = max.max(sample);
+                sum += sample as u64;
+                squares += (sample as u64 * sample as u64) as u128;
+                if bins > 0 {
+                    histogram[(sample as usize * bins) >> depth] += 1;
+                }
+            }
+
+            let n = samples.len().max(1) as f64;
+            let mean = sum as f64 / n;
+            Self {
+                min: min.min(max) as u32,
+                max: max as u32,
+                mean,
+                stddev: (squares as f64 / n - mean * mean).max(0.0).sqrt(),
+                histogram,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct PlaneStats {
+        settings: Mutex<Settings>,
+        /// Frames seen since the element started.
+        frames: Mutex<u64>,
+    }
+
+    impl ObjectImpl for PlaneStats {
+        fn constructed(&self) {
+            self.parent_constructed();
+            self.obj().set_passthrough(true);
+        }
+
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecUInt::builder("interval")
+                        .nick("interval")
+                        .blurb("Post the statistics of every this many frames, starting with the first")
+                        .minimum(1)
+                        .default_value(DEFAULT_INTERVAL)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("histogram", DEFAULT_HISTOGRAM)
+                        .nick("histogram")
+                        .blurb("Number of bins in the histogram of each plane, which spans the whole range of the samples")
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "interval" => settings.interval = value.get().expect("type checked upstream"),
+                "histogram" => settings.histogram = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "interval" => settings.interval.to_value(),
+                "histogram" => settings.histogram.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for PlaneStats {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PlaneStats {
+        const NAME: &'static str = "planeStats";
+        type Type = super::PlaneStats;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for PlaneStats {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = true;
+
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            *self.frames.lock().unwrap() = 0;
+            self.parent_start()
+        }
+    }
+
+    impl ElementImpl for PlaneStats {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Plane Stats Tool",
+                    "Filter/Analyzer/Video",
+                    "Post the min, max, mean, standard deviation and histogram of each plane.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for PlaneStats {
+        fn transform_frame_ip_passthrough(
+            &self,
+            frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let mut frames = self.frames.lock().unwrap();
+            let index = *frames;
+            *frames += 1;
+            drop(frames);
+            if index % settings.interval as u64 != 0 {
+                return Ok(gst::FlowSuccess::Ok);
+            }
+
+            let depth = frame.format_info().depth()[0];
+            let buffer = frame.buffer();
+            let mut structure = gst::Structure::builder("planestats")
+                .field("frame", index)
+                .field("pts", buffer.pts())
+                .field("offset", buffer.offset())
+                .field("depth", depth);
+            for (comp, name) in COMPONENT_NAMES.iter().enumerate() {
+                let samples = yuv_offset::component_samples(frame, comp);
+                let stats = Stats::new(&samples, depth, settings.histogram as usize);
+                structure = structure
+                    .field(format!("{name}-min").as_str(), stats.min)
+                    .field(format!("{name}-max").as_str(), stats.max)
+                    .field(format!("{name}-mean").as_str(), stats.mean)
+                    .field(format!("{name}-stddev").as_str(), stats.stddev);
+                if settings.histogram != Histogram::Off {
+                    structure = structure.field(
+                        format!("{name}-histogram").as_str(),
+                        gst::Array::new(stats.histogram),
+                    );
+                }
+            }
+
+            let obj = self.obj();
+            let _ = obj.post_message(
+                gst::message::Element::builder(structure.build())
+                    .src(&*obj)
+                    .build(),
+            );
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use gst_video::VideoFormat;
+        use test::Bencher;
+
+        fn new_element(settings: Settings) -> super::super::PlaneStats {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::PlaneStats>();
+            *element.imp().settings.lock().unwrap() = settings;
+            element
+        }
+
+        /// Run `frames` copies of a frame through `element` and return the messages
+        /// it posts.
+        fn run_frames(
+            element: &super::super::PlaneStats,
+            info: &gst_video::VideoInfo,
+            data: Vec<u8>,
+            frames: usize,
+        ) -> Vec<gst::Structure> {
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+            let frame =
+                gst_video::VideoFrame::from_buffer_readable(gst::Buffer::from_slice(data), info)
+                    .unwrap();
+            for _ in 0..frames {
+                element
+                    .imp()
+                    .transform_frame_ip_passthrough(&frame.as_video_frame_ref())
+                    .unwrap();
+            }
+            std::iter::from_fn(|| bus.pop_filtered(&[gst::MessageType::Element]))
+                .map(|message| message.structure().unwrap().to_owned())
+                .collect()
+        }
+
+        #[test]
+        fn test_stats() {
+            let stats = Stats::new(&[2, 4, 4, 4, 5, 5, 7, 9], 8, 0);
+            assert_eq!((stats.min, stats.max), (2, 9));
+            assert_eq!(stats.mean, 5.0);
+            assert_eq!(stats.stddev, 2.0);
+            assert!(stats.histogram.is_empty());
+        }
+
+        #[test]
+        fn test_histogram_bins() {
+            let samples = [0, 3, 4, 255];
+            assert_eq!(
+                Stats::new(&samples, 8, 256).histogram.iter().sum::<u32>(),
+                4
+            );
+            let histogram = Stats::new(&samples, 8, 1024).histogram;
+            assert_eq!(
+                (histogram[0], histogram[12], histogram[16], histogram[1020]),
+                (1, 1, 1, 1)
+            );
+
+            // 10-bit samples share the 256 bins four to a bin.
+            let histogram = Stats::new(&[0, 3, 4, 1023], 10, 256).histogram;
+            assert_eq!((histogram[0], histogram[1], histogram[255]), (2, 1, 1));
+        }
+
+        #[test]
+        fn test_messages_every_interval() {
+            let element = new_element(Settings {
+                interval: 2,
+                histogram: Histogram::Bins256,
+            });
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2)
+                .build()
+                .unwrap();
+            let mut data = vec![128; info.size()];
+            data[..8].copy_from_slice(&[16, 16, 16, 16, 235, 235, 235, 235]);
+
+            let messages = run_frames(&element, &info, data, 5);
+            assert_eq!(messages.len(), 3);
+            for (message, frame) in messages.iter().zip([0u64, 2, 4]) {
+                assert_eq!(message.name(), "planestats");
+                assert_eq!(message.get::<u64>("frame").unwrap(), frame);
+                assert_eq!(message.get::<u32>("y-min").unwrap(), 16);
+                assert_eq!(message.get::<u32>("y-max").unwrap(), 235);
+                assert_eq!(message.get::<f64>("y-mean").unwrap(), 125.5);
+                assert_eq!(message.get::<f64>("y-stddev").unwrap(), 109.5);
+                assert_eq!(message.get::<f64>("u-stddev").unwrap(), 0.0);
+                let histogram = message.get::<gst::Array>("u-histogram").unwrap();
+                assert_eq!(histogram.len(), 256);
+                assert_eq!(histogram[128].get::<u32>().unwrap(), 2);
+            }
+        }
+
+        #[test]
+        fn test_no_histogram_by_default() {
+            let element = new_element(Settings::default());
+            let info = gst_video::VideoInfo::builder(VideoFormat::Nv12, 4, 2)
+                .build()
+                .unwrap();
+            let messages = run_frames(&element, &info, vec![64; info.size()], 1);
+            assert_eq!(messages.len(), 1);
+            assert!(!messages[0].has_field("y-histogram"));
+            assert_eq!(messages[0].get::<f64>("v-mean").unwrap(), 64.0);
+        }
+
+        #[test]
+        fn test_ten_bit() {
+            let element = new_element(Settings {
+                histogram: Histogram::Bins1024,
+                ..Default::default()
+            });
+            let info = gst_video::VideoInfo::builder(VideoFormat::I42010le, 4, 2)
+                .build()
+                .unwrap();
+            let data = (0..info.size() / 2)
+                .flat_map(|_| 1000u16.to_le_bytes())
+                .collect();
+            let messages = run_frames(&element, &info, data, 1);
+            assert_eq!(messages[0].get::<u32>("depth").unwrap(), 10);
+            assert_eq!(messages[0].get::<u32>("y-max").unwrap(), 1000);
+            let histogram = messages[0].get::<gst::Array>("y-histogram").unwrap();
+            assert_eq!(histogram[1000].get::<u32>().unwrap(), 8);
+        }
+
+        #[test]
+        fn test_passthrough() {
+            let element = new_element(Settings::default());
+            assert!(element.is_passthrough());
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let element = new_element(test::black_box(Settings {
+                histogram: Histogram::Bins1024,
+                ..Default::default()
+            }));
+            // Every frame is measured and posted, but nothing reads the bus, so
+            // have it drop the messages rather than queue millions of them.
+            let bus = gst::Bus::new();
+            bus.set_flushing(true);
+            element.set_bus(Some(&bus));
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, width, height)
+                .build()
+                .unwrap();
+            let data_frame = (0..info.size())
+                .map(|i| (i * 97 + 13) as u8)
+                .collect::<Vec<_>>();
+            let frame = test::black_box(
+                gst_video::VideoFrame::from_buffer_readable(
+                    gst::Buffer::from_slice(data_frame),
+                    &info,
+                )
+                .unwrap(),
+            );
+
+            b.iter(|| {
+                element
+                    .imp()
+                    .transform_frame_ip_passthrough(&frame.as_video_frame_ref())
+            });
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
@@ -0,0 +1,985 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct QualityCompare(ObjectSubclass<imp::QualityCompare>) @extends gst_base::Aggregator, gst::Element, gst::Object;
+}
+
+/// What to do with the frames at the head of the `ref` and `dist` pads.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Pairing {
+    /// The reference frame is earlier than anything left to compare it with.
+    DropReference,
+    Compare,
+    /// There is no reference frame for the distorted one.
+    PassThrough,
+}
+
+/// Pair frames by PTS. A later reference is kept for a later distorted frame,
+/// and a missing one either hasn't arrived in time or never will.
+pub fn pairing(
+    reference: Option<Option<gst::ClockTime>>,
+    distorted: Option<gst::ClockTime>,
+) -> Pairing {
+    match reference {
+        Some(pts) if pts < distorted => Pairing::DropReference,
+        Some(pts) if pts == distorted => Pairing::Compare,
+        _ => Pairing::PassThrough,
+    }
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "qualityCompare",
+        gst::Rank::NONE,
+        QualityCompare::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_base::prelude::*;
+    use gst_base::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::{pairing, Pairing};
+    use crate::ssim;
+    use crate::yuv_offset::{self, ErrorSum, FORMATS};
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "qualityCompare",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Quality Compare"),
+        )
+    });
+
+    const DEFAULT_Y_WEIGHT: f64 = 6.0;
+    const DEFAULT_CHROMA_WEIGHT: f64 = 1.0;
+    const DEFAULT_SSIM: bool = false;
+
+    /// Names used for each component in the messages.
+    const COMPONENT_NAMES: [&str; 3] = ["y", "u", "v"];
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        /// Weights of the Y, U and V PSNRs in the combined YUV PSNR.
+        weights: [f64; 3],
+        ssim: bool,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                weights: [
+                    DEFAULT_Y_WEIGHT,
+                    DEFAULT_CHROMA_WEIGHT,
+                    DEFAULT_CHROMA_WEIGHT,
+                ],
+                ssim: DEFAULT_SSIM,
+            }
+        }
+    }
+
+    /// The negotiated video on each sink pad.
+    #[derive(Debug, Default)]
+    struct State {
+        reference: Option<gst_video::VideoInfo>,
+        distorted: Option<gst_video::VideoInfo>,
+        /// Whether the map pad has had its stream-start, and the caps and segment
+        /// it last had. The segment is the `dist` pad's, which the map follows.
+        map_started: bool,
+        map_caps: Option<gst::Caps>,
+        map_segment: Option<gst::Segment>,
+    }
+
+    /// A running mean.
+    #[derive(Debug, Default, Clone, Copy)]
+    struct Mean {
+        sum: f64,
+        count: u64,
+    }
+
+    impl Mean {
+        fn add(&mut self, value: f64) {
+            self.sum += value;
+            self.count += 1;
+        }
+
+        fn mean(&self) -> Option<f64> {
+            (self.count > 0).then(|| self.sum / self.count as f64)
+        }
+    }
+
+    /// Totals since the element started, for the summary posted at EOS.
+    #[derive(Debug, Default)]
+    struct Metrics {
+        frames: u64,
+        /// Distorted frames passed through without a reference frame at the same PTS.
+        unmatched: u64,
+        depth: u32,
+        errors: [ErrorSum; 3],
+        ssim: [Mean; 3],
+        ms_ssim: Mean,
+    }
+
+    /// SSIM of each component, where the window fits, and MS-SSIM of luma, where
+    /// all five scales do.
+    #[derive(Debug, Default)]
+    struct Similarity {
+        ssim: [Option<ssim::Ssim>; 3],
+        ms_ssim: Option<f64>,
+    }
+
+    /// The weighted mean of the component PSNRs.
+    fn weighted_psnr(errors: &[ErrorSum; 3], weights: &[f64; 3], max: f64) -> f64 {
+        let total = errors
+            .iter()
+            .zip(weights)
+            .map(|(error, weight)| weight * error.psnr(max))
+            .sum::<f64>();
+        total / weights.iter().sum::<f64>()
+    }
+
+    /// Start a message with the MSE and PSNR of each component, and the combined
+    /// YUV PSNR.
+    fn metrics_structure(
+        name: &str,
+        errors: &[ErrorSum; 3],
+        weights: &[f64; 3],
+        max: f64,
+    ) -> gst::structure::Builder {
+        let mut builder = gst::Structure::builder(name);
+        for (comp, error) in COMPONENT_NAMES.iter().zip(errors) {
+            builder = builder
+                .field(format!("{comp}-mse").as_str(), error.mse())
+                .field(format!("{comp}-psnr").as_str(), error.psnr(max));
+        }
+        builder.field("yuv-psnr", weighted_psnr(errors, weights, max))
+    }
+
+    #[derive(Default)]
+    pub struct QualityCompare {
+        settings: Mutex<Settings>,
+        state: Mutex<State>,
+        metrics: Mutex<Metrics>,
+        map_pad: Mutex<Option<gst::Pad>>,
+    }
+
+    impl ObjectImpl for QualityCompare {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let obj = self.obj();
+            for name in ["ref", "dist"] {
+                let templ = obj.pad_template(name).unwrap();
+                let pad = gst::PadBuilder::<gst_base::AggregatorPad>::from_template(&templ).build();
+                obj.add_pad(&pad).unwrap();
+            }
+        }
+
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                let weight = |name: &str, blurb: &str, default| {
+                    glib::ParamSpecDouble::builder(name)
+                        .nick(name)
+                        .blurb(blurb)
+                        .minimum(0.0)
+                        .maximum(f64::MAX)
+                        .default_value(default)
+                        .mutable_playing()
+                        .build()
+                };
+                vec![
+                    weight(
+                        "y-weight",
+                        "Weight of the Y PSNR in the YUV PSNR",
+                        DEFAULT_Y_WEIGHT,
+                    ),
+                    weight(
+                        "u-weight",
+                        "Weight of the U PSNR in the YUV PSNR",
+                        DEFAULT_CHROMA_WEIGHT,
+                    ),
+                    weight(
+                        "v-weight",
+                        "Weight of the V PSNR in the YUV PSNR",
+                        DEFAULT_CHROMA_WEIGHT,
+                    ),
+                    glib::ParamSpecBoolean::builder("ssim")
+                        .nick("ssim")
+                        .blurb("Also measure the SSIM of each plane and the MS-SSIM of luma. Always on while there is a map pad, which outputs the (width-10)x(height-10) luma SSIM map")
+                        .default_value(DEFAULT_SSIM)
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "y-weight" => settings.weights[0] = value.get().expect("type checked upstream"),
+                "u-weight" => settings.weights[1] = value.get().expect("type checked upstream"),
+                "v-weight" => settings.weights[2] = value.get().expect("type checked upstream"),
+                "ssim" => settings.ssim = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "y-weight" => settings.weights[0].to_value(),
+                "u-weight" => settings.weights[1].to_value(),
+                "v-weight" => settings.weights[2].to_value(),
+                "ssim" => settings.ssim.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for QualityCompare {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for QualityCompare {
+        const NAME: &'static str = "qualityCompare";
+        type Type = super::QualityCompare;
+        type ParentType = gst_base::Aggregator;
+    }
+
+    impl ElementImpl for QualityCompare {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Quality Compare Tool",
+                    "Filter/Analyzer/Video",
+                    "Measure the MSE, PSNR and SSIM of distorted video against a reference, and pass the distorted video on.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let sink_pad_template = |name| {
+                    gst::PadTemplate::with_gtype(
+                        name,
+                        gst::PadDirection::Sink,
+                        gst::PadPresence::Always,
+                        &caps,
+                        gst_base::AggregatorPad::static_type(),
+                    )
+                    .unwrap()
+                };
+
+                // The luma SSIM map, 10 smaller than the distorted video each way.
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format(VideoFormat::Gray8)
+                    .build();
+                let map_pad_template = gst::PadTemplate::new(
+                    "map",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Request,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![
+                    src_pad_template,
+                    map_pad_template,
+                    sink_pad_template("ref"),
+                    sink_pad_template("dist"),
+                ]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+
+        fn request_new_pad(
+            &self,
+            templ: &gst::PadTemplate,
+            name: Option<&str>,
+            caps: Option<&gst::Caps>,
+        ) -> Option<gst::Pad> {
+            if templ.name_template() != "map" {
+                return self.parent_request_new_pad(templ, name, caps);
+            }
+            let mut map_pad = self.map_pad.lock().unwrap();
+            if map_pad.is_some() {
+                gst::warning!(CAT, imp = self, "There is already a map pad");
+                return None;
+            }
+            let pad = gst::Pad::builder_from_template(templ).build();
+            pad.set_active(true).ok()?;
+            self.obj().add_pad(&pad).ok()?;
+            *map_pad = Some(pad.clone());
+            Some(pad)
+        }
+
+        fn release_pad(&self, pad: &gst::Pad) {
+            let mut map_pad = self.map_pad.lock().unwrap();
+            if map_pad.as_ref() != Some(pad) {
+                drop(map_pad);
+                return self.parent_release_pad(pad);
+            }
+            *map_pad = None;
+            drop(map_pad);
+            let mut state = self.state.lock().unwrap();
+            state.map_started = false;
+            state.map_caps = None;
+            state.map_segment = None;
+            drop(state);
+            let _ = pad.set_active(false);
+            let _ = self.obj().remove_pad(pad);
+        }
+    }
+
+    impl AggregatorImpl for QualityCompare {
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = State::default();
+            *self.metrics.lock().unwrap() = Metrics::default();
+            self.parent_start()
+        }
+
+        fn flush(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+            *self.metrics.lock().unwrap() = Metrics::default();
+            self.parent_flush()
+        }
+
+        fn sink_event(&self, pad: &gst_base::AggregatorPad, event: gst::Event) -> bool {
+            match event.view() {
+                gst::EventView::Caps(caps) => {
+                    let Ok(info) = gst_video::VideoInfo::from_caps(caps.caps()) else {
+                        return false;
+                    };
+                    let mut state = self.state.lock().unwrap();
+                    if pad.name() == "ref" {
+                        state.reference = Some(info);
+                    } else {
+                        state.distorted = Some(info);
+                        drop(state);
+                        // The distorted video's caps are the output caps.
+                        self.obj().static_pad("src").unwrap().mark_reconfigure();
+                    }
+                }
+                // The map is timed like the distorted video, so it is flushed with
+                // it. Its segment is sent again with the next map.
+                gst::EventView::FlushStart(_) if pad.name() == "dist" => {
+                    self.push_map_event(event.clone());
+                }
+                gst::EventView::FlushStop(_) if pad.name() == "dist" => {
+                    self.state.lock().unwrap().map_segment = None;
+                    self.push_map_event(event.clone());
+                }
+                _ => (),
+            }
+            self.parent_sink_event(pad, event)
+        }
+
+        fn update_src_caps(&self, caps: &gst::Caps) -> Result<gst::Caps, gst::FlowError> {
+            let distorted = self
+                .sink_pad("dist")
+                .current_caps()
+                .ok_or(gst_base::AGGREGATOR_FLOW_NEED_DATA)?;
+            if !caps.can_intersect(&distorted) {
+                return Err(gst::FlowError::NotNegotiated);
+            }
+            Ok(distorted)
+        }
+
+        fn aggregate(&self, _timeout: bool) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let reference_pad = self.sink_pad("ref");
+            let distorted_pad = self.sink_pad("dist");
+
+            let Some(distorted) = distorted_pad.peek_buffer() else {
+                if distorted_pad.is_eos() {
+                    self.post_summary();
+                    if let Some(pad) = self.map_pad.lock().unwrap().clone() {
+                        pad.push_event(gst::event::Eos::new());
+                    }
+                    return Err(gst::FlowError::Eos);
+                }
+                return Ok(gst::FlowSuccess::Ok);
+            };
+            let reference = reference_pad.peek_buffer();
+
+            match pairing(
+                reference.as_ref().map(|buffer| buffer.pts()),
+                distorted.pts(),
+            ) {
+                Pairing::DropReference => {
+                    reference_pad.drop_buffer();
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+                Pairing::Compare => {
+                    reference_pad.drop_buffer();
+                    self.compare(&reference.unwrap(), &distorted)?;
+                }
+                Pairing::PassThrough => {
+                    gst::debug!(
+                        CAT,
+                        imp = self,
+                        "No reference frame for {:?}",
+                        distorted.pts()
+                    );
+                    self.metrics.lock().unwrap().unmatched += 1;
+                }
+            }
+            distorted_pad.drop_buffer();
+            self.obj().finish_buffer(distorted)
+        }
+    }
+
+    impl QualityCompare {
+        fn sink_pad(&self, name: &str) -> gst_base::AggregatorPad {
+            self.obj().static_pad(name).unwrap().downcast().unwrap()
+        }
+
+        fn post_structure(&self, structure: gst::Structure) {
+            let obj = self.obj();
+            let _ = obj.post_message(gst::message::Element::builder(structure).src(&*obj).build());
+        }
+
+        /// Measure a distorted frame against its reference, post the result, and add
+        /// it to the totals for the summary.
+        fn compare(
+            &self,
+            reference: &gst::Buffer,
+            distorted: &gst::Buffer,
+        ) -> Result<(), gst::FlowError> {
+            let state = self.state.lock().unwrap();
+            let (Some(reference_info), Some(distorted_info)) = (&state.reference, &state.distorted)
+            else {
+                return Err(gst::FlowError::NotNegotiated);
+            };
+            if (
+                reference_info.format(),
+                reference_info.width(),
+                reference_info.height(),
+            ) != (
+                distorted_info.format(),
+                distorted_info.width(),
+                distorted_info.height(),
+            ) {
+                gst::element_imp_error!(
+                    self,
+                    gst::StreamError::Format,
+                    [
+                        "Reference is {}x{} {:?} but distorted is {}x{} {:?}",
+                        reference_info.width(),
+                        reference_info.height(),
+                        reference_info.format(),
+                        distorted_info.width(),
+                        distorted_info.height(),
+                        distorted_info.format()
+                    ]
+                );
+                return Err(gst::FlowError::NotNegotiated);
+            }
+
+            let reference_frame = gst_video::VideoFrameRef::from_buffer_ref_readable(
+                reference.as_ref(),
+                reference_info,
+            )
+            .map_err(|_| gst::FlowError::Error)?;
+            let distorted_frame = gst_video::VideoFrameRef::from_buffer_ref_readable(
+                distorted.as_ref(),
+                distorted_info,
+            )
+            .map_err(|_| gst::FlowError::Error)?;
+            let errors = yuv_offset::frame_errors(&reference_frame, &distorted_frame);
+            let depth = distorted_frame.format_info().depth()[0];
+            let fps = distorted_info.fps();
+            drop(state);
+
+            let settings = *self.settings.lock().unwrap();
+            let map_pad = self.map_pad.lock().unwrap().clone();
+            let similarity = if settings.ssim || map_pad.is_some() {
+                similarity(&reference_frame, &distorted_frame, max_value(depth))
+            } else {
+                Similarity::default()
+            };
+
+            let mut metrics = self.metrics.lock().unwrap();
+            metrics.frames += 1;
+            metrics.depth = depth;
+            for (total, frame) in metrics.errors.iter_mut().zip(&errors) {
+                total.merge(frame);
+            }
+            for (mean, result) in metrics.ssim.iter_mut().zip(&similarity.ssim) {
+                if let Some(result) = result {
+                    mean.add(result.ssim);
+                }
+            }
+            if let Some(ms_ssim) = similarity.ms_ssim {
+                metrics.ms_ssim.add(ms_ssim);
+            }
+            drop(metrics);
+
+            let mut structure = metrics_structure(
+                "qualitycompare",
+                &errors,
+                &settings.weights,
+                max_value(depth),
+            )
+            .field("pts", distorted.pts())
+            .field("offset", distorted.offset());
+            for (comp, result) in COMPONENT_NAMES.iter().zip(&similarity.ssim) {
+                if let Some(result) = result {
+                    structure = structure.field(format!("{comp}-ssim").as_str(), result.ssim);
+                }
+            }
+            if let Some(ms_ssim) = similarity.ms_ssim {
+                structure = structure.field("ms-ssim", ms_ssim);
+            }
+            self.post_structure(structure.build());
+
+            match (map_pad, &similarity.ssim[0]) {
+                (Some(pad), Some(result)) => self.push_map(&pad, &result.map, fps, distorted),
+                _ => Ok(()),
+            }
+        }
+
+        fn push_map_event(&self, event: gst::Event) {
+            if let Some(pad) = self.map_pad.lock().unwrap().clone() {
+                pad.push_event(event);
+            }
+        }
+
+        /// Send the luma SSIM map out of the map pad, with the timestamps and
+        /// segment of the distorted frame it came from. The map only covers where
+        /// the 11x11 window fits, so it is 10 smaller than the frame each way.
+        fn push_map(
+            &self,
+            pad: &gst::Pad,
+            map: &ssim::Plane,
+            fps: gst::Fraction,
+            distorted: &gst::Buffer,
+        ) -> Result<(), gst::FlowError> {
+            let info = gst_video::VideoInfo::builder(
+                VideoFormat::Gray8,
+                map.width as u32,
+                map.height as u32,
+            )
+            .fps(fps)
+            .build()
+            .map_err(|_| gst::FlowError::Error)?;
+            let mut buffer = map_buffer(&info, map)?;
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_pts(distorted.pts());
+                buffer.set_duration(distorted.duration());
+                buffer.set_offset(distorted.offset());
+            }
+
+            let caps = info.to_caps().map_err(|_| gst::FlowError::Error)?;
+            let segment = self.sink_pad("dist").segment();
+            let mut state = self.state.lock().unwrap();
+            let mut events = vec![];
+            if !state.map_started {
+                let stream_id = pad.create_stream_id(&*self.obj(), Some("map"));
+                events.push(gst::event::StreamStart::new(&stream_id));
+            }
+            if state.map_caps.as_ref() != Some(&caps) {
+                events.push(gst::event::Caps::new(&caps));
+                state.map_caps = Some(caps);
+            }
+            if state.map_segment.as_ref() != Some(&segment) {
+                events.push(gst::event::Segment::new(&segment));
+                state.map_segment = Some(segment);
+            }
+            state.map_started = true;
+            drop(state);
+
+            for event in events {
+                pad.push_event(event);
+            }
+            match pad.push(buffer) {
+                Ok(_) | Err(gst::FlowError::NotLinked) => Ok(()),
+                Err(err) => Err(err),
+            }
+        }
+
+        /// Post the totals since the element started, once.
+        fn post_summary(&self) {
+            let metrics = std::mem::take(&mut *self.metrics.lock().unwrap());
+            if metrics.frames == 0 && metrics.unmatched == 0 {
+                return;
+            }
+            let name = "qualitycompare-summary";
+            // With every frame unmatched there is no depth to give a PSNR.
+            let structure = if metrics.frames > 0 {
+                let weights = self.settings.lock().unwrap().weights;
+                let max = max_value(metrics.depth);
+                metrics_structure(name, &metrics.errors, &weights, max)
+            } else {
+                gst::Structure::builder(name)
+            };
+            let mut structure = structure
+                .field("frames", metrics.frames)
+                .field("unmatched", metrics.unmatched);
+            for (comp, mean) in COMPONENT_NAMES.iter().zip(&metrics.ssim) {
+                if let Some(mean) = mean.mean() {
+                    structure = structure.field(format!("{comp}-ssim").as_str(), mean);
+                }
+            }
+            if let Some(mean) = metrics.ms_ssim.mean() {
+                structure = structure.field("ms-ssim", mean);
+            }
+            self.post_structure(structure.build());
+        }
+    }
+
+    fn max_value(depth: u32) -> f64 {
+        ((1u64 << depth) - 1) as f64
+    }
+
+    /// Measure the SSIM of every component of two frames of the same format and
+    /// size, and the MS-SSIM of their luma.
+    fn similarity(
+        reference: &impl VideoFrameExt,
+        distorted: &impl VideoFrameExt,
+        max: f64,
+    ) -> Similarity {
+        let mut similarity = Similarity::default();
+        for comp in 0..3 {
+            let (width, height) = (
+                reference.comp_width(comp as u8) as usize,
+                reference.comp_height(comp as u8) as usize,
+            );
+            let a = ssim::Plane::new(
+                width,
+                height,
+                &yuv_offset::component_samples(reference, comp),
+            );
+            let b = ssim::Plane::new(
+                width,
+                height,
+                &yuv_offset::component_samples(distorted, comp),
+            );
+            let result = ssim::ssim(&a, &b, max);
+            if comp == 0 {
+                similarity.ms_ssim = result
+                    .as_ref()
+                    .and_then(|first| ssim::ms_ssim(first, &a, &b, max));
+            }
+            similarity.ssim[comp] = result;
+        }
+        similarity
+    }
+
+    /// A GRAY8 frame of an SSIM map, from black at 0 or below to white at 1.
+    fn map_buffer(
+        info: &gst_video::VideoInfo,
+        map: &ssim::Plane,
+    ) -> Result<gst::Buffer, gst::FlowError> {
+        let buffer = gst::Buffer::with_size(info.size()).map_err(|_| gst::FlowError::Error)?;
+        let mut frame = gst_video::VideoFrame::from_buffer_writable(buffer, info)
+            .map_err(|_| gst::FlowError::Error)?;
+        let stride = frame.plane_stride()[0] as usize;
+        let data = frame.plane_data_mut(0).unwrap();
+        for (line, row) in data
+            .chunks_mut(stride)
+            .zip(map.data.chunks_exact(map.width))
+        {
+            for (out, ssim) in line.iter_mut().zip(row) {
+                *out = (ssim.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+        Ok(frame.into_buffer())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use gst_video::VideoFormat;
+
+        fn new_element() -> super::super::QualityCompare {
+            let _ = gst::init();
+            glib::Object::new::<super::super::QualityCompare>()
+        }
+
+        /// An I420 frame of one colour.
+        fn frame(info: &gst_video::VideoInfo, yuv: [u8; 3], pts: u64) -> gst::Buffer {
+            let offset = info.offset();
+            let mut data = vec![yuv[0]; info.size()];
+            data[offset[1]..offset[2]].fill(yuv[1]);
+            data[offset[2]..].fill(yuv[2]);
+            let mut buffer = gst::Buffer::from_slice(data);
+            buffer
+                .get_mut()
+                .unwrap()
+                .set_pts(gst::ClockTime::from_mseconds(pts));
+            buffer
+        }
+
+        #[test]
+        fn test_pairing() {
+            let pts = |ms| Some(gst::ClockTime::from_mseconds(ms));
+            assert_eq!(pairing(Some(pts(0)), pts(40)), Pairing::DropReference);
+            assert_eq!(pairing(Some(pts(40)), pts(40)), Pairing::Compare);
+            assert_eq!(pairing(Some(pts(80)), pts(40)), Pairing::PassThrough);
+            assert_eq!(pairing(None, pts(40)), Pairing::PassThrough);
+            assert_eq!(pairing(Some(None), None), Pairing::Compare);
+        }
+
+        #[test]
+        fn test_weighted_psnr() {
+            let mut errors = [ErrorSum::default(); 3];
+            errors[0].add(10.0);
+            errors[1].add(2.0);
+            errors[2].add(-2.0);
+            let psnr = weighted_psnr(&errors, &[6.0, 1.0, 1.0], 255.0);
+            assert!((psnr - 31.6257).abs() < 1e-3, "{psnr}");
+            assert_eq!(
+                weighted_psnr(&errors, &[1.0, 0.0, 0.0], 255.0),
+                errors[0].psnr(255.0)
+            );
+        }
+
+        #[test]
+        fn test_compare_posts_metrics() {
+            let element = new_element();
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2)
+                .build()
+                .unwrap();
+            {
+                let mut state = element.imp().state.lock().unwrap();
+                state.reference = Some(info.clone());
+                state.distorted = Some(info.clone());
+            }
+
+            let reference = frame(&info, [128, 128, 128], 40);
+            let distorted = frame(&info, [138, 130, 126], 40);
+            element.imp().compare(&reference, &distorted).unwrap();
+            element.imp().compare(&reference, &reference).unwrap();
+            element.imp().post_summary();
+
+            let messages = std::iter::from_fn(|| bus.pop_filtered(&[gst::MessageType::Element]))
+                .map(|message| message.structure().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            assert_eq!(messages.len(), 3);
+            let first = &messages[0];
+            assert_eq!(first.name(), "qualitycompare");
+            assert_eq!(
+                first.get::<Option<gst::ClockTime>>("pts").unwrap(),
+                Some(gst::ClockTime::from_mseconds(40))
+            );
+            assert_eq!(first.get::<f64>("y-mse").unwrap(), 100.0);
+            assert_eq!(first.get::<f64>("u-mse").unwrap(), 4.0);
+            assert!((first.get::<f64>("y-psnr").unwrap() - 28.1308).abs() < 1e-3);
+            assert!((first.get::<f64>("yuv-psnr").unwrap() - 31.6257).abs() < 1e-3);
+            assert_eq!(messages[1].get::<f64>("yuv-psnr").unwrap(), f64::INFINITY);
+
+            let summary = &messages[2];
+            assert_eq!(summary.name(), "qualitycompare-summary");
+            assert_eq!(summary.get::<u64>("frames").unwrap(), 2);
+            assert_eq!(summary.get::<f64>("y-mse").unwrap(), 50.0);
+        }
+
+        #[test]
+        fn test_summary_of_only_unmatched_frames() {
+            let element = new_element();
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+            element.imp().metrics.lock().unwrap().unmatched = 3;
+            element.imp().post_summary();
+
+            let message = bus.pop_filtered(&[gst::MessageType::Element]).unwrap();
+            let summary = message.structure().unwrap();
+            assert_eq!(summary.name(), "qualitycompare-summary");
+            assert_eq!(summary.get::<u64>("frames").unwrap(), 0);
+            assert_eq!(summary.get::<u64>("unmatched").unwrap(), 3);
+            assert!(!summary.has_field("y-mse"));
+            assert!(!summary.has_field("yuv-psnr"));
+        }
+
+        #[test]
+        fn test_mismatched_inputs_are_an_error() {
+            let element = new_element();
+            let reference_info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2)
+                .build()
+                .unwrap();
+            let distorted_info = gst_video::VideoInfo::builder(VideoFormat::I420, 8, 2)
+                .build()
+                .unwrap();
+            {
+                let mut state = element.imp().state.lock().unwrap();
+                state.reference = Some(reference_info.clone());
+                state.distorted = Some(distorted_info.clone());
+            }
+            let result = element.imp().compare(
+                &frame(&reference_info, [128, 128, 128], 0),
+                &frame(&distorted_info, [128, 128, 128], 0),
+            );
+            assert_eq!(result, Err(gst::FlowError::NotNegotiated));
+        }
+
+        #[test]
+        fn test_pads() {
+            let element = new_element();
+            for name in ["ref", "dist", "src"] {
+                assert!(element.static_pad(name).is_some(), "{name}");
+            }
+        }
+
+        #[test]
+        fn test_map_pad_is_requested_once() {
+            let element = new_element();
+            let pad = element.request_pad_simple("map").unwrap();
+            assert!(element.request_pad_simple("map").is_none());
+            element.release_request_pad(&pad);
+            assert!(element.imp().map_pad.lock().unwrap().is_none());
+            assert!(element.request_pad_simple("map").is_some());
+        }
+
+        #[test]
+        fn test_ssim_posted_when_enabled() {
+            let element = new_element();
+            element.set_property("ssim", true);
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 32, 32)
+                .build()
+                .unwrap();
+            {
+                let mut state = element.imp().state.lock().unwrap();
+                state.reference = Some(info.clone());
+                state.distorted = Some(info.clone());
+            }
+
+            let reference = frame(&info, [100, 128, 128], 0);
+            let distorted = frame(&info, [110, 128, 128], 0);
+            element.imp().compare(&reference, &distorted).unwrap();
+            element.imp().post_summary();
+
+            let messages = std::iter::from_fn(|| bus.pop_filtered(&[gst::MessageType::Element]))
+                .map(|message| message.structure().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            let c1 = (0.01f64 * 255.0).powi(2);
+            let luminance = (2.0 * 100.0 * 110.0 + c1) / (100.0f64.powi(2) + 110.0f64.powi(2) + c1);
+            for message in &messages {
+                assert!((message.get::<f64>("y-ssim").unwrap() - luminance).abs() < 1e-9);
+                assert!((message.get::<f64>("u-ssim").unwrap() - 1.0).abs() < 1e-9);
+                // 32x32 is too small for the coarsest scale.
+                assert!(!message.has_field("ms-ssim"));
+            }
+            assert_eq!(messages.len(), 2);
+        }
+
+        #[test]
+        fn test_map_follows_dist_segment_and_flushes() {
+            let element = new_element();
+            let map = element.request_pad_simple("map").unwrap();
+            let events = std::sync::Arc::new(Mutex::new(vec![]));
+            let sink = gst::Pad::builder(gst::PadDirection::Sink)
+                .chain_function(|_, _, _| Ok(gst::FlowSuccess::Ok))
+                .event_function({
+                    let events = events.clone();
+                    move |_, _, event| {
+                        events.lock().unwrap().push(event);
+                        true
+                    }
+                })
+                .build();
+            sink.set_active(true).unwrap();
+            map.link(&sink).unwrap();
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 32, 32)
+                .build()
+                .unwrap();
+            {
+                let mut state = element.imp().state.lock().unwrap();
+                state.reference = Some(info.clone());
+                state.distorted = Some(info.clone());
+            }
+            let reference = frame(&info, [100, 128, 128], 0);
+            let distorted = frame(&info, [110, 128, 128], 0);
+            let dist = element.imp().sink_pad("dist");
+            element.imp().compare(&reference, &distorted).unwrap();
+            element
+                .imp()
+                .sink_event(&dist, gst::event::FlushStart::new());
+            element
+                .imp()
+                .sink_event(&dist, gst::event::FlushStop::new(true));
+            element.imp().compare(&reference, &distorted).unwrap();
+
+            let events = events.lock().unwrap();
+            let caps = events
+                .iter()
+                .find_map(|event| match event.view() {
+                    gst::EventView::Caps(caps) => Some(caps.caps_owned()),
+                    _ => None,
+                })
+                .unwrap();
+            let map_info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+            assert_eq!((map_info.width(), map_info.height()), (22, 22));
+
+            let types = events
+                .iter()
+                .map(|event| event.type_())
+                .filter(|&type_| {
+                    type_ != gst::EventType::StreamStart && type_ != gst::EventType::Caps
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                types,
+                [
+                    gst::EventType::Segment,
+                    gst::EventType::FlushStart,
+                    gst::EventType::FlushStop,
+                    gst::EventType::Segment,
+                ]
+            );
+            for event in events.iter() {
+                if let gst::EventView::Segment(segment) = event.view() {
+                    assert_eq!(segment.segment(), &dist.segment());
+                }
+            }
+        }
+
+        #[test]
+        fn test_map_buffer() {
+            let _ = gst::init();
+            let map = ssim::Plane {
+                width: 3,
+                height: 2,
+                data: vec![1.0, 0.5, -0.2, 0.0, 1.2, 0.25],
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::Gray8, 3, 2)
+                .build()
+                .unwrap();
+            let buffer = map_buffer(&info, &map).unwrap();
+            let frame = gst_video::VideoFrame::from_buffer_readable(buffer, &info).unwrap();
+            let stride = frame.plane_stride()[0] as usize;
+            let data = frame.plane_data(0).unwrap();
+            assert_eq!(data[..3], [255, 128, 0]);
+            assert_eq!(data[stride..stride + 3], [0, 255, 64]);
+        }
+    }
+}
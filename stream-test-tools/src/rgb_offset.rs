@@ -0,0 +1,398 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct RgbOffset(ObjectSubclass<imp::RgbOffset>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "rgbOffset",
+        gst::Rank::NONE,
+        RgbOffset::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::{VideoFormat, VideoFrameExt};
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use crate::yuv_offset::Overflow;
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "rgbOffset",
+            gst::DebugColorFlags::empty(),
+            Some("Rust RGB Offset"),
+        )
+    });
+
+    const DEFAULT_SHIFT: i32 = 0;
+    const DEFAULT_OVERFLOW: Overflow = Overflow::Wrap;
+
+    /// Studio-range RGB, used for the colour channels by `Overflow::SaturateLegal`.
+    const LEGAL_RGB: (i32, i32) = (16, 235);
+    /// Alpha has no legal range, so it always saturates to the full range.
+    const LEGAL_ALPHA: (i32, i32) = (0, 255);
+
+    const FORMATS: [VideoFormat; 7] = [
+        VideoFormat::Rgb,
+        VideoFormat::Bgr,
+        VideoFormat::Rgbx,
+        VideoFormat::Bgrx,
+        VideoFormat::Xrgb,
+        VideoFormat::Argb,
+        VideoFormat::Rgba,
+    ];
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        r: i32,
+        g: i32,
+        b: i32,
+        a: i32,
+        overflow: Overflow,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                r: DEFAULT_SHIFT,
+                g: DEFAULT_SHIFT,
+                b: DEFAULT_SHIFT,
+                a: DEFAULT_SHIFT,
+                overflow: DEFAULT_OVERFLOW,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct RgbOffset {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for RgbOffset {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecInt::builder("r")
+                        .nick("r")
+                        .blurb("Offset applied to the R channel")
+                        .default_value(DEFAULT_SHIFT)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecInt::builder("g")
+                        .nick("g")
+                        .blurb("Offset applied to the G channel")
+                        .default_value(DEFAULT_SHIFT)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecInt::builder("b")
+                        .nick("b")
+                        .blurb("Offset applied to the B channel")
+                        .default_value(DEFAULT_SHIFT)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecInt::builder("a")
+                        .nick("a")
+                        .blurb("Offset applied to the alpha channel, if there is one")
+                        .default_value(DEFAULT_SHIFT)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("overflow", DEFAULT_OVERFLOW)
+                        .nick("overflow")
+                        .blurb("How samples pushed outside of the range are handled")
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "r" => settings.r = value.get().expect("type checked upstream"),
+                "g" => settings.g = value.get().expect("type checked upstream"),
+                "b" => settings.b = value.get().expect("type checked upstream"),
+                "a" => settings.a = value.get().expect("type checked upstream"),
+                "overflow" => settings.overflow = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "r" => settings.r.to_value(),
+                "g" => settings.g.to_value(),
+                "b" => settings.b.to_value(),
+                "a" => settings.a.to_value(),
+                "overflow" => settings.overflow.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for RgbOffset {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for RgbOffset {
+        const NAME: &'static str = "rgbOffset";
+        type Type = super::RgbOffset;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for RgbOffset {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::NeverInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = true;
+    }
+
+    impl ElementImpl for RgbOffset {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "RGB Offset Tool",
+                    "Filter/Effect/Converter/Video",
+                    "Modify the incoming RGB channels to test tools for analysing videos.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(FORMATS)
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for RgbOffset {
+        fn transform_frame(
+            &self,
+            in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+            out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let offsets = [
+                (settings.r, LEGAL_RGB),
+                (settings.g, LEGAL_RGB),
+                (settings.b, LEGAL_RGB),
+                (settings.a, LEGAL_ALPHA),
+            ];
+
+            let format_info = in_frame.format_info();
+            let components = (0..format_info.n_components() as usize)
+                .map(|comp| (format_info.poffset()[comp] as usize, offsets[comp]))
+                .collect::<Vec<_>>();
+
+            let width = in_frame.width() as usize;
+            let pixel_stride = format_info.pixel_stride()[0] as usize;
+            let line_bytes = width * pixel_stride;
+            let in_stride = in_frame.plane_stride()[0] as usize;
+            let in_data = in_frame.plane_data(0).unwrap();
+            let out_stride = out_frame.plane_stride()[0] as usize;
+            let out_data = out_frame.plane_data_mut(0).unwrap();
+
+            // Rows may be padded, so walk them one at a time and only touch the pixels.
+            for (in_line, out_line) in in_data
+                .chunks_exact(in_stride)
+                .zip(out_data.chunks_exact_mut(out_stride))
+            {
+                // Copy first so that any padding byte in xRGB-style formats passes through.
+                out_line[..line_bytes].copy_from_slice(&in_line[..line_bytes]);
+                for (out_p, in_p) in out_line[..line_bytes]
+                    .chunks_exact_mut(pixel_stride)
+                    .zip(in_line[..line_bytes].chunks_exact(pixel_stride))
+                {
+                    for &(poffset, (offset, legal)) in &components {
+                        let value = (in_p[poffset] as i32).wrapping_add(offset);
+                        out_p[poffset] = settings.overflow.apply(value, legal, 255) as u8;
+                    }
+                }
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use test::Bencher;
+
+        fn run_plugin_tests(
+            settings: Settings,
+            format: VideoFormat,
+            data_inframe: Vec<u8>,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let _ = gst::init();
+
+            const WIDTH: u32 = 1;
+            const HEIGHT: u32 = 2;
+
+            let plugin = RgbOffset {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(format, WIDTH, HEIGHT)
+                .build()
+                .unwrap();
+            assert_eq!(data_inframe.len(), info.size());
+            let buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            let inframe = gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap();
+
+            let buffer_outframe = gst::Buffer::from_slice(vec![0; info.size()]);
+            let mut outframe = gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap();
+            plugin
+                .transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+                .unwrap();
+            outframe
+        }
+
+        fn channel_settings() -> Settings {
+            Settings {
+                r: 1,
+                g: 2,
+                b: 3,
+                a: 4,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_rgb_skips_row_padding() {
+            // A 1x2 RGB frame has a 4 byte stride, so every row ends in a padding byte.
+            let data_inframe = vec![10, 20, 30, 99, 40, 50, 60, 99];
+            let outframe = run_plugin_tests(channel_settings(), VideoFormat::Rgb, data_inframe);
+            assert_eq!(outframe.plane_data(0).unwrap(), [11, 22, 33, 0, 41, 52, 63, 0]);
+        }
+
+        #[test]
+        fn test_bgr() {
+            let data_inframe = vec![10, 20, 30, 0, 40, 50, 60, 0];
+            let outframe = run_plugin_tests(channel_settings(), VideoFormat::Bgr, data_inframe);
+            assert_eq!(outframe.plane_data(0).unwrap(), [13, 22, 31, 0, 43, 52, 61, 0]);
+        }
+
+        #[test]
+        fn test_xrgb_keeps_padding_byte() {
+            let data_inframe = vec![7, 10, 20, 30, 7, 40, 50, 60];
+            let outframe = run_plugin_tests(channel_settings(), VideoFormat::Xrgb, data_inframe);
+            assert_eq!(outframe.plane_data(0).unwrap(), [7, 11, 22, 33, 7, 41, 52, 63]);
+        }
+
+        #[test]
+        fn test_bgrx_keeps_padding_byte() {
+            let data_inframe = vec![10, 20, 30, 7, 40, 50, 60, 7];
+            let outframe = run_plugin_tests(channel_settings(), VideoFormat::Bgrx, data_inframe);
+            assert_eq!(outframe.plane_data(0).unwrap(), [13, 22, 31, 7, 43, 52, 61, 7]);
+        }
+
+        #[test]
+        fn test_argb_offsets_alpha() {
+            let data_inframe = vec![100, 10, 20, 30, 254, 40, 50, 60];
+            let outframe = run_plugin_tests(channel_settings(), VideoFormat::Argb, data_inframe);
+            assert_eq!(outframe.plane_data(0).unwrap(), [104, 11, 22, 33, 2, 41, 52, 63]);
+        }
+
+        #[test]
+        fn test_rgba_saturate_legal() {
+            let settings = Settings {
+                r: 10,
+                g: -10,
+                b: 0,
+                a: 10,
+                overflow: Overflow::SaturateLegal,
+            };
+            let data_inframe = vec![230, 20, 0, 250, 100, 100, 255, 0];
+            let outframe = run_plugin_tests(settings, VideoFormat::Rgba, data_inframe);
+            assert_eq!(outframe.plane_data(0).unwrap(), [235, 16, 16, 255, 110, 90, 235, 10]);
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let _ = gst::init();
+            let settings = test::black_box(Settings {
+                r: 10,
+                g: 10,
+                b: 10,
+                ..Default::default()
+            });
+
+            let plugin = RgbOffset {
+                settings: Mutex::new(settings),
+            };
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::Rgbx, width, height)
+                .build()
+                .unwrap();
+            let data_inframe = vec![0; info.size()];
+            let buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            let inframe = test::black_box(gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap());
+
+            let data_outframe = vec![0; info.size()];
+            let buffer_outframe = gst::Buffer::from_slice(data_outframe);
+            let mut outframe = test::black_box(gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap());
+
+            b.iter(|| {
+                plugin.transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+            });
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+//! What `waveform` and `vectorscope` share: the 8- and 10-bit input formats,
+//! the caps of a fixed-size graph, the legal levels of the input, and an RGB
+//! canvas that the graph is drawn on before it is written out as I420 or RGB.
+//!
+//! Every level is a code at the depth of the input, so 10-bit input is graphed
+//! as finely as the output has rows for.
+
+use gst_video::{VideoFormat, VideoFrameExt};
+
+use crate::yuv_offset;
+
+/// The formats of `yuv_offset::FORMATS` that are 8 or 10 bits deep.
+pub fn formats() -> Vec<VideoFormat> {
+    yuv_offset::formats_where(|info| info.depth()[0] <= 10)
+}
+
+/// The formats the graph is output as.
+pub const OUTPUT_FORMATS: [VideoFormat; 2] = [VideoFormat::I420, VideoFormat::Rgb];
+
+pub const BACKGROUND: [u8; 3] = [0, 0, 0];
+pub const GRATICULE: [u8; 3] = [88, 88, 88];
+/// The lines at the edges of the legal range.
+pub const LEGAL: [u8; 3] = [208, 136, 0];
+/// The levels outside the legal range.
+pub const ILLEGAL: [u8; 3] = [56, 0, 0];
+
+/// `BaseTransformImpl::transform_caps` for a graph of `width` by `height` from
+/// any size of input.
+pub fn transform_caps(
+    direction: gst::PadDirection,
+    caps: &gst::Caps,
+    filter: Option<&gst::Caps>,
+    width: u32,
+    height: u32,
+) -> Option<gst::Caps> {
+    let mut other_caps = caps.clone();
+    for s in other_caps.make_mut().iter_mut() {
+        // The colorimetry of the input says nothing about the graph, nor the
+        // other way round.
+        s.remove_field("colorimetry");
+        s.remove_field("chroma-site");
+        if direction == gst::PadDirection::Sink {
+            s.set("format", gst::List::new(OUTPUT_FORMATS.map(|f| f.to_str())));
+            s.set("width", width as i32);
+            s.set("height", height as i32);
+            s.set("pixel-aspect-ratio", gst::Fraction::new(1, 1));
+        } else {
+            s.set("format", gst::List::new(formats().into_iter().map(|f| f.to_str())));
+            s.set("width", gst::IntRange::new(1, i32::MAX));
+            s.set("height", gst::IntRange::new(1, i32::MAX));
+            s.remove_field("pixel-aspect-ratio");
+        }
+    }
+    if let Some(filter) = filter {
+        Some(filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First))
+    } else {
+        Some(other_caps)
+    }
+}
+
+/// The legal codes of a format and range, at its depth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Levels {
+    pub depth: u32,
+    pub black: u32,
+    pub white: u32,
+    pub chroma_min: u32,
+    pub chroma_max: u32,
+}
+
+impl Levels {
+    /// An unknown range is treated as limited.
+    pub fn new(info: &gst_video::VideoInfo) -> Self {
+        let depth = info.format_info().depth()[0];
+        let max = (1 << depth) - 1;
+        if info.colorimetry().range() == gst_video::VideoColorRange::Range0_255 {
+            Self {
+                depth,
+                black: 0,
+                white: max,
+                chroma_min: 0,
+                chroma_max: max,
+            }
+        } else {
+            let shift = depth - 8;
+            Self {
+                depth,
+                black: 16 << shift,
+                white: 235 << shift,
+                chroma_min: 16 << shift,
+                chroma_max: 240 << shift,
+            }
+        }
+    }
+
+    pub fn max(&self) -> u32 {
+        (1 << self.depth) - 1
+    }
+
+    /// The code of zero chroma.
+    pub fn chroma_zero(&self) -> u32 {
+        1 << (self.depth - 1)
+    }
+
+    /// The code of `level` from 0 for black to 1 for white, not clipped.
+    pub fn luma_code(&self, level: f32) -> f32 {
+        self.black as f32 + level * (self.white - self.black) as f32
+    }
+
+    /// The code of `level` from -0.5 to 0.5, not clipped.
+    pub fn chroma_code(&self, level: f32) -> f32 {
+        self.chroma_zero() as f32 + level * (self.chroma_max - self.chroma_min) as f32
+    }
+}
+
+/// The luma weights `(kr, kb)` of the matrix in the caps. Anything without its
+/// own weights is treated as BT.709, as `colorMismatch` does.
+pub fn luma_weights(info: &gst_video::VideoInfo) -> (f32, f32) {
+    match info.colorimetry().matrix() {
+        gst_video::VideoColorMatrix::Bt601 => (0.299, 0.114),
+        gst_video::VideoColorMatrix::Bt2020 => (0.2627, 0.0593),
+        _ => (0.2126, 0.0722),
+    }
+}
+
+/// `(Cb, Cr)` from -0.5 to 0.5 of R'G'B' from 0 to 1.
+pub fn chroma(rgb: [f32; 3], (kr, kb): (f32, f32)) -> (f32, f32) {
+    let [r, g, b] = rgb;
+    let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+    ((b - y) / (2.0 * (1.0 - kb)), (r - y) / (2.0 * (1.0 - kr)))
+}
+
+/// The row of a graph `height` rows high that `code` is drawn on, with the
+/// largest code at the top.
+pub fn level_row(code: u32, depth: u32, height: usize) -> usize {
+    let from_bottom = ((code as u64 * height as u64) >> depth) as usize;
+    height - 1 - from_bottom.min(height - 1)
+}
+
+/// How many samples land on each point of a graph.
+#[derive(Debug, Clone)]
+pub struct Hits {
+    pub width: usize,
+    pub height: usize,
+    counts: Vec<u32>,
+}
+
+impl Hits {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            counts: vec![0; width * height],
+        }
+    }
+
+    pub fn add(&mut self, x: usize, y: usize) {
+        self.counts[y * self.width + x] += 1;
+    }
+
+    pub fn count(&self, x: usize, y: usize) -> u32 {
+        self.counts[y * self.width + x]
+    }
+
+    /// Draw every point that was hit at `left` on `canvas` in `colour`, as
+    /// bright as the square root of its count times `scale`, up to full
+    /// brightness. Graticules stay visible where they're brighter.
+    pub fn draw(&self, canvas: &mut Canvas, left: usize, colour: [u8; 3], scale: f64) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let count = self.count(x, y);
+                if count == 0 {
+                    continue;
+                }
+                let brightness = (count as f64 * scale).min(1.0).sqrt();
+                let trace = colour.map(|c| (c as f64 * brightness).round() as u8);
+                let pixel = canvas.pixel_mut(left + x, y);
+                *pixel = std::array::from_fn(|c| pixel[c].max(trace[c]));
+            }
+        }
+    }
+}
+
+/// An R'G'B' image that a graph is drawn on.
+#[derive(Debug, Clone)]
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+    data: Vec<[u8; 3]>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![BACKGROUND; width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        self.data[y * self.width + x]
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut [u8; 3] {
+        &mut self.data[y * self.width + x]
+    }
+
+    /// Set every pixel inside the canvas from `(x0, y0)` up to `(x1, y1)`.
+    pub fn fill(&mut self, (x0, y0): (usize, usize), (x1, y1): (usize, usize), colour: [u8; 3]) {
+        for y in y0..y1.min(self.height) {
+            for x in x0..x1.min(self.width) {
+                *self.pixel_mut(x, y) = colour;
+            }
+        }
+    }
+
+    /// The edges of the rectangle [`fill`](Self::fill) would set.
+    pub fn outline(&mut self, (x0, y0): (usize, usize), (x1, y1): (usize, usize), colour: [u8; 3]) {
+        self.fill((x0, y0), (x1, y0 + 1), colour);
+        self.fill((x0, y1 - 1), (x1, y1), colour);
+        self.fill((x0, y0), (x0 + 1, y1), colour);
+        self.fill((x1 - 1, y0), (x1, y1), colour);
+    }
+
+    pub fn hline(&mut self, y: usize, colour: [u8; 3]) {
+        self.fill((0, y), (self.width, y + 1), colour);
+    }
+
+    pub fn vline(&mut self, x: usize, colour: [u8; 3]) {
+        self.fill((x, 0), (x + 1, self.height), colour);
+    }
+
+    /// Write the canvas to a frame of the same size in one of the
+    /// `OUTPUT_FORMATS`, encoding it as the frame's caps say for I420.
+    pub fn write(&self, frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>) {
+        assert_eq!(
+            (frame.width() as usize, frame.height() as usize),
+            (self.width, self.height)
+        );
+        match frame.format() {
+            VideoFormat::Rgb => {
+                let stride = frame.plane_stride()[0] as usize;
+                let data = frame.plane_data_mut(0).unwrap();
+                for (y, line) in data.chunks_mut(stride).take(self.height).enumerate() {
+                    for (x, out) in line[..self.width * 3].chunks_exact_mut(3).enumerate() {
+                        out.copy_from_slice(&self.pixel(x, y));
+                    }
+                }
+            }
+            VideoFormat::I420 => self.write_i420(frame),
+            format => unreachable!("{format:?} is not an output format"),
+        }
+    }
+
+    fn write_i420(&self, frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>) {
+        let levels = Levels::new(frame.info());
+        let weights = luma_weights(frame.info());
+        let normalised = |pixel: [u8; 3]| pixel.map(|c| c as f32 / 255.0);
+        let to_code = |code: f32| code.round().clamp(0.0, 255.0) as u8;
+        let strides = frame.plane_stride().to_vec();
+
+        let (kr, kb) = weights;
+        let data = frame.plane_data_mut(0).unwrap();
+        for (y, line) in data
+            .chunks_mut(strides[0] as usize)
+            .take(self.height)
+            .enumerate()
+        {
+            for (x, out) in line[..self.width].iter_mut().enumerate() {
+                let [r, g, b] = normalised(self.pixel(x, y));
+                *out = to_code(levels.luma_code(kr * r + (1.0 - kr - kb) * g + kb * b));
+            }
+        }
+
+        // Each chroma sample is the average of the pixels it covers.
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut cb = vec![0.0; width * height];
+        let mut cr = vec![0.0; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let xs = 2 * x..(2 * x + 2).min(self.width);
+                let ys = 2 * y..(2 * y + 2).min(self.height);
+                let n = (xs.len() * ys.len()) as f32;
+                let mut rgb = [0.0; 3];
+                for sy in ys {
+                    for sx in xs.clone() {
+                        let pixel = normalised(self.pixel(sx, sy));
+                        rgb.iter_mut().zip(pixel).for_each(|(sum, c)| *sum += c / n);
+                    }
+                }
+                (cb[y * width + x], cr[y * width + x]) = chroma(rgb, weights);
+            }
+        }
+        for (plane, values) in [(1, cb), (2, cr)] {
+            let stride = strides[plane] as usize;
+            let data = frame.plane_data_mut(plane as u32).unwrap();
+            for (line, row) in data.chunks_mut(stride).zip(values.chunks_exact(width)) {
+                for (out, &value) in line[..width].iter_mut().zip(row) {
+                    *out = to_code(levels.chroma_code(value));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(format: VideoFormat, range: gst_video::VideoColorRange) -> gst_video::VideoInfo {
+        let _ = gst::init();
+        let colorimetry = gst_video::VideoColorimetry::new(
+            range,
+            gst_video::VideoColorMatrix::Bt709,
+            gst_video::VideoTransferFunction::Bt709,
+            gst_video::VideoColorPrimaries::Bt709,
+        );
+        gst_video::VideoInfo::builder(format, 4, 2)
+            .colorimetry(&colorimetry)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_formats_are_8_or_10_bits() {
+        let _ = gst::init();
+        let formats = formats();
+        assert_eq!(formats.len(), 18);
+        assert!(formats.contains(&VideoFormat::V210));
+        assert!(formats.contains(&VideoFormat::P01010le));
+        assert!(!formats.contains(&VideoFormat::I42012le));
+        assert!(!formats.contains(&VideoFormat::P016Le));
+    }
+
+    #[test]
+    fn test_levels() {
+        let limited = gst_video::VideoColorRange::Range16_235;
+        let levels = Levels::new(&info(VideoFormat::I42010le, limited));
+        assert_eq!((levels.black, levels.white), (64, 940));
+        assert_eq!((levels.chroma_min, levels.chroma_max), (64, 960));
+        assert_eq!((levels.max(), levels.chroma_zero()), (1023, 512));
+
+        let full = gst_video::VideoColorRange::Range0_255;
+        let levels = Levels::new(&info(VideoFormat::I420, full));
+        assert_eq!(
+            (levels.black, levels.white, levels.chroma_max),
+            (0, 255, 255)
+        );
+        assert_eq!(levels.luma_code(0.5), 127.5);
+    }
+
+    #[test]
+    fn test_level_row() {
+        assert_eq!(level_row(0, 8, 256), 255);
+        assert_eq!(level_row(255, 8, 256), 0);
+        assert_eq!(level_row(128, 8, 64), 31);
+        assert_eq!(level_row(512, 10, 64), 31);
+        assert_eq!(level_row(1023, 10, 100), 0);
+    }
+
+    #[test]
+    fn test_chroma() {
+        let weights = (0.2126, 0.0722);
+        let (cb, cr) = chroma([0.5, 0.5, 0.5], weights);
+        assert!(cb.abs() < 1e-6 && cr.abs() < 1e-6);
+        let (cb, cr) = chroma([1.0, 0.0, 0.0], weights);
+        assert!((cr - 0.5).abs() < 1e-6);
+        assert!(cb < 0.0);
+    }
+
+    #[test]
+    fn test_hits_brightness() {
+        let mut canvas = Canvas::new(4, 2);
+        canvas.hline(1, GRATICULE);
+        let mut hits = Hits::new(2, 2);
+        hits.add(0, 0);
+        (0..4).for_each(|_| hits.add(1, 1));
+        hits.draw(&mut canvas, 2, [255, 255, 255], 0.25);
+        assert_eq!(canvas.pixel(2, 0), [128; 3]);
+        assert_eq!(canvas.pixel(3, 1), [255; 3]);
+        assert_eq!(canvas.pixel(0, 1), GRATICULE);
+        assert_eq!(canvas.pixel(0, 0), BACKGROUND);
+    }
+
+    #[test]
+    fn test_write_i420() {
+        let info = info(VideoFormat::I420, gst_video::VideoColorRange::Range16_235);
+        let mut canvas = Canvas::new(4, 2);
+        canvas.fill((0, 0), (2, 2), [255, 255, 255]);
+        canvas.fill((2, 0), (4, 2), [255, 0, 0]);
+        let buffer = gst::Buffer::with_size(info.size()).unwrap();
+        let mut frame = gst_video::VideoFrame::from_buffer_writable(buffer, &info).unwrap();
+        canvas.write(&mut frame.as_mut_video_frame_ref());
+
+        assert_eq!(&frame.plane_data(0).unwrap()[..4], [235, 235, 63, 63]);
+        assert_eq!(frame.plane_data(1).unwrap()[..2], [128, 102]);
+        assert_eq!(frame.plane_data(2).unwrap()[..2], [128, 240]);
+    }
+}
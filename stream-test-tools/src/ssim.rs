@@ -0,0 +1,253 @@
+//! SSIM and MS-SSIM, as Wang et al. define them: an 11x11 Gaussian window with
+//! a standard deviation of 1.5 samples, placed only where it fits entirely
+//! within the plane, and five scales for MS-SSIM.
+//!
+//! Everything is done in `f64`, since the variances are small differences of
+//! large sums at 16 bits.
+
+/// Width and height of the window.
+pub const WINDOW: usize = 11;
+
+const SIGMA: f64 = 1.5;
+
+/// The exponents of the contrast-structure term at each scale of MS-SSIM, the
+/// last of which also applies to the luminance term.
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// The smallest width or height MS-SSIM can be measured at, so that the window
+/// still fits at the coarsest scale.
+pub const MS_SSIM_MIN_SIZE: usize = WINDOW << (MS_SSIM_WEIGHTS.len() - 1);
+
+/// One component of a frame.
+#[derive(Debug, Clone)]
+pub struct Plane {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f64>,
+}
+
+/// The mean SSIM and mean contrast-structure term of two planes, and the SSIM at
+/// each position of the window.
+#[derive(Debug, Clone)]
+pub struct Ssim {
+    pub ssim: f64,
+    pub cs: f64,
+    pub map: Plane,
+}
+
+impl Plane {
+    pub fn new(width: usize, height: usize, samples: &[u16]) -> Self {
+        Self {
+            width,
+            height,
+            data: samples.iter().map(|&s| s as f64).collect(),
+        }
+    }
+
+    fn product(&self, other: &Plane) -> Plane {
+        Plane {
+            data: self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(a, b)| a * b)
+                .collect(),
+            ..*self
+        }
+    }
+
+    /// The weighted mean under the window at every position it fits, which is
+    /// `WINDOW - 1` smaller in each direction.
+    fn windowed(&self, taps: &[f64; WINDOW]) -> Plane {
+        let width = self.width + 1 - WINDOW;
+        let height = self.height + 1 - WINDOW;
+        let mut rows = Vec::with_capacity(width * self.height);
+        for line in self.data.chunks_exact(self.width) {
+            rows.extend(
+                (0..width).map(|x| taps.iter().zip(&line[x..]).map(|(t, s)| t * s).sum::<f64>()),
+            );
+        }
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            data.extend((0..width).map(|x| {
+                taps.iter()
+                    .enumerate()
+                    .map(|(i, t)| t * rows[(y + i) * width + x])
+                    .sum::<f64>()
+            }));
+        }
+        Plane {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Half the size, each sample the mean of a 2x2 block. An odd last row or
+    /// column is dropped.
+    fn downsampled(&self) -> Plane {
+        let width = self.width / 2;
+        let height = self.height / 2;
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let top = &self.data[2 * y * self.width..];
+            let bottom = &self.data[(2 * y + 1) * self.width..];
+            data.extend(
+                (0..width).map(|x| {
+                    (top[2 * x] + top[2 * x + 1] + bottom[2 * x] + bottom[2 * x + 1]) / 4.0
+                }),
+            );
+        }
+        Plane {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// The normalised 1D Gaussian the window is made of.
+pub fn gaussian_taps() -> [f64; WINDOW] {
+    let centre = (WINDOW / 2) as f64;
+    let mut taps =
+        std::array::from_fn(|i| (-(i as f64 - centre).powi(2) / (2.0 * SIGMA * SIGMA)).exp());
+    let sum = taps.iter().sum::<f64>();
+    taps.iter_mut().for_each(|t| *t /= sum);
+    taps
+}
+
+/// SSIM of `a` and `b`, which are the same size, for samples up to `max`. `None`
+/// if the window doesn't fit.
+pub fn ssim(a: &Plane, b: &Plane, max: f64) -> Option<Ssim> {
+    if a.width < WINDOW || a.height < WINDOW {
+        return None;
+    }
+    let taps = gaussian_taps();
+    let mean_a = a.windowed(&taps);
+    let mean_b = b.windowed(&taps);
+    let mean_aa = a.product(a).windowed(&taps);
+    let mean_bb = b.product(b).windowed(&taps);
+    let mean_ab = a.product(b).windowed(&taps);
+    let c1 = (0.01 * max).powi(2);
+    let c2 = (0.03 * max).powi(2);
+
+    let mut cs_sum = 0.0;
+    let data = (0..mean_a.data.len())
+        .map(|i| {
+            let (ma, mb) = (mean_a.data[i], mean_b.data[i]);
+            let var_a = mean_aa.data[i] - ma * ma;
+            let var_b = mean_bb.data[i] - mb * mb;
+            let cov = mean_ab.data[i] - ma * mb;
+            let cs = (2.0 * cov + c2) / (var_a + var_b + c2);
+            cs_sum += cs;
+            cs * (2.0 * ma * mb + c1) / (ma * ma + mb * mb + c1)
+        })
+        .collect::<Vec<_>>();
+    let n = data.len() as f64;
+    Some(Ssim {
+        ssim: data.iter().sum::<f64>() / n,
+        cs: cs_sum / n,
+        map: Plane { data, ..mean_a },
+    })
+}
+
+/// MS-SSIM of `a` and `b`, carrying on from their full scale SSIM `first`.
+/// `None` if either is smaller than `MS_SSIM_MIN_SIZE`. Negative terms count as
+/// zero, since they can't be raised to a fractional power.
+pub fn ms_ssim(first: &Ssim, a: &Plane, b: &Plane, max: f64) -> Option<f64> {
+    if a.width < MS_SSIM_MIN_SIZE || a.height < MS_SSIM_MIN_SIZE {
+        return None;
+    }
+    let mut product = first.cs.max(0.0).powf(MS_SSIM_WEIGHTS[0]);
+    let (mut a, mut b) = (a.downsampled(), b.downsampled());
+    for (scale, weight) in MS_SSIM_WEIGHTS.iter().enumerate().skip(1) {
+        let result = ssim(&a, &b, max)?;
+        let term = if scale == MS_SSIM_WEIGHTS.len() - 1 {
+            result.ssim
+        } else {
+            (a, b) = (a.downsampled(), b.downsampled());
+            result.cs
+        };
+        product *= term.max(0.0).powf(*weight);
+    }
+    Some(product)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic noise from 0 to 255.
+    fn noise(width: usize, height: usize, seed: usize) -> Plane {
+        let samples = (0..width * height)
+            .map(|i| ((i * 97 + seed * 13) % 251) as u16)
+            .collect::<Vec<_>>();
+        Plane::new(width, height, &samples)
+    }
+
+    fn constant(width: usize, height: usize, value: u16) -> Plane {
+        Plane::new(width, height, &vec![value; width * height])
+    }
+
+    #[test]
+    fn test_gaussian_taps() {
+        let taps = gaussian_taps();
+        assert!((taps.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!((taps[5] - 0.26596).abs() < 1e-4);
+        for i in 0..5 {
+            assert_eq!(taps[i], taps[10 - i]);
+        }
+    }
+
+    #[test]
+    fn test_identical_planes() {
+        let a = noise(MS_SSIM_MIN_SIZE, MS_SSIM_MIN_SIZE, 1);
+        let result = ssim(&a, &a, 255.0).unwrap();
+        assert!((result.ssim - 1.0).abs() < 1e-9);
+        assert!(result.map.data.iter().all(|s| (s - 1.0).abs() < 1e-9));
+        assert_eq!((result.map.width, result.map.height), (166, 166));
+        assert!((ms_ssim(&result, &a, &a, 255.0).unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_constant_offset_only_costs_luminance() {
+        let (a, b) = (
+            constant(MS_SSIM_MIN_SIZE, 200, 100),
+            constant(MS_SSIM_MIN_SIZE, 200, 110),
+        );
+        let result = ssim(&a, &b, 255.0).unwrap();
+        let c1 = (0.01f64 * 255.0).powi(2);
+        let luminance = (2.0 * 100.0 * 110.0 + c1) / (100.0f64.powi(2) + 110.0f64.powi(2) + c1);
+        assert!((result.ssim - luminance).abs() < 1e-9);
+        assert!((result.cs - 1.0).abs() < 1e-9);
+        let ms = ms_ssim(&result, &a, &b, 255.0).unwrap();
+        assert!((ms - luminance.powf(0.1333)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_noise_lowers_ssim() {
+        let a = noise(64, 48, 1);
+        let b = noise(64, 48, 2);
+        let same = ssim(&a, &a, 255.0).unwrap().ssim;
+        let different = ssim(&a, &b, 255.0).unwrap().ssim;
+        assert!(different < same);
+        assert!((-1.0..1.0).contains(&different));
+    }
+
+    #[test]
+    fn test_too_small() {
+        let a = constant(10, 20, 0);
+        assert!(ssim(&a, &a, 255.0).is_none());
+        let a = constant(MS_SSIM_MIN_SIZE - 1, MS_SSIM_MIN_SIZE, 0);
+        let result = ssim(&a, &a, 255.0).unwrap();
+        assert!(ms_ssim(&result, &a, &a, 255.0).is_none());
+    }
+
+    #[test]
+    fn test_downsampled() {
+        let plane = Plane::new(3, 2, &[0, 4, 100, 8, 12, 100]);
+        let half = plane.downsampled();
+        assert_eq!((half.width, half.height), (1, 1));
+        assert_eq!(half.data, [6.0]);
+    }
+}
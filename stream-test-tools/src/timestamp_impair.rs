@@ -0,0 +1,590 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct TimestampImpair(ObjectSubclass<imp::TimestampImpair>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "timestampImpair",
+        gst::Rank::NONE,
+        TimestampImpair::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_base::prelude::*;
+    use gst_base::subclass::prelude::*;
+
+    use std::collections::BTreeSet;
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use crate::frame_impair::{format_frames, parse_frames};
+    use crate::noise_inject::{Distribution, Rng};
+
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "timestampImpair",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Timestamp Impair"),
+        )
+    });
+
+    const DEFAULT_JITTER: u64 = 0;
+    const DEFAULT_DISTRIBUTION: Distribution = Distribution::Uniform;
+    const DEFAULT_SEED: u64 = 0;
+    const DEFAULT_DISCONT_JUMP: i64 = 0;
+
+    /// The timestamps that can be jittered, in the order their generators are
+    /// numbered.
+    const JITTERED: [&str; 3] = ["pts", "dts", "duration"];
+
+    #[derive(Debug, Clone)]
+    struct Settings {
+        /// Spread of the jitter on the PTS, DTS and duration, in nanoseconds.
+        jitter: [u64; 3],
+        distribution: Distribution,
+        seed: u64,
+        /// Input buffers, counted from 0, replaced by a GAP event.
+        gap_frames: BTreeSet<u64>,
+        /// Input buffers, counted from 0, flagged DISCONT.
+        discont_frames: BTreeSet<u64>,
+        /// How far every discont moves the timestamps from then on.
+        discont_jump: i64,
+        /// Input buffers, counted from 0, that get a new segment sent ahead of them.
+        segment_frames: BTreeSet<u64>,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                jitter: [DEFAULT_JITTER; 3],
+                distribution: DEFAULT_DISTRIBUTION,
+                seed: DEFAULT_SEED,
+                gap_frames: Default::default(),
+                discont_frames: Default::default(),
+                discont_jump: DEFAULT_DISCONT_JUMP,
+                segment_frames: Default::default(),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct State {
+        /// Input buffers seen since the last flush.
+        frame: u64,
+        /// Sum of the discont jumps so far.
+        shift: i64,
+        /// A discont landed on a gap, so the next buffer out carries the flag.
+        discont_pending: bool,
+        /// The last segment sent downstream, by upstream or by us.
+        segment: Option<gst::FormattedSegment<gst::ClockTime>>,
+    }
+
+    #[derive(Default)]
+    pub struct TimestampImpair {
+        settings: Mutex<Settings>,
+        state: Mutex<State>,
+    }
+
+    impl ObjectImpl for TimestampImpair {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                let jitters = JITTERED.into_iter().map(|name| {
+                    let property = format!("{name}-jitter");
+                    glib::ParamSpecUInt64::builder(&property)
+                        .nick(&property)
+                        .blurb(&format!(
+                            "Spread of the {} jitter in ns: the sigma for gaussian, the limit for uniform",
+                            name.to_uppercase()
+                        ))
+                        .default_value(DEFAULT_JITTER)
+                        .mutable_playing()
+                        .build()
+                });
+                let lists = [
+                    ("gap-frames", "replace with a GAP event"),
+                    ("discont-frames", "flag DISCONT"),
+                    ("segment-frames", "send a new segment ahead of"),
+                ]
+                .into_iter()
+                .map(|(name, what)| {
+                    glib::ParamSpecString::builder(name)
+                        .nick(name)
+                        .blurb(&format!(
+                            "Comma separated input buffer numbers, counted from 0, to {what}"
+                        ))
+                        .mutable_playing()
+                        .build()
+                });
+                jitters
+                    .chain(lists)
+                    .chain([
+                        glib::ParamSpecEnum::builder_with_default(
+                            "distribution",
+                            DEFAULT_DISTRIBUTION,
+                        )
+                        .nick("distribution")
+                        .blurb("Distribution the jitter is drawn from")
+                        .mutable_playing()
+                        .build(),
+                        glib::ParamSpecUInt64::builder("seed")
+                            .nick("seed")
+                            .blurb("Seed for the jitter")
+                            .default_value(DEFAULT_SEED)
+                            .mutable_playing()
+                            .build(),
+                        glib::ParamSpecInt64::builder("discont-jump")
+                            .nick("discont-jump")
+                            .blurb(
+                                "Nanoseconds added to every timestamp from each discont frame on",
+                            )
+                            .default_value(DEFAULT_DISCONT_JUMP)
+                            .mutable_playing()
+                            .build(),
+                    ])
+                    .collect()
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "distribution" => {
+                    settings.distribution = value.get().expect("type checked upstream")
+                }
+                "seed" => settings.seed = value.get().expect("type checked upstream"),
+                "discont-jump" => {
+                    settings.discont_jump = value.get().expect("type checked upstream")
+                }
+                name @ ("gap-frames" | "discont-frames" | "segment-frames") => {
+                    let list = value
+                        .get::<Option<String>>()
+                        .expect("type checked upstream");
+                    let frames = self.parse_frames(list.as_deref().unwrap_or(""));
+                    match name {
+                        "gap-frames" => settings.gap_frames = frames,
+                        "discont-frames" => settings.discont_frames = frames,
+                        _ => settings.segment_frames = frames,
+                    }
+                }
+                name => {
+                    let index = JITTERED
+                        .iter()
+                        .position(|jittered| name.strip_suffix("-jitter") == Some(jittered))
+                        .unwrap_or_else(|| unimplemented!());
+                    settings.jitter[index] = value.get().expect("type checked upstream");
+                }
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "distribution" => settings.distribution.to_value(),
+                "seed" => settings.seed.to_value(),
+                "discont-jump" => settings.discont_jump.to_value(),
+                "gap-frames" => format_frames(&settings.gap_frames).to_value(),
+                "discont-frames" => format_frames(&settings.discont_frames).to_value(),
+                "segment-frames" => format_frames(&settings.segment_frames).to_value(),
+                name => {
+                    let index = JITTERED
+                        .iter()
+                        .position(|jittered| name.strip_suffix("-jitter") == Some(jittered))
+                        .unwrap_or_else(|| unimplemented!());
+                    settings.jitter[index].to_value()
+                }
+            }
+        }
+    }
+
+    impl GstObjectImpl for TimestampImpair {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TimestampImpair {
+        const NAME: &'static str = "timestampImpair";
+        type Type = super::TimestampImpair;
+        type ParentType = gst_base::BaseTransform;
+    }
+
+    impl BaseTransformImpl for TimestampImpair {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = State::default();
+            self.parent_start()
+        }
+
+        fn sink_event(&self, event: gst::Event) -> bool {
+            match event.view() {
+                gst::EventView::Segment(segment) => {
+                    self.state.lock().unwrap().segment =
+                        segment.segment().downcast_ref::<gst::ClockTime>().cloned();
+                }
+                gst::EventView::FlushStop(_) => *self.state.lock().unwrap() = State::default(),
+                _ => (),
+            }
+            self.parent_sink_event(event)
+        }
+
+        fn transform_ip(
+            &self,
+            buf: &mut gst::BufferRef,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let (events, keep) = self.impair(buf);
+            let src_pad = self.obj().src_pad();
+            for event in events {
+                if !src_pad.push_event(event) {
+                    gst::debug!(CAT, imp = self, "Downstream didn't take an injected event");
+                }
+            }
+            if keep {
+                Ok(gst::FlowSuccess::Ok)
+            } else {
+                Ok(gst_base::BASE_TRANSFORM_FLOW_DROPPED)
+            }
+        }
+    }
+
+    impl ElementImpl for TimestampImpair {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Timestamp Impair Tool",
+                    "Filter/Effect",
+                    "Jitter timestamps and inject gaps, disconts and segment changes to test timing analysers.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst::Caps::new_any();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl TimestampImpair {
+        /// Comma separated frame numbers. Anything that isn't one is skipped.
+        fn parse_frames(&self, list: &str) -> BTreeSet<u64> {
+            let (frames, invalid) = parse_frames(list);
+            for frame in invalid {
+                gst::warning!(CAT, imp = self, "Ignoring frame number {frame:?}");
+            }
+            frames
+        }
+
+        /// Rewrite the timing of the next input buffer. Returns the events to
+        /// send ahead of it, and whether it should be sent at all.
+        fn impair(&self, buffer: &mut gst::BufferRef) -> (Vec<gst::Event>, bool) {
+            let settings = self.settings.lock().unwrap().clone();
+            let mut state = self.state.lock().unwrap();
+            let frame = state.frame;
+            state.frame += 1;
+            let mut events = vec![];
+
+            let discont = settings.discont_frames.contains(&frame);
+            if discont {
+                state.shift = state.shift.saturating_add(settings.discont_jump);
+                state.discont_pending = true;
+            }
+
+            let jitter = |stream: usize| {
+                Rng::new(settings.seed, frame, stream)
+                    .sample(settings.distribution, settings.jitter[stream] as f64)
+                    .round() as i64
+            };
+            buffer.set_pts(shift(buffer.pts(), state.shift.saturating_add(jitter(0))));
+            buffer.set_dts(shift(buffer.dts(), state.shift.saturating_add(jitter(1))));
+            buffer.set_duration(shift(buffer.duration(), jitter(2)));
+            if discont {
+                self.post_action("discont", frame, buffer, |s| {
+                    s.field("jump", settings.discont_jump)
+                });
+            }
+
+            if settings.segment_frames.contains(&frame) {
+                match next_segment(state.segment.as_ref(), buffer.pts()) {
+                    Some(segment) => {
+                        self.post_action("segment", frame, buffer, |s| {
+                            s.field("base", segment.base())
+                        });
+                        events.push(gst::event::Segment::new(&segment));
+                        state.segment = Some(segment);
+                    }
+                    None => gst::warning!(
+                        CAT,
+                        imp = self,
+                        "Can't start a segment at frame {frame} with PTS {}",
+                        buffer.pts().display()
+                    ),
+                }
+            }
+
+            if settings.gap_frames.contains(&frame) {
+                self.post_action("gap", frame, buffer, |s| {
+                    s.field("duration", buffer.duration())
+                });
+                if let Some(pts) = buffer.pts() {
+                    events.push(
+                        gst::event::Gap::builder(pts)
+                            .duration(buffer.duration())
+                            .build(),
+                    );
+                }
+                return (events, false);
+            }
+
+            if std::mem::take(&mut state.discont_pending) {
+                buffer.set_flags(gst::BufferFlags::DISCONT);
+            }
+            (events, true)
+        }
+
+        /// Post what happened to input buffer `frame` on the bus, with its new timing.
+        fn post_action(
+            &self,
+            action: &str,
+            frame: u64,
+            buffer: &gst::BufferRef,
+            extra: impl FnOnce(gst::structure::Builder) -> gst::structure::Builder,
+        ) {
+            gst::debug!(CAT, imp = self, "{action} at frame {frame}");
+            let structure = gst::Structure::builder("timestampimpair")
+                .field("action", action)
+                .field("frame", frame)
+                .field("offset", buffer.offset())
+                .field("pts", buffer.pts());
+            let structure = extra(structure).build();
+            let obj = self.obj();
+            let _ = obj.post_message(gst::message::Element::builder(structure).src(&*obj).build());
+        }
+    }
+
+    /// `time` moved by `by` nanoseconds, stopping at 0.
+    fn shift(time: Option<gst::ClockTime>, by: i64) -> Option<gst::ClockTime> {
+        time.map(|time| {
+            gst::ClockTime::from_nseconds(
+                time.nseconds()
+                    .saturating_add_signed(by)
+                    .min(gst::ClockTime::MAX.nseconds()),
+            )
+        })
+    }
+
+    /// A segment starting at `pts` that carries on the running and stream time
+    /// of `segment`, so that only the event itself is new to downstream.
+    fn next_segment(
+        segment: Option<&gst::FormattedSegment<gst::ClockTime>>,
+        pts: Option<gst::ClockTime>,
+    ) -> Option<gst::FormattedSegment<gst::ClockTime>> {
+        let segment = segment?;
+        let pts = pts?;
+        let running_time = segment.to_running_time(pts)?;
+        let stream_time = segment.to_stream_time(pts)?;
+        let mut next = segment.clone();
+        next.set_base(running_time);
+        next.set_start(pts);
+        next.set_time(stream_time);
+        next.set_position(pts);
+        Some(next)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const FRAME: gst::ClockTime = gst::ClockTime::from_mseconds(40);
+
+        /// The events sent ahead of an input buffer, and the buffer if it is sent.
+        type Output = (Vec<gst::Event>, Option<gst::Buffer>);
+
+        /// Run `frames` 40ms buffers through a `timestampImpair` with the given
+        /// properties and a segment from 0. Returns each buffer that would be
+        /// sent with the events ahead of it, and the actions posted on the bus.
+        fn run_plugin_tests(
+            properties: &[(&str, &dyn ToValue)],
+            frames: u64,
+        ) -> (Vec<Output>, Vec<gst::Structure>) {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::TimestampImpair>();
+            for (name, value) in properties {
+                element.set_property_from_value(name, &value.to_value());
+            }
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+
+            let plugin = element.imp();
+            plugin.state.lock().unwrap().segment = Some(gst::FormattedSegment::new());
+            let mut out = vec![];
+            for frame in 0..frames {
+                let mut buffer = gst::Buffer::with_size(1).unwrap();
+                let buffer_mut = buffer.get_mut().unwrap();
+                buffer_mut.set_offset(frame);
+                buffer_mut.set_pts(FRAME * frame);
+                buffer_mut.set_dts(FRAME * frame);
+                buffer_mut.set_duration(FRAME);
+                let (events, keep) = plugin.impair(buffer_mut);
+                out.push((events, keep.then_some(buffer)));
+            }
+
+            let mut actions = vec![];
+            while let Some(message) = bus.pop_filtered(&[gst::MessageType::Element]) {
+                actions.push(message.structure().unwrap().to_owned());
+            }
+            (out, actions)
+        }
+
+        fn pts_ms(out: &[Output]) -> Vec<Option<u64>> {
+            out.iter()
+                .map(|(_, buffer)| buffer.as_ref().map(|b| b.pts().unwrap().mseconds()))
+                .collect()
+        }
+
+        #[test]
+        fn test_no_impairment() {
+            let (out, actions) = run_plugin_tests(&[], 3);
+            assert_eq!(pts_ms(&out), [Some(0), Some(40), Some(80)]);
+            assert!(out.iter().all(|(events, _)| events.is_empty()));
+            assert!(actions.is_empty());
+        }
+
+        #[test]
+        fn test_jitter_is_bounded_and_seeded() {
+            let limit = gst::ClockTime::from_mseconds(5);
+            let run = |seed: u64| {
+                let (out, _) =
+                    run_plugin_tests(&[("pts-jitter", &limit.nseconds()), ("seed", &seed)], 200);
+                out.into_iter()
+                    .map(|(_, buffer)| buffer.unwrap())
+                    .enumerate()
+                    .map(|(frame, buffer)| {
+                        // Only the PTS moves.
+                        assert_eq!(buffer.dts(), Some(FRAME * frame as u64));
+                        assert_eq!(buffer.duration(), Some(FRAME));
+                        buffer.pts().unwrap().nseconds() as i64
+                            - (FRAME * frame as u64).nseconds() as i64
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let first = run(3);
+            assert_eq!(first, run(3));
+            assert_ne!(first, run(4));
+            // The first frame can't go below 0.
+            assert!(first[0] >= 0);
+            assert!(first.iter().all(|j| j.unsigned_abs() <= limit.nseconds()));
+            assert!(
+                first
+                    .iter()
+                    .filter(|j| j.unsigned_abs() > limit.nseconds() / 2)
+                    .count()
+                    > 50
+            );
+        }
+
+        #[test]
+        fn test_gap() {
+            let (out, actions) = run_plugin_tests(&[("gap-frames", &"1")], 3);
+            assert_eq!(pts_ms(&out), [Some(0), None, Some(80)]);
+            let gst::EventView::Gap(gap) = out[1].0[0].view() else {
+                panic!("expected a gap, got {:?}", out[1].0);
+            };
+            assert_eq!(gap.get(), (FRAME, Some(FRAME)));
+            assert_eq!(actions.len(), 1);
+            assert_eq!(actions[0].get::<String>("action").unwrap(), "gap");
+            assert_eq!(actions[0].get::<u64>("offset").unwrap(), 1);
+        }
+
+        #[test]
+        fn test_discont_jump() {
+            let (out, actions) = run_plugin_tests(
+                &[
+                    ("discont-frames", &"2"),
+                    ("discont-jump", &1_000_000_000i64),
+                ],
+                4,
+            );
+            assert_eq!(pts_ms(&out), [Some(0), Some(40), Some(1080), Some(1120)]);
+            let flagged = out
+                .iter()
+                .map(|(_, b)| {
+                    b.as_ref()
+                        .unwrap()
+                        .flags()
+                        .contains(gst::BufferFlags::DISCONT)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(flagged, [false, false, true, false]);
+            assert_eq!(actions[0].get::<i64>("jump").unwrap(), 1_000_000_000);
+        }
+
+        #[test]
+        fn test_discont_on_gap_flags_next_buffer() {
+            let (out, _) = run_plugin_tests(&[("discont-frames", &"1"), ("gap-frames", &"1")], 3);
+            assert!(out[1].1.is_none());
+            assert!(out[2]
+                .1
+                .as_ref()
+                .unwrap()
+                .flags()
+                .contains(gst::BufferFlags::DISCONT));
+        }
+
+        #[test]
+        fn test_segment_keeps_running_time() {
+            let (out, actions) = run_plugin_tests(&[("segment-frames", &"2")], 3);
+            assert_eq!(pts_ms(&out), [Some(0), Some(40), Some(80)]);
+            let gst::EventView::Segment(segment) = out[2].0[0].view() else {
+                panic!("expected a segment, got {:?}", out[2].0);
+            };
+            let segment = segment.segment().downcast_ref::<gst::ClockTime>().unwrap();
+            assert_eq!(segment.start(), Some(FRAME * 2));
+            assert_eq!(segment.base(), Some(FRAME * 2));
+            assert_eq!(segment.to_running_time(FRAME * 2), Some(FRAME * 2));
+            assert_eq!(actions[0].get::<String>("action").unwrap(), "segment");
+        }
+
+        #[test]
+        fn test_frames_property_round_trip() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::TimestampImpair>();
+            element.set_property("segment-frames", "9, 2,,y");
+            assert_eq!(element.property::<String>("segment-frames"), "2,9");
+            element.set_property("dts-jitter", 7u64);
+            assert_eq!(element.property::<u64>("dts-jitter"), 7);
+        }
+    }
+}
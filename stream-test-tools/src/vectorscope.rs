@@ -0,0 +1,533 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct Vectorscope(ObjectSubclass<imp::Vectorscope>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "vectorscope",
+        gst::Rank::NONE,
+        Vectorscope::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::VideoFrameExt;
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use crate::scope::{self, Canvas, Hits, Levels};
+    use crate::yuv_offset;
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "vectorscope",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Vectorscope"),
+        )
+    });
+
+    const DEFAULT_SIZE: u32 = 256;
+    const DEFAULT_INTENSITY: f64 = 1.0;
+    const DEFAULT_GRATICULE: bool = true;
+    const DEFAULT_LEGAL_RANGE: bool = true;
+
+    const TRACE: [u8; 3] = [255, 255, 255];
+
+    /// The colours of the bars, in the order they go round the scope.
+    const BARS: [[f32; 3]; 6] = [
+        [1.0, 0.0, 0.0],
+        [1.0, 0.0, 1.0],
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+    ];
+
+    /// The angle of the skin tone line, anticlockwise from the Cb axis.
+    const SKIN_TONE_DEGREES: f32 = 123.0;
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        size: u32,
+        intensity: f64,
+        graticule: bool,
+        legal_range: bool,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                size: DEFAULT_SIZE,
+                intensity: DEFAULT_INTENSITY,
+                graticule: DEFAULT_GRATICULE,
+                legal_range: DEFAULT_LEGAL_RANGE,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct Vectorscope {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for Vectorscope {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecUInt::builder("size")
+                        .nick("size")
+                        .blurb("Width and height of the graph, across which every code of the input is spread")
+                        .minimum(16)
+                        .default_value(DEFAULT_SIZE)
+                        .mutable_ready()
+                        .build(),
+                    glib::ParamSpecDouble::builder("intensity")
+                        .nick("intensity")
+                        .blurb("Brightness of the trace, where 1 fully lights a frame spread evenly over every point")
+                        .minimum(0.0)
+                        .default_value(DEFAULT_INTENSITY)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecBoolean::builder("graticule")
+                        .nick("graticule")
+                        .blurb("Draw the axes, targets for 75% and 100% bars and the skin tone line")
+                        .default_value(DEFAULT_GRATICULE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecBoolean::builder("legal-range")
+                        .nick("legal-range")
+                        .blurb("Outline the legal chroma of the caps' range and shade the codes outside it")
+                        .default_value(DEFAULT_LEGAL_RANGE)
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "size" => settings.size = value.get().expect("type checked upstream"),
+                "intensity" => settings.intensity = value.get().expect("type checked upstream"),
+                "graticule" => settings.graticule = value.get().expect("type checked upstream"),
+                "legal-range" => settings.legal_range = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "size" => settings.size.to_value(),
+                "intensity" => settings.intensity.to_value(),
+                "graticule" => settings.graticule.to_value(),
+                "legal-range" => settings.legal_range.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for Vectorscope {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Vectorscope {
+        const NAME: &'static str = "vectorscope";
+        type Type = super::Vectorscope;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for Vectorscope {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::NeverInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn transform_caps(
+            &self,
+            direction: gst::PadDirection,
+            caps: &gst::Caps,
+            filter: Option<&gst::Caps>,
+        ) -> Option<gst::Caps> {
+            let size = self.settings.lock().unwrap().size;
+            scope::transform_caps(direction, caps, filter, size, size)
+        }
+    }
+
+    impl ElementImpl for Vectorscope {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Vectorscope Tool",
+                    "Filter/Analyzer/Video",
+                    "Graph the chroma of every sample with Cb across and Cr up.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(scope::OUTPUT_FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(scope::formats())
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for Vectorscope {
+        fn transform_frame(
+            &self,
+            in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+            out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let mut canvas = Canvas::new(out_frame.width() as usize, out_frame.height() as usize);
+            render(in_frame, &settings, &mut canvas);
+            canvas.write(out_frame);
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    /// Where codes land on a square graph, with Cb increasing to the right and Cr
+    /// increasing upwards.
+    #[derive(Debug, Clone, Copy)]
+    struct Axes {
+        depth: u32,
+        size: usize,
+        max: f32,
+    }
+
+    impl Axes {
+        fn point(&self, cb: u32, cr: u32) -> (usize, usize) {
+            let x = ((cb as u64 * self.size as u64) >> self.depth) as usize;
+            (
+                x.min(self.size - 1),
+                scope::level_row(cr, self.depth, self.size),
+            )
+        }
+
+        /// Where unrounded, unclipped codes land.
+        fn point_of(&self, cb: f32, cr: f32) -> (usize, usize) {
+            let code = |c: f32| c.round().clamp(0.0, self.max) as u32;
+            self.point(code(cb), code(cr))
+        }
+    }
+
+    /// Draw the legal range, the graticule and the trace of `frame` on `canvas`.
+    fn render(
+        frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+        settings: &Settings,
+        canvas: &mut Canvas,
+    ) {
+        let levels = Levels::new(frame.info());
+        let size = canvas.width.min(canvas.height);
+        let axes = Axes {
+            depth: levels.depth,
+            size,
+            max: levels.max() as f32,
+        };
+        let (left, top) = axes.point(levels.chroma_min, levels.chroma_max);
+        let (right, bottom) = axes.point(levels.chroma_max, levels.chroma_min);
+        let centre = axes.point(levels.chroma_zero(), levels.chroma_zero());
+
+        if settings.legal_range {
+            canvas.fill((0, 0), (size, size), scope::ILLEGAL);
+            canvas.fill((left, top), (right + 1, bottom + 1), scope::BACKGROUND);
+        }
+        if settings.graticule {
+            canvas.fill(
+                (centre.0, top),
+                (centre.0 + 1, bottom + 1),
+                scope::GRATICULE,
+            );
+            canvas.fill(
+                (left, centre.1),
+                (right + 1, centre.1 + 1),
+                scope::GRATICULE,
+            );
+
+            let weights = scope::luma_weights(frame.info());
+            let target = |rgb: [f32; 3], amplitude: f32| {
+                let (cb, cr) = scope::chroma(rgb.map(|c| c * amplitude), weights);
+                axes.point_of(levels.chroma_code(cb), levels.chroma_code(cr))
+            };
+            // A box round each 75% bar and a dot on each 100% one.
+            let half = (size / 64).max(2);
+            for bar in BARS {
+                let (x, y) = target(bar, 0.75);
+                let from = (x.saturating_sub(half), y.saturating_sub(half));
+                canvas.outline(from, (x + half + 1, y + half + 1), scope::GRATICULE);
+                let (x, y) = target(bar, 1.0);
+                canvas.fill(
+                    (x.saturating_sub(1), y.saturating_sub(1)),
+                    (x + 2, y + 2),
+                    scope::GRATICULE,
+                );
+            }
+
+            let (sin, cos) = SKIN_TONE_DEGREES.to_radians().sin_cos();
+            let radius = (right - left) as f32 / 2.0;
+            for step in 0..radius as usize {
+                let x = centre.0 as f32 + step as f32 * cos;
+                let y = centre.1 as f32 - step as f32 * sin;
+                let (x, y) = (x.round() as usize, y.round() as usize);
+                canvas.fill((x, y), (x + 1, y + 1), scope::GRATICULE);
+            }
+        }
+        if settings.legal_range && (left, top) != (0, 0) {
+            canvas.outline((left, top), (right + 1, bottom + 1), scope::LEGAL);
+        }
+
+        let cb = yuv_offset::component_samples(frame, 1);
+        let cr = yuv_offset::component_samples(frame, 2);
+        let mut hits = Hits::new(size, size);
+        for (&cb, &cr) in cb.iter().zip(&cr) {
+            let (x, y) = axes.point(cb as u32, cr as u32);
+            hits.add(x, y);
+        }
+        // A frame spread evenly over every point is fully lit at intensity 1.
+        let scale = settings.intensity * (size * size) as f64 / cb.len().max(1) as f64;
+        hits.draw(canvas, 0, TRACE, scale);
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use gst_video::VideoFormat;
+        use test::Bencher;
+
+        fn new_element(settings: Settings) -> super::super::Vectorscope {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::Vectorscope>();
+            *element.imp().settings.lock().unwrap() = settings;
+            element
+        }
+
+        /// BT.709 limited range, whatever the size.
+        fn info(format: VideoFormat, width: u32, height: u32) -> gst_video::VideoInfo {
+            let _ = gst::init();
+            let colorimetry = gst_video::VideoColorimetry::new(
+                gst_video::VideoColorRange::Range16_235,
+                gst_video::VideoColorMatrix::Bt709,
+                gst_video::VideoTransferFunction::Bt709,
+                gst_video::VideoColorPrimaries::Bt709,
+            );
+            gst_video::VideoInfo::builder(format, width, height)
+                .colorimetry(&colorimetry)
+                .build()
+                .unwrap()
+        }
+
+        /// An I420 frame of 4x2 with the same `y`, `u` and `v` everywhere.
+        fn flat_i420(y: u8, u: u8, v: u8) -> (gst_video::VideoInfo, Vec<u8>) {
+            let info = info(VideoFormat::I420, 4, 2);
+            let mut data = vec![y; info.size()];
+            data[info.offset()[1]..info.offset()[2]].fill(u);
+            data[info.offset()[2]..].fill(v);
+            (info, data)
+        }
+
+        fn run_element(
+            settings: Settings,
+            info: &gst_video::VideoInfo,
+            data: Vec<u8>,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let element = new_element(settings);
+            let inframe =
+                gst_video::VideoFrame::from_buffer_readable(gst::Buffer::from_slice(data), info)
+                    .unwrap();
+            let out_info =
+                gst_video::VideoInfo::builder(VideoFormat::Rgb, settings.size, settings.size)
+                    .build()
+                    .unwrap();
+            let buffer = gst::Buffer::with_size(out_info.size()).unwrap();
+            let mut outframe =
+                gst_video::VideoFrame::from_buffer_writable(buffer, &out_info).unwrap();
+            element
+                .imp()
+                .transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+                .unwrap();
+            outframe
+        }
+
+        fn pixel(
+            frame: &gst_video::VideoFrame<gst_video::video_frame::Writable>,
+            x: usize,
+            y: usize,
+        ) -> [u8; 3] {
+            let offset = y * frame.plane_stride()[0] as usize + x * 3;
+            frame.plane_data(0).unwrap()[offset..offset + 3]
+                .try_into()
+                .unwrap()
+        }
+
+        fn trace_only() -> Settings {
+            Settings {
+                graticule: false,
+                legal_range: false,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_grey_is_centred() {
+            let (info, data) = flat_i420(180, 128, 128);
+            let frame = run_element(trace_only(), &info, data);
+            for y in 0..256 {
+                for x in 0..256 {
+                    let expected = if (x, y) == (128, 127) {
+                        TRACE
+                    } else {
+                        scope::BACKGROUND
+                    };
+                    assert_eq!(pixel(&frame, x, y), expected, "({x}, {y})");
+                }
+            }
+        }
+
+        #[test]
+        fn test_ten_bit() {
+            let info = info(VideoFormat::I42010le, 4, 2);
+            let mut data = vec![0u16; info.size() / 2];
+            let chroma = info.offset()[1] / 2;
+            data[chroma..].fill(1020);
+            let data = data.iter().flat_map(|s| s.to_le_bytes()).collect();
+            let frame = run_element(trace_only(), &info, data);
+            assert_eq!(pixel(&frame, 255, 0), TRACE);
+        }
+
+        #[test]
+        fn test_red_lands_on_its_target() {
+            // BT.709 red, Cb 102 and Cr 240.
+            let (info, data) = flat_i420(63, 102, 240);
+            let frame = run_element(Settings::default(), &info, data);
+            assert_eq!(pixel(&frame, 102, 255 - 240), TRACE);
+            // The dot of the 100% target surrounds the trace, which is on the
+            // top edge of the legal range.
+            assert_eq!(pixel(&frame, 101, 255 - 239), scope::GRATICULE);
+            assert_eq!(pixel(&frame, 103, 255 - 241), scope::GRATICULE);
+            assert_eq!(pixel(&frame, 101, 255 - 240), scope::LEGAL);
+        }
+
+        #[test]
+        fn test_graticule_and_legal_range() {
+            let (info, data) = flat_i420(128, 128, 128);
+            let frame = run_element(Settings::default(), &info, data);
+            assert_eq!(pixel(&frame, 0, 0), scope::ILLEGAL);
+            assert_eq!(pixel(&frame, 255, 255), scope::ILLEGAL);
+            // Legal chroma is 16 to 240, so the box is from 16 to 240 across and
+            // from 255 - 240 to 255 - 16 down.
+            assert_eq!(pixel(&frame, 16, 100), scope::LEGAL);
+            assert_eq!(pixel(&frame, 240, 100), scope::LEGAL);
+            assert_eq!(pixel(&frame, 100, 15), scope::LEGAL);
+            assert_eq!(pixel(&frame, 100, 239), scope::LEGAL);
+            assert_eq!(pixel(&frame, 128, 60), scope::GRATICULE);
+            assert_eq!(pixel(&frame, 60, 127), scope::GRATICULE);
+            assert_eq!(pixel(&frame, 60, 60), scope::BACKGROUND);
+        }
+
+        #[test]
+        fn test_full_range_has_no_illegal_chroma() {
+            let colorimetry = gst_video::VideoColorimetry::new(
+                gst_video::VideoColorRange::Range0_255,
+                gst_video::VideoColorMatrix::Bt709,
+                gst_video::VideoTransferFunction::Bt709,
+                gst_video::VideoColorPrimaries::Bt709,
+            );
+            let _ = gst::init();
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2)
+                .colorimetry(&colorimetry)
+                .build()
+                .unwrap();
+            let frame = run_element(Settings::default(), &info, vec![128; info.size()]);
+            assert_eq!(pixel(&frame, 0, 0), scope::BACKGROUND);
+            assert_eq!(pixel(&frame, 255, 255), scope::BACKGROUND);
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let settings = Settings::default();
+            let element = new_element(test::black_box(settings));
+
+            let info = info(VideoFormat::I420, width, height);
+            let data_frame = (0..info.size())
+                .map(|i| (i * 97 + 13) as u8)
+                .collect::<Vec<_>>();
+            let inframe = test::black_box(
+                gst_video::VideoFrame::from_buffer_readable(
+                    gst::Buffer::from_slice(data_frame),
+                    &info,
+                )
+                .unwrap(),
+            );
+            let out_info =
+                gst_video::VideoInfo::builder(VideoFormat::I420, settings.size, settings.size)
+                    .build()
+                    .unwrap();
+            let buffer = gst::Buffer::with_size(out_info.size()).unwrap();
+            let mut outframe =
+                gst_video::VideoFrame::from_buffer_writable(buffer, &out_info).unwrap();
+
+            b.iter(|| {
+                element.imp().transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+            });
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
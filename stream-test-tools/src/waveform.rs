@@ -0,0 +1,569 @@
+use gst::glib;
+use gst::prelude::*;
+
+glib::wrapper! {
+    pub struct Waveform(ObjectSubclass<imp::Waveform>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstWaveformMode")]
+pub enum Mode {
+    #[enum_value(name = "Luma across the whole width", nick = "luma")]
+    Luma = 0,
+    #[enum_value(
+        name = "R', G' and B' side by side, decoded with the matrix in the caps",
+        nick = "rgb-parade"
+    )]
+    RgbParade = 1,
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "waveform",
+        gst::Rank::NONE,
+        Waveform::static_type(),
+    )
+}
+
+mod imp {
+
+    use gst::glib;
+    use gst::prelude::*;
+    use gst_video::subclass::prelude::*;
+    use gst_video::VideoFrameExt;
+
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use super::Mode;
+    use crate::scope::{self, Canvas, Hits, Levels};
+    use crate::yuv_offset;
+
+    #[allow(dead_code)]
+    static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+        gst::DebugCategory::new(
+            "waveform",
+            gst::DebugColorFlags::empty(),
+            Some("Rust Waveform"),
+        )
+    });
+
+    const DEFAULT_MODE: Mode = Mode::Luma;
+    const DEFAULT_WIDTH: u32 = 512;
+    const DEFAULT_HEIGHT: u32 = 256;
+    const DEFAULT_INTENSITY: f64 = 1.0;
+    const DEFAULT_GRATICULE: bool = true;
+    const DEFAULT_LEGAL_RANGE: bool = true;
+
+    const LUMA_TRACE: [u8; 3] = [96, 255, 96];
+    const RGB_TRACES: [[u8; 3]; 3] = [[255, 64, 64], [64, 255, 64], [64, 128, 255]];
+
+    #[derive(Debug, Clone, Copy)]
+    struct Settings {
+        mode: Mode,
+        width: u32,
+        height: u32,
+        intensity: f64,
+        graticule: bool,
+        legal_range: bool,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                mode: DEFAULT_MODE,
+                width: DEFAULT_WIDTH,
+                height: DEFAULT_HEIGHT,
+                intensity: DEFAULT_INTENSITY,
+                graticule: DEFAULT_GRATICULE,
+                legal_range: DEFAULT_LEGAL_RANGE,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct Waveform {
+        settings: Mutex<Settings>,
+    }
+
+    impl ObjectImpl for Waveform {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+                vec![
+                    glib::ParamSpecEnum::builder_with_default("mode", DEFAULT_MODE)
+                        .nick("mode")
+                        .blurb("What is graphed")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("width")
+                        .nick("width")
+                        .blurb("Width of the graph, across which the width of the frame is spread")
+                        .minimum(16)
+                        .default_value(DEFAULT_WIDTH)
+                        .mutable_ready()
+                        .build(),
+                    glib::ParamSpecUInt::builder("height")
+                        .nick("height")
+                        .blurb("Height of the graph, up which every code of the input is spread")
+                        .minimum(16)
+                        .default_value(DEFAULT_HEIGHT)
+                        .mutable_ready()
+                        .build(),
+                    glib::ParamSpecDouble::builder("intensity")
+                        .nick("intensity")
+                        .blurb("Brightness of the traces, where 1 fully lights a column spread evenly over every level")
+                        .minimum(0.0)
+                        .default_value(DEFAULT_INTENSITY)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecBoolean::builder("graticule")
+                        .nick("graticule")
+                        .blurb("Draw a line every 10% from black to white")
+                        .default_value(DEFAULT_GRATICULE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecBoolean::builder("legal-range")
+                        .nick("legal-range")
+                        .blurb("Mark the black and white levels of the caps' range and shade the levels outside them")
+                        .default_value(DEFAULT_LEGAL_RANGE)
+                        .mutable_playing()
+                        .build(),
+                ]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "mode" => settings.mode = value.get().expect("type checked upstream"),
+                "width" => settings.width = value.get().expect("type checked upstream"),
+                "height" => settings.height = value.get().expect("type checked upstream"),
+                "intensity" => settings.intensity = value.get().expect("type checked upstream"),
+                "graticule" => settings.graticule = value.get().expect("type checked upstream"),
+                "legal-range" => settings.legal_range = value.get().expect("type checked upstream"),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "mode" => settings.mode.to_value(),
+                "width" => settings.width.to_value(),
+                "height" => settings.height.to_value(),
+                "intensity" => settings.intensity.to_value(),
+                "graticule" => settings.graticule.to_value(),
+                "legal-range" => settings.legal_range.to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for Waveform {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Waveform {
+        const NAME: &'static str = "waveform";
+        type Type = super::Waveform;
+        type ParentType = gst_video::VideoFilter;
+    }
+
+    impl BaseTransformImpl for Waveform {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::NeverInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn transform_caps(
+            &self,
+            direction: gst::PadDirection,
+            caps: &gst::Caps,
+            filter: Option<&gst::Caps>,
+        ) -> Option<gst::Caps> {
+            let settings = *self.settings.lock().unwrap();
+            scope::transform_caps(direction, caps, filter, settings.width, settings.height)
+        }
+    }
+
+    impl ElementImpl for Waveform {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "Waveform Tool",
+                    "Filter/Analyzer/Video",
+                    "Graph the levels of each column as a luma or RGB parade waveform.",
+                    "Joseph Briggs",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(scope::OUTPUT_FORMATS)
+                    .build();
+                let src_pad_template = gst::PadTemplate::new(
+                    "src",
+                    gst::PadDirection::Src,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                let caps = gst_video::VideoCapsBuilder::new()
+                    .format_list(scope::formats())
+                    .build();
+                let sink_pad_template = gst::PadTemplate::new(
+                    "sink",
+                    gst::PadDirection::Sink,
+                    gst::PadPresence::Always,
+                    &caps,
+                )
+                .unwrap();
+
+                vec![src_pad_template, sink_pad_template]
+            });
+
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl VideoFilterImpl for Waveform {
+        fn transform_frame(
+            &self,
+            in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+            out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+            let mut canvas = Canvas::new(out_frame.width() as usize, out_frame.height() as usize);
+            render(in_frame, &settings, &mut canvas);
+            canvas.write(out_frame);
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+
+    /// Draw the graticule, the legal range and the traces of `frame` on `canvas`.
+    fn render(
+        frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+        settings: &Settings,
+        canvas: &mut Canvas,
+    ) {
+        let levels = Levels::new(frame.info());
+        let (width, height) = (canvas.width, canvas.height);
+        let row = |code: u32| scope::level_row(code, levels.depth, height);
+        let sections = match settings.mode {
+            Mode::Luma => 1,
+            Mode::RgbParade => 3,
+        };
+        let section_width = width / sections;
+
+        if settings.legal_range {
+            canvas.fill((0, 0), (width, row(levels.white)), scope::ILLEGAL);
+            canvas.fill((0, row(levels.black) + 1), (width, height), scope::ILLEGAL);
+        }
+        if settings.graticule {
+            for step in 0..=10 {
+                let code = levels.luma_code(step as f32 / 10.0).round() as u32;
+                canvas.hline(row(code), scope::GRATICULE);
+            }
+            for section in 1..sections {
+                canvas.vline(section * section_width, scope::GRATICULE);
+            }
+        }
+        if settings.legal_range {
+            canvas.hline(row(levels.black), scope::LEGAL);
+            canvas.hline(row(levels.white), scope::LEGAL);
+        }
+
+        let (in_width, in_height) = (frame.width() as usize, frame.height() as usize);
+        let luma = yuv_offset::component_samples(frame, 0);
+        // The graph columns each sample lands in, which is more than one when the
+        // graph is wider than the frame.
+        let columns = |i: usize| {
+            let x = i % in_width;
+            let first = x * section_width / in_width;
+            first..((x + 1) * section_width / in_width).max(first + 1)
+        };
+        // A column spread evenly over every level is fully lit at intensity 1.
+        let column_samples = in_height as f64 * (in_width as f64 / section_width as f64).max(1.0);
+        let scale = settings.intensity * height as f64 / column_samples;
+
+        match settings.mode {
+            Mode::Luma => {
+                let mut hits = Hits::new(section_width, height);
+                for (i, &y) in luma.iter().enumerate() {
+                    columns(i).for_each(|x| hits.add(x, row(y as u32)));
+                }
+                hits.draw(canvas, 0, LUMA_TRACE, scale);
+            }
+            Mode::RgbParade => {
+                let mut hits: [Hits; 3] = std::array::from_fn(|_| Hits::new(section_width, height));
+                let u = yuv_offset::component_samples(frame, 1);
+                let v = yuv_offset::component_samples(frame, 2);
+                let format_info = frame.format_info();
+                let (w_sub, h_sub) = (format_info.w_sub()[1], format_info.h_sub()[1]);
+                let chroma_width = frame.comp_width(1) as usize;
+                let (kr, kb) = scope::luma_weights(frame.info());
+                let luma_span = (levels.white - levels.black) as f32;
+                let chroma_span = (levels.chroma_max - levels.chroma_min) as f32;
+                let max = levels.max() as f32;
+
+                for (i, &y) in luma.iter().enumerate() {
+                    let (x, line) = (i % in_width, i / in_width);
+                    let c = (line >> h_sub) * chroma_width + (x >> w_sub);
+                    let y = (y as f32 - levels.black as f32) / luma_span;
+                    let cb = (u[c] as f32 - levels.chroma_zero() as f32) / chroma_span;
+                    let cr = (v[c] as f32 - levels.chroma_zero() as f32) / chroma_span;
+                    // Not clipped, so out of gamut colours leave the legal range.
+                    let r = y + 2.0 * (1.0 - kr) * cr;
+                    let b = y + 2.0 * (1.0 - kb) * cb;
+                    let g = (y - kr * r - kb * b) / (1.0 - kr - kb);
+                    for (hits, level) in hits.iter_mut().zip([r, g, b]) {
+                        let code = levels.luma_code(level).round().clamp(0.0, max);
+                        columns(i).for_each(|x| hits.add(x, row(code as u32)));
+                    }
+                }
+                for (section, (hits, colour)) in hits.iter().zip(RGB_TRACES).enumerate() {
+                    hits.draw(canvas, section * section_width, colour, scale);
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use gst_video::VideoFormat;
+        use test::Bencher;
+
+        fn new_element(settings: Settings) -> super::super::Waveform {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::Waveform>();
+            *element.imp().settings.lock().unwrap() = settings;
+            element
+        }
+
+        /// BT.709 limited range, whatever the size.
+        fn info(format: VideoFormat, width: u32, height: u32) -> gst_video::VideoInfo {
+            let colorimetry = gst_video::VideoColorimetry::new(
+                gst_video::VideoColorRange::Range16_235,
+                gst_video::VideoColorMatrix::Bt709,
+                gst_video::VideoTransferFunction::Bt709,
+                gst_video::VideoColorPrimaries::Bt709,
+            );
+            gst_video::VideoInfo::builder(format, width, height)
+                .colorimetry(&colorimetry)
+                .build()
+                .unwrap()
+        }
+
+        /// An I420 frame of 4x2 with the same `y`, `u` and `v` everywhere.
+        fn flat_i420(y: u8, u: u8, v: u8) -> (gst_video::VideoInfo, Vec<u8>) {
+            let _ = gst::init();
+            let info = info(VideoFormat::I420, 4, 2);
+            let mut data = vec![y; info.size()];
+            data[info.offset()[1]..info.offset()[2]].fill(u);
+            data[info.offset()[2]..].fill(v);
+            (info, data)
+        }
+
+        fn run_element(
+            settings: Settings,
+            info: &gst_video::VideoInfo,
+            data: Vec<u8>,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let element = new_element(settings);
+            let inframe =
+                gst_video::VideoFrame::from_buffer_readable(gst::Buffer::from_slice(data), info)
+                    .unwrap();
+            let out_info =
+                gst_video::VideoInfo::builder(VideoFormat::Rgb, settings.width, settings.height)
+                    .build()
+                    .unwrap();
+            let buffer = gst::Buffer::with_size(out_info.size()).unwrap();
+            let mut outframe =
+                gst_video::VideoFrame::from_buffer_writable(buffer, &out_info).unwrap();
+            element
+                .imp()
+                .transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+                .unwrap();
+            outframe
+        }
+
+        fn pixel(
+            frame: &gst_video::VideoFrame<gst_video::video_frame::Writable>,
+            x: usize,
+            y: usize,
+        ) -> [u8; 3] {
+            let offset = y * frame.plane_stride()[0] as usize + x * 3;
+            frame.plane_data(0).unwrap()[offset..offset + 3]
+                .try_into()
+                .unwrap()
+        }
+
+        /// Traces only, on a graph with a row for every 8-bit code.
+        fn traces_only(mode: Mode, width: u32) -> Settings {
+            Settings {
+                mode,
+                width,
+                height: 256,
+                graticule: false,
+                legal_range: false,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_luma_trace() {
+            let (info, data) = flat_i420(128, 128, 128);
+            let frame = run_element(traces_only(Mode::Luma, 8), &info, data);
+            for x in 0..8 {
+                for y in 0..256 {
+                    let expected = if y == 127 {
+                        LUMA_TRACE
+                    } else {
+                        scope::BACKGROUND
+                    };
+                    assert_eq!(pixel(&frame, x, y), expected, "({x}, {y})");
+                }
+            }
+        }
+
+        #[test]
+        fn test_ten_bit_levels() {
+            let _ = gst::init();
+            let info = info(VideoFormat::I42010le, 4, 2);
+            let data = (0..info.size() / 2)
+                .flat_map(|_| 512u16.to_le_bytes())
+                .collect();
+            let frame = run_element(traces_only(Mode::Luma, 8), &info, data);
+            assert_eq!(pixel(&frame, 3, 127), LUMA_TRACE);
+            assert_eq!(pixel(&frame, 3, 128), scope::BACKGROUND);
+        }
+
+        #[test]
+        fn test_rgb_parade() {
+            // Grey is the same level in each of R', G' and B'.
+            let (info, data) = flat_i420(128, 128, 128);
+            let frame = run_element(traces_only(Mode::RgbParade, 12), &info, data);
+            for (section, colour) in RGB_TRACES.iter().enumerate() {
+                for x in 0..4 {
+                    assert_eq!(pixel(&frame, section * 4 + x, 127), *colour);
+                }
+            }
+
+            // BT.709 red: R' at white, G' and B' at black.
+            let (info, data) = flat_i420(63, 102, 240);
+            let frame = run_element(traces_only(Mode::RgbParade, 12), &info, data);
+            assert_eq!(pixel(&frame, 0, 255 - 235), RGB_TRACES[0]);
+            for (section, colour) in RGB_TRACES.iter().enumerate().skip(1) {
+                let row = (0..256)
+                    .find(|&y| pixel(&frame, section * 4, y) == *colour)
+                    .unwrap();
+                assert!((238..=240).contains(&row), "{row}");
+            }
+        }
+
+        #[test]
+        fn test_graticule_and_legal_range() {
+            let (info, data) = flat_i420(128, 128, 128);
+            let settings = Settings {
+                width: 16,
+                ..Default::default()
+            };
+            let frame = run_element(settings, &info, data);
+            assert_eq!(pixel(&frame, 0, 0), scope::ILLEGAL);
+            assert_eq!(pixel(&frame, 0, 255), scope::ILLEGAL);
+            assert_eq!(pixel(&frame, 0, 255 - 235), scope::LEGAL);
+            assert_eq!(pixel(&frame, 0, 255 - 16), scope::LEGAL);
+            // 50% is code 125.5, rounded up.
+            assert_eq!(pixel(&frame, 0, 255 - 126), scope::GRATICULE);
+            assert_eq!(pixel(&frame, 0, 100), scope::BACKGROUND);
+        }
+
+        #[test]
+        fn test_transform_caps() {
+            let element = new_element(Settings {
+                width: 300,
+                height: 200,
+                ..Default::default()
+            });
+            let caps = gst_video::VideoCapsBuilder::new()
+                .format(VideoFormat::I42010le)
+                .width(1920)
+                .height(1080)
+                .build();
+            let src_caps = element
+                .imp()
+                .transform_caps(gst::PadDirection::Sink, &caps, None)
+                .unwrap();
+            let s = src_caps.structure(0).unwrap();
+            assert_eq!(s.get::<i32>("width").unwrap(), 300);
+            assert_eq!(s.get::<i32>("height").unwrap(), 200);
+            assert!(src_caps.can_intersect(
+                &gst_video::VideoCapsBuilder::new()
+                    .format(VideoFormat::Rgb)
+                    .build()
+            ));
+        }
+
+        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+            let settings = Settings {
+                mode: Mode::RgbParade,
+                ..Default::default()
+            };
+            let element = new_element(test::black_box(settings));
+
+            let info = info(VideoFormat::I420, width, height);
+            let data_frame = (0..info.size())
+                .map(|i| (i * 97 + 13) as u8)
+                .collect::<Vec<_>>();
+            let inframe = test::black_box(
+                gst_video::VideoFrame::from_buffer_readable(
+                    gst::Buffer::from_slice(data_frame),
+                    &info,
+                )
+                .unwrap(),
+            );
+            let out_info =
+                gst_video::VideoInfo::builder(VideoFormat::I420, settings.width, settings.height)
+                    .build()
+                    .unwrap();
+            let buffer = gst::Buffer::with_size(out_info.size()).unwrap();
+            let mut outframe =
+                gst_video::VideoFrame::from_buffer_writable(buffer, &out_info).unwrap();
+
+            b.iter(|| {
+                element.imp().transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+            });
+        }
+
+        #[bench]
+        fn bench_plugin_4k(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, b);
+        }
+    }
+}
@@ -1,10 +1,142 @@
 use gst::glib;
 use gst::prelude::*;
+use gst_video::{VideoFormat, VideoFrameExt};
 
 glib::wrapper! {
     pub struct YuvOffset(ObjectSubclass<imp::YuvOffset>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
 }
 
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstYuvOffsetOverflow")]
+pub enum Overflow {
+    #[enum_value(name = "Wrap around modulo 2^depth", nick = "wrap")]
+    Wrap = 0,
+    #[enum_value(
+        name = "Saturate to the legal range (16-235 luma, 16-240 chroma at 8 bits)",
+        nick = "saturate-legal"
+    )]
+    SaturateLegal = 1,
+    #[enum_value(name = "Saturate to the full range (0-255 at 8 bits)", nick = "saturate-full")]
+    SaturateFull = 2,
+    #[enum_value(name = "Reflect back off the ends of the range", nick = "reflect")]
+    Reflect = 3,
+}
+
+impl Overflow {
+    /// Bring an offset sample back into `0..=max`, where `max` is `2^depth - 1`.
+    /// `legal` is the legal range of the component the sample belongs to, and is
+    /// only used by `SaturateLegal`.
+    pub fn apply(self, value: i32, legal: (i32, i32), max: i32) -> i32 {
+        match self {
+            Overflow::Wrap => value & max,
+            Overflow::SaturateLegal => value.clamp(legal.0, legal.1),
+            Overflow::SaturateFull => value.clamp(0, max),
+            Overflow::Reflect => {
+                let period = 2 * max;
+                let folded = value.rem_euclid(period);
+                if folded > max {
+                    period - folded
+                } else {
+                    folded
+                }
+            }
+        }
+    }
+}
+
+/// The YUV formats yuvOffset works on, which the analysers that sit next to it
+/// accept too.
+pub const FORMATS: [VideoFormat; 30] = [
+    VideoFormat::I420,
+    VideoFormat::Yv12,
+    VideoFormat::Y41b,
+    VideoFormat::Y42b,
+    VideoFormat::Y444,
+    VideoFormat::Nv12,
+    VideoFormat::Nv21,
+    VideoFormat::I42010le,
+    VideoFormat::I42010be,
+    VideoFormat::I42012le,
+    VideoFormat::I42012be,
+    VideoFormat::I42210le,
+    VideoFormat::I42210be,
+    VideoFormat::I42212le,
+    VideoFormat::I42212be,
+    VideoFormat::Y44410le,
+    VideoFormat::Y44410be,
+    VideoFormat::Y44412le,
+    VideoFormat::Y44412be,
+    VideoFormat::Y44416le,
+    VideoFormat::Y44416be,
+    VideoFormat::P01010le,
+    VideoFormat::P01010be,
+    VideoFormat::P012Le,
+    VideoFormat::P012Be,
+    VideoFormat::P016Le,
+    VideoFormat::P016Be,
+    VideoFormat::Yuy2,
+    VideoFormat::Uyvy,
+    VideoFormat::V210,
+];
+
+/// The formats of [`FORMATS`] whose info passes `keep`, for the elements that
+/// only handle some of them.
+pub fn formats_where(keep: impl Fn(&gst_video::VideoFormatInfo) -> bool) -> Vec<VideoFormat> {
+    FORMATS
+        .into_iter()
+        .filter(|&format| keep(gst_video::VideoFormatInfo::from_format(format)))
+        .collect()
+}
+
+/// Running totals of the difference between the samples of one component of
+/// two frames.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ErrorSum {
+    squared: f64,
+    absolute: f64,
+    samples: u64,
+}
+
+impl ErrorSum {
+    pub fn add(&mut self, diff: f64) {
+        self.squared += diff * diff;
+        self.absolute += diff.abs();
+        self.samples += 1;
+    }
+
+    pub fn merge(&mut self, other: &ErrorSum) {
+        self.squared += other.squared;
+        self.absolute += other.absolute;
+        self.samples += other.samples;
+    }
+
+    pub fn mse(&self) -> f64 {
+        self.squared / self.samples.max(1) as f64
+    }
+
+    pub fn mae(&self) -> f64 {
+        self.absolute / self.samples.max(1) as f64
+    }
+
+    /// Infinite when the frames are identical.
+    pub fn psnr(&self, max: f64) -> f64 {
+        10.0 * (max * max / self.mse()).log10()
+    }
+}
+
+/// The error between every visible sample of each component of two frames with
+/// the same format and size, in one of the `FORMATS`.
+pub fn frame_errors(a: &impl VideoFrameExt, b: &impl VideoFrameExt) -> [ErrorSum; 3] {
+    imp::frame_errors(a, b, &imp::components(a.info()))
+}
+
+/// Every visible sample of component `comp` of a frame in one of the `FORMATS`,
+/// row by row, shifted down to the component's depth.
+pub fn component_samples(frame: &impl VideoFrameExt, comp: usize) -> Vec<u16> {
+    imp::component_samples(frame, comp)
+}
+
 pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     gst::Element::register(
         Some(plugin),
@@ -18,14 +150,18 @@ mod imp {
 
     use gst::glib;
     use gst::prelude::*;
-    use gst::State;
+    use gst_base::prelude::*;
     use gst_video::subclass::prelude::*;
-    use gst_video::VideoFrameExt;
+    use gst_video::{VideoFormat, VideoFrameExt};
 
-    use std::sync::Mutex;
+    use std::ops::Range;
+    use std::sync::{Mutex, MutexGuard};
 
     use once_cell::sync::Lazy;
 
+    use super::{ErrorSum, Overflow, FORMATS};
+    use crate::kernels;
+
     #[allow(dead_code)]
     static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
         gst::DebugCategory::new(
@@ -36,12 +172,80 @@ mod imp {
     });
 
     const DEFAULT_SHIFT: i32 = 0;
+    const DEFAULT_GAIN: f64 = 1.0;
+    const DEFAULT_GAMMA: f64 = 1.0;
+    const DEFAULT_OVERFLOW: Overflow = Overflow::Wrap;
+    const DEFAULT_ROI_POSITION: u32 = 0;
+    const DEFAULT_ROI_SIZE: u32 = 0;
+    const DEFAULT_ROI_INVERT: bool = false;
+    const DEFAULT_POST_METRICS: bool = false;
+    const DEFAULT_N_THREADS: u32 = 1;
+
+    /// Names used for each component in the metrics messages.
+    const COMPONENT_NAMES: [&str; 3] = ["y", "u", "v"];
+
+    const LEGAL_LUMA: (i32, i32) = (16, 235);
+    const LEGAL_CHROMA: (i32, i32) = (16, 240);
+
+    /// The component carried by each 10-bit sample of a 16 byte v210 block, which
+    /// packs six pixels into four little-endian words of three samples each.
+    const V210_COMPONENTS: [usize; 12] = [1, 0, 2, 0, 1, 0, 2, 0, 1, 0, 2, 0];
+    /// Which of the block's six luma or three chroma samples each sample is.
+    const V210_POSITIONS: [usize; 12] = [0, 0, 0, 1, 1, 2, 1, 3, 2, 4, 2, 5];
 
+    /// Where one component's samples sit in the rows of its plane, and what to do to them.
     #[derive(Debug, Clone, Copy)]
+    pub(super) struct Component {
+        plane: u32,
+        /// Byte offset of the first sample in a row.
+        poffset: usize,
+        /// Bytes from one sample to the next.
+        pixel_stride: usize,
+        /// How far the sample is shifted up within its container.
+        shift: u32,
+        /// log2 of the horizontal and vertical subsampling.
+        w_sub: u32,
+        h_sub: u32,
+        /// Number of samples in each row, and number of rows.
+        width: usize,
+        height: usize,
+    }
+
+    /// A rectangle in luma samples. The right and bottom edges are exclusive.
+    #[derive(Debug, Clone, Copy)]
+    struct Rect {
+        left: usize,
+        top: usize,
+        right: usize,
+        bottom: usize,
+    }
+
+    /// The rectangle, in luma samples, that the offsets are restricted to, and the
+    /// crop it sits within.
+    #[derive(Debug, Clone, Copy)]
+    struct Roi {
+        region: Rect,
+        crop: Rect,
+        invert: bool,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
     struct Settings {
         y: i32,
         u: i32,
         v: i32,
+        y_gain: f64,
+        u_gain: f64,
+        v_gain: f64,
+        y_gamma: f64,
+        overflow: Overflow,
+        roi_x: u32,
+        roi_y: u32,
+        roi_width: u32,
+        roi_height: u32,
+        roi_invert: bool,
+        post_metrics: bool,
+        n_threads: u32,
     }
 
     impl Default for Settings {
@@ -50,18 +254,105 @@ mod imp {
                 y: DEFAULT_SHIFT,
                 u: DEFAULT_SHIFT,
                 v: DEFAULT_SHIFT,
+                y_gain: DEFAULT_GAIN,
+                u_gain: DEFAULT_GAIN,
+                v_gain: DEFAULT_GAIN,
+                y_gamma: DEFAULT_GAMMA,
+                overflow: DEFAULT_OVERFLOW,
+                roi_x: DEFAULT_ROI_POSITION,
+                roi_y: DEFAULT_ROI_POSITION,
+                roi_width: DEFAULT_ROI_SIZE,
+                roi_height: DEFAULT_ROI_SIZE,
+                roi_invert: DEFAULT_ROI_INVERT,
+                post_metrics: DEFAULT_POST_METRICS,
+                n_threads: DEFAULT_N_THREADS,
             }
         }
     }
 
-    #[allow(dead_code)]
+    impl Settings {
+        /// Whether these settings leave every sample as it is. Saturating to the
+        /// legal range would still clip, and the metrics need to see every frame.
+        fn is_noop(&self) -> bool {
+            self.y == 0
+                && self.u == 0
+                && self.v == 0
+                && self.y_gain == 1.0
+                && self.u_gain == 1.0
+                && self.v_gain == 1.0
+                && self.y_gamma == 1.0
+                && self.overflow != Overflow::SaturateLegal
+                && !self.post_metrics
+        }
+    }
+
+    /// Per-component lookup tables, built for the settings and bit depth they hold.
+    struct State {
+        settings: Settings,
+        depth: u32,
+        luts: [Vec<u16>; 3],
+        steps: [Option<Step>; 3],
+    }
+
+    /// A plain offset of 8-bit samples, which the row kernels apply faster than
+    /// the lookup table when the gain and gamma of a component leave it alone.
+    #[derive(Debug, Clone, Copy)]
+    enum Step {
+        Wrap(u8),
+        Clamp { offset: i16, lo: u8, hi: u8 },
+    }
+
+    impl Step {
+        fn new(
+            offset: i32,
+            gain: f64,
+            gamma: f64,
+            legal: (i32, i32),
+            overflow: Overflow,
+        ) -> Option<Self> {
+            if gain != 1.0 || gamma != 1.0 {
+                return None;
+            }
+            // Anything past 255 either way saturates just the same.
+            let clamp = |lo: i32, hi: i32| Step::Clamp {
+                offset: offset.clamp(-255, 255) as i16,
+                lo: lo as u8,
+                hi: hi as u8,
+            };
+            match overflow {
+                Overflow::Wrap => Some(Step::Wrap(offset as u8)),
+                Overflow::SaturateLegal => Some(clamp(legal.0, legal.1)),
+                Overflow::SaturateFull => Some(clamp(0, 255)),
+                Overflow::Reflect => None,
+            }
+        }
+    }
+
+    /// Totals since the element started, for the summary posted at EOS.
+    #[derive(Debug, Default)]
+    struct Metrics {
+        frames: u64,
+        depth: u32,
+        errors: [ErrorSum; 3],
+    }
+
     #[derive(Default)]
     pub struct YuvOffset {
         settings: Mutex<Settings>,
         state: Mutex<Option<State>>,
+        metrics: Mutex<Metrics>,
     }
 
     impl ObjectImpl for YuvOffset {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let noop = self.settings.lock().unwrap().is_noop();
+            self.obj().set_passthrough(noop);
+            // Offset the input buffer itself whenever it is writable, rather than
+            // copying it into a new one first.
+            self.obj().set_in_place(true);
+        }
+
         fn properties() -> &'static [glib::ParamSpec] {
             static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
                 vec![
@@ -83,6 +374,85 @@ mod imp {
                         .default_value(DEFAULT_SHIFT)
                         .mutable_playing()
                         .build(),
+                    glib::ParamSpecDouble::builder("y-gain")
+                        .nick("y-gain")
+                        .blurb("Gain applied to the Y plane about mid-grey")
+                        .minimum(0.0)
+                        .maximum(100.0)
+                        .default_value(DEFAULT_GAIN)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecDouble::builder("u-gain")
+                        .nick("u-gain")
+                        .blurb("Gain applied to the U plane about neutral chroma")
+                        .minimum(0.0)
+                        .maximum(100.0)
+                        .default_value(DEFAULT_GAIN)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecDouble::builder("v-gain")
+                        .nick("v-gain")
+                        .blurb("Gain applied to the V plane about neutral chroma")
+                        .minimum(0.0)
+                        .maximum(100.0)
+                        .default_value(DEFAULT_GAIN)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecDouble::builder("y-gamma")
+                        .nick("y-gamma")
+                        .blurb("Gamma applied to the Y plane before the gain and offset")
+                        .minimum(0.01)
+                        .maximum(10.0)
+                        .default_value(DEFAULT_GAMMA)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecEnum::builder_with_default("overflow", DEFAULT_OVERFLOW)
+                        .nick("overflow")
+                        .blurb("How samples pushed outside of the range are handled")
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("roi-x")
+                        .nick("roi-x")
+                        .blurb("Left edge of the region of interest, in luma samples")
+                        .default_value(DEFAULT_ROI_POSITION)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("roi-y")
+                        .nick("roi-y")
+                        .blurb("Top edge of the region of interest, in luma samples")
+                        .default_value(DEFAULT_ROI_POSITION)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("roi-width")
+                        .nick("roi-width")
+                        .blurb("Width of the region of interest (0 = to the right edge)")
+                        .default_value(DEFAULT_ROI_SIZE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("roi-height")
+                        .nick("roi-height")
+                        .blurb("Height of the region of interest (0 = to the bottom edge)")
+                        .default_value(DEFAULT_ROI_SIZE)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecBoolean::builder("roi-invert")
+                        .nick("roi-invert")
+                        .blurb("Offset everything except the region of interest")
+                        .default_value(DEFAULT_ROI_INVERT)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecBoolean::builder("post-metrics")
+                        .nick("post-metrics")
+                        .blurb("Post the MSE, PSNR and MAE of every frame, and a summary at EOS, on the bus")
+                        .default_value(DEFAULT_POST_METRICS)
+                        .mutable_playing()
+                        .build(),
+                    glib::ParamSpecUInt::builder("n-threads")
+                        .nick("n-threads")
+                        .blurb("Number of threads to split each frame across, or 0 for one per CPU")
+                        .default_value(DEFAULT_N_THREADS)
+                        .mutable_playing()
+                        .build(),
                 ]
             });
 
@@ -105,8 +475,73 @@ mod imp {
                     let v = value.get().expect("type checked upstream");
                     settings.v = v;
                 }
+                "y-gain" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let y_gain = value.get().expect("type checked upstream");
+                    settings.y_gain = y_gain;
+                }
+                "u-gain" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let u_gain = value.get().expect("type checked upstream");
+                    settings.u_gain = u_gain;
+                }
+                "v-gain" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let v_gain = value.get().expect("type checked upstream");
+                    settings.v_gain = v_gain;
+                }
+                "y-gamma" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let y_gamma = value.get().expect("type checked upstream");
+                    settings.y_gamma = y_gamma;
+                }
+                "overflow" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let overflow = value.get().expect("type checked upstream");
+                    settings.overflow = overflow;
+                }
+                "roi-x" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let roi_x = value.get().expect("type checked upstream");
+                    settings.roi_x = roi_x;
+                }
+                "roi-y" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let roi_y = value.get().expect("type checked upstream");
+                    settings.roi_y = roi_y;
+                }
+                "roi-width" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let roi_width = value.get().expect("type checked upstream");
+                    settings.roi_width = roi_width;
+                }
+                "roi-height" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let roi_height = value.get().expect("type checked upstream");
+                    settings.roi_height = roi_height;
+                }
+                "roi-invert" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let roi_invert = value.get().expect("type checked upstream");
+                    settings.roi_invert = roi_invert;
+                }
+                "post-metrics" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let post_metrics = value.get().expect("type checked upstream");
+                    settings.post_metrics = post_metrics;
+                }
+                "n-threads" => {
+                    let mut settings = self.settings.lock().unwrap();
+                    let n_threads = value.get().expect("type checked upstream");
+                    settings.n_threads = n_threads;
+                }
                 _ => unimplemented!(),
             }
+
+            // Go back to (or leave) passthrough as soon as the settings stop (or
+            // start) changing anything, even while PLAYING.
+            let noop = self.settings.lock().unwrap().is_noop();
+            self.obj().set_passthrough(noop);
         }
 
         fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
@@ -123,6 +558,54 @@ mod imp {
                     let settings = self.settings.lock().unwrap();
                     settings.v.to_value()
                 }
+                "y-gain" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.y_gain.to_value()
+                }
+                "u-gain" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.u_gain.to_value()
+                }
+                "v-gain" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.v_gain.to_value()
+                }
+                "y-gamma" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.y_gamma.to_value()
+                }
+                "overflow" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.overflow.to_value()
+                }
+                "roi-x" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.roi_x.to_value()
+                }
+                "roi-y" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.roi_y.to_value()
+                }
+                "roi-width" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.roi_width.to_value()
+                }
+                "roi-height" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.roi_height.to_value()
+                }
+                "roi-invert" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.roi_invert.to_value()
+                }
+                "post-metrics" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.post_metrics.to_value()
+                }
+                "n-threads" => {
+                    let settings = self.settings.lock().unwrap();
+                    settings.n_threads.to_value()
+                }
                 _ => unimplemented!(),
             }
         }
@@ -139,9 +622,36 @@ mod imp {
 
     impl BaseTransformImpl for YuvOffset {
         const MODE: gst_base::subclass::BaseTransformMode =
-            gst_base::subclass::BaseTransformMode::NeverInPlace;
+            gst_base::subclass::BaseTransformMode::Both;
         const PASSTHROUGH_ON_SAME_CAPS: bool = false;
-        const TRANSFORM_IP_ON_PASSTHROUGH: bool = true;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            *self.metrics.lock().unwrap() = Metrics::default();
+            self.parent_start()
+        }
+
+        fn sink_event(&self, event: gst::Event) -> bool {
+            match event.view() {
+                gst::EventView::Eos(_) if self.settings.lock().unwrap().post_metrics => {
+                    let metrics = self.metrics.lock().unwrap();
+                    // With nothing measured there is no depth to give a PSNR.
+                    if metrics.frames > 0 {
+                        let max = ((1 << metrics.depth) - 1) as f64;
+                        let structure = metrics_structure("yuvoffset-summary", &metrics.errors, max)
+                            .field("frames", metrics.frames)
+                            .build();
+                        drop(metrics);
+                        self.post_structure(structure);
+                    }
+                }
+                gst::EventView::FlushStop(_) => {
+                    *self.metrics.lock().unwrap() = Metrics::default();
+                }
+                _ => (),
+            }
+            self.parent_sink_event(event)
+        }
     }
 
     impl ElementImpl for YuvOffset {
@@ -160,7 +670,7 @@ mod imp {
         fn pad_templates() -> &'static [gst::PadTemplate] {
             static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
                 let caps = gst_video::VideoCapsBuilder::new()
-                    .format_list([gst_video::VideoFormat::I420])
+                    .format_list(FORMATS)
                     .build();
                 let src_pad_template = gst::PadTemplate::new(
                     "src",
@@ -171,7 +681,7 @@ mod imp {
                 .unwrap();
 
                 let caps = gst_video::VideoCapsBuilder::new()
-                    .format_list([gst_video::VideoFormat::I420])
+                    .format_list(FORMATS)
                     .build();
                 let sink_pad_template = gst::PadTemplate::new(
                     "sink",
@@ -188,7 +698,459 @@ mod imp {
         }
     }
 
-    impl YuvOffset {}
+    impl YuvOffset {
+        /// Make sure the lookup tables match the current settings and bit depth.
+        fn update_state(&self, settings: &Settings, depth: u32) -> MutexGuard<'_, Option<State>> {
+            let mut state = self.state.lock().unwrap();
+            if !matches!(&*state, Some(state) if state.settings == *settings && state.depth == depth) {
+                *state = Some(State::new(settings, depth));
+            }
+            state
+        }
+
+        fn post_structure(&self, structure: gst::Structure) {
+            let obj = self.obj();
+            let _ = obj.post_message(gst::message::Element::builder(structure).src(&*obj).build());
+        }
+
+        /// Compare the input and output frames, post the result, and add it to the
+        /// totals for the summary.
+        fn post_frame_metrics(
+            &self,
+            in_frame: &gst_video::VideoFrameRef<&gst::BufferRef>,
+            out_frame: &gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) {
+            let errors = frame_errors(in_frame, out_frame, &components(in_frame.info()));
+            let depth = in_frame.format_info().depth()[0];
+            let max = ((1 << depth) - 1) as f64;
+
+            let mut metrics = self.metrics.lock().unwrap();
+            metrics.frames += 1;
+            metrics.depth = depth;
+            for (total, frame) in metrics.errors.iter_mut().zip(&errors) {
+                total.merge(frame);
+            }
+            drop(metrics);
+
+            let buffer = in_frame.buffer();
+            let structure = metrics_structure("yuvoffset-metrics", &errors, max)
+                .field("pts", buffer.pts())
+                .field("offset", buffer.offset())
+                .build();
+            self.post_structure(structure);
+        }
+    }
+
+    /// Start a metrics message with the MSE, PSNR and MAE of each component.
+    fn metrics_structure(name: &str, errors: &[ErrorSum; 3], max: f64) -> gst::structure::Builder {
+        let mut builder = gst::Structure::builder(name);
+        for (comp, error) in COMPONENT_NAMES.iter().zip(errors) {
+            builder = builder
+                .field(format!("{comp}-mse").as_str(), error.mse())
+                .field(format!("{comp}-psnr").as_str(), error.psnr(max))
+                .field(format!("{comp}-mae").as_str(), error.mae());
+        }
+        builder
+    }
+
+    /// Read sample `x` of component `index` from a row of its plane.
+    fn read_sample(
+        line: &[u8],
+        format_info: &gst_video::VideoFormatInfo,
+        components: &[Component],
+        index: usize,
+        x: usize,
+    ) -> i32 {
+        let comp = &components[index];
+        if format_info.format() == VideoFormat::V210 {
+            let per_block = 6 >> comp.w_sub;
+            let slot = (0..12)
+                .find(|&k| V210_COMPONENTS[k] == index && V210_POSITIONS[k] == x % per_block)
+                .unwrap();
+            let word = (x / per_block) * 16 + (slot / 3) * 4;
+            let packed = u32::from_le_bytes(line[word..word + 4].try_into().unwrap());
+            return ((packed >> (10 * (slot % 3))) & 0x3ff) as i32;
+        }
+
+        let i = comp.poffset + x * comp.pixel_stride;
+        if format_info.depth()[index] > 8 {
+            let bytes = [line[i], line[i + 1]];
+            let raw = if format_info.is_le() {
+                u16::from_le_bytes(bytes)
+            } else {
+                u16::from_be_bytes(bytes)
+            };
+            (raw >> comp.shift) as i32
+        } else {
+            line[i] as i32
+        }
+    }
+
+    pub(super) fn component_samples(frame: &impl VideoFrameExt, index: usize) -> Vec<u16> {
+        let format_info = frame.format_info();
+        let components = components(frame.info());
+        let comp = &components[index];
+        let stride = frame.plane_stride()[comp.plane as usize] as usize;
+        let plane = frame.plane_data(comp.plane).unwrap();
+        let mut samples = Vec::with_capacity(comp.width * comp.height);
+        for row in 0..comp.height {
+            let line = &plane[row * stride..];
+            for x in 0..comp.width {
+                samples.push(read_sample(line, format_info, &components, index, x) as u16);
+            }
+        }
+        samples
+    }
+
+    /// The error between every visible input and output sample of each component.
+    pub(super) fn frame_errors(
+        in_frame: &impl VideoFrameExt,
+        out_frame: &impl VideoFrameExt,
+        components: &[Component],
+    ) -> [ErrorSum; 3] {
+        let format_info = in_frame.format_info();
+        let mut errors = [ErrorSum::default(); 3];
+        for (index, comp) in components.iter().enumerate() {
+            let plane = comp.plane as usize;
+            let in_stride = in_frame.plane_stride()[plane] as usize;
+            let out_stride = out_frame.plane_stride()[plane] as usize;
+            let in_plane = in_frame.plane_data(comp.plane).unwrap();
+            let out_plane = out_frame.plane_data(comp.plane).unwrap();
+            for row in 0..comp.height {
+                let in_line = &in_plane[row * in_stride..];
+                let out_line = &out_plane[row * out_stride..];
+                for x in 0..comp.width {
+                    let input = read_sample(in_line, format_info, components, index, x);
+                    let output = read_sample(out_line, format_info, components, index, x);
+                    errors[index].add((output - input) as f64);
+                }
+            }
+        }
+        errors
+    }
+
+    impl State {
+        fn new(settings: &Settings, depth: u32) -> Self {
+            let lut = |offset, gain, gamma, legal| {
+                build_lut(offset, gain, gamma, legal_range(legal, depth), depth, settings.overflow)
+            };
+            let step = |offset, gain, gamma, legal| {
+                (depth == 8)
+                    .then(|| Step::new(offset, gain, gamma, legal, settings.overflow))
+                    .flatten()
+            };
+            Self {
+                settings: *settings,
+                depth,
+                luts: [
+                    lut(settings.y, settings.y_gain, settings.y_gamma, LEGAL_LUMA),
+                    lut(settings.u, settings.u_gain, 1.0, LEGAL_CHROMA),
+                    lut(settings.v, settings.v_gain, 1.0, LEGAL_CHROMA),
+                ],
+                steps: [
+                    step(settings.y, settings.y_gain, settings.y_gamma, LEGAL_LUMA),
+                    step(settings.u, settings.u_gain, 1.0, LEGAL_CHROMA),
+                    step(settings.v, settings.v_gain, 1.0, LEGAL_CHROMA),
+                ],
+            }
+        }
+    }
+
+    /// Scale an 8-bit legal range up to `depth` bits.
+    fn legal_range(range: (i32, i32), depth: u32) -> (i32, i32) {
+        (range.0 << (depth - 8), range.1 << (depth - 8))
+    }
+
+    /// Tabulate `gain * (gamma(in) - pivot) + pivot + offset` for every `depth`-bit
+    /// input, bringing the result back into range with `overflow`. The pivot is the
+    /// middle of the range, which is also neutral chroma.
+    fn build_lut(
+        offset: i32,
+        gain: f64,
+        gamma: f64,
+        legal: (i32, i32),
+        depth: u32,
+        overflow: Overflow,
+    ) -> Vec<u16> {
+        let max = (1 << depth) - 1;
+        let pivot = (1 << (depth - 1)) as f64;
+        (0..=max)
+            .map(|input| {
+                let value = (input as f64 / max as f64).powf(1.0 / gamma) * max as f64;
+                let value = (gain * (value - pivot) + pivot).round() as i32;
+                overflow.apply(value.wrapping_add(offset), legal, max) as u16
+            })
+            .collect()
+    }
+
+    impl Rect {
+        /// The whole of a `width` by `height` frame, or the part of it that a
+        /// `VideoCropMeta` on `buffer` leaves visible.
+        fn crop(buffer: &gst::BufferRef, width: usize, height: usize) -> Self {
+            let Some(meta) = buffer.meta::<gst_video::VideoCropMeta>() else {
+                return Self {
+                    left: 0,
+                    top: 0,
+                    right: width,
+                    bottom: height,
+                };
+            };
+            let (x, y, crop_width, crop_height) = meta.rect();
+            let left = (x as usize).min(width);
+            let top = (y as usize).min(height);
+            Self {
+                left,
+                top,
+                right: (left + crop_width as usize).min(width),
+                bottom: (top + crop_height as usize).min(height),
+            }
+        }
+
+        /// The columns and rows of a component that the rectangle covers, in the
+        /// component's own subsampled coordinates. Samples only partly covered at
+        /// the right and bottom edges are included.
+        fn scaled(&self, comp: &Component) -> (Range<usize>, Range<usize>) {
+            let scale_up = |v: usize, sub: u32| (v + (1 << sub) - 1) >> sub;
+            let right = scale_up(self.right, comp.w_sub).min(comp.width);
+            let left = (self.left >> comp.w_sub).min(right);
+            let bottom = scale_up(self.bottom, comp.h_sub).min(comp.height);
+            let top = (self.top >> comp.h_sub).min(bottom);
+            (left..right, top..bottom)
+        }
+    }
+
+    impl Roi {
+        /// Place the region from the settings within `crop`. Its position is
+        /// relative to the top left of the crop, and it never extends past it.
+        fn new(settings: &Settings, crop: Rect) -> Self {
+            // A size of 0 extends the region to the edge of the crop.
+            let extent = |offset: u32, size: u32, start: usize, end: usize| {
+                let region_start = (start + offset as usize).min(end);
+                let region_end = match size {
+                    0 => end,
+                    size => (region_start + size as usize).min(end),
+                };
+                (region_start, region_end)
+            };
+            let (left, right) = extent(settings.roi_x, settings.roi_width, crop.left, crop.right);
+            let (top, bottom) = extent(settings.roi_y, settings.roi_height, crop.top, crop.bottom);
+            Self {
+                region: Rect {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                },
+                crop,
+                invert: settings.roi_invert,
+            }
+        }
+
+        /// The columns of `row` of a component that should be offset, in the
+        /// component's own subsampled coordinates. Nothing outside the crop is.
+        fn spans(&self, row: usize, comp: &Component) -> [Range<usize>; 2] {
+            let (crop_columns, crop_rows) = self.crop.scaled(comp);
+            let (columns, rows) = self.region.scaled(comp);
+            if !crop_rows.contains(&row) {
+                return [0..0, 0..0];
+            }
+
+            match (rows.contains(&row), self.invert) {
+                (true, false) => [columns, 0..0],
+                (false, false) => [0..0, 0..0],
+                (true, true) => [
+                    crop_columns.start..columns.start,
+                    columns.end..crop_columns.end,
+                ],
+                (false, true) => [crop_columns, 0..0],
+            }
+        }
+    }
+
+    fn offset_row_u8(
+        line: &mut [u8],
+        comp: &Component,
+        span: Range<usize>,
+        lut: &[u16],
+        step: Option<Step>,
+    ) {
+        if comp.pixel_stride == 1 {
+            let start = comp.poffset + span.start;
+            let samples = &mut line[start..start + span.len()];
+            match step {
+                Some(Step::Wrap(offset)) => kernels::offset_wrap_u8(samples, offset),
+                Some(Step::Clamp { offset, lo, hi }) => {
+                    kernels::offset_clamp_u8(samples, offset, lo, hi)
+                }
+                None => kernels::lut_u8(samples, lut),
+            }
+            return;
+        }
+        for x in span {
+            let i = comp.poffset + x * comp.pixel_stride;
+            line[i] = lut[line[i] as usize] as u8;
+        }
+    }
+
+    fn offset_row_u16(
+        line: &mut [u8],
+        comp: &Component,
+        span: Range<usize>,
+        lut: &[u16],
+        little_endian: bool,
+    ) {
+        // Mask off anything above the component's depth so stray bits can't index
+        // past the end of the table.
+        let max = lut.len() - 1;
+        for x in span {
+            let i = comp.poffset + x * comp.pixel_stride;
+            let bytes = [line[i], line[i + 1]];
+            let raw = if little_endian {
+                u16::from_le_bytes(bytes)
+            } else {
+                u16::from_be_bytes(bytes)
+            };
+            let out = lut[(raw >> comp.shift) as usize & max] << comp.shift;
+            let bytes = if little_endian {
+                out.to_le_bytes()
+            } else {
+                out.to_be_bytes()
+            };
+            line[i..i + 2].copy_from_slice(&bytes);
+        }
+    }
+
+    /// Offset one row of v210. `spans` holds the ROI columns of each component.
+    fn offset_row_v210(
+        line: &mut [u8],
+        components: &[Component],
+        spans: &[[Range<usize>; 2]],
+        luts: &[Vec<u16>; 3],
+    ) {
+        for (block, block_data) in line.chunks_exact_mut(16).enumerate() {
+            for (word, word_data) in block_data.chunks_exact_mut(4).enumerate() {
+                let packed = u32::from_le_bytes((&*word_data).try_into().unwrap());
+                let mut out = packed;
+                for slot in 0..3 {
+                    let comp_index = V210_COMPONENTS[3 * word + slot];
+                    let comp = &components[comp_index];
+                    let x = block * (6 >> comp.w_sub) + V210_POSITIONS[3 * word + slot];
+                    if !spans[comp_index].iter().any(|span| span.contains(&x)) {
+                        continue;
+                    }
+                    let value = (packed >> (10 * slot)) & 0x3ff;
+                    let value = luts[comp_index][value as usize] as u32;
+                    out = (out & !(0x3ff << (10 * slot))) | (value << (10 * slot));
+                }
+                word_data.copy_from_slice(&out.to_le_bytes());
+            }
+        }
+    }
+
+    /// Where each component of `info`'s format lives, and how big it is.
+    pub(super) fn components(info: &gst_video::VideoInfo) -> Vec<Component> {
+        let format_info = info.format_info();
+        (0..format_info.n_components() as usize)
+            .map(|comp| Component {
+                plane: format_info.plane()[comp],
+                poffset: format_info.poffset()[comp] as usize,
+                pixel_stride: format_info.pixel_stride()[comp] as usize,
+                shift: format_info.shift()[comp],
+                w_sub: format_info.w_sub()[comp],
+                h_sub: format_info.h_sub()[comp],
+                width: info.comp_width(comp as u8) as usize,
+                height: info.comp_height(comp as u8) as usize,
+            })
+            .collect()
+    }
+
+    /// Everything needed to offset any row of a frame, shared between the threads
+    /// working on it.
+    struct FrameKernel<'a> {
+        components: Vec<Component>,
+        roi: Roi,
+        luts: &'a [Vec<u16>; 3],
+        steps: [Option<Step>; 3],
+        depth: u32,
+        little_endian: bool,
+        v210: bool,
+        /// Visible rows of each plane, and the bytes at the start of each of them
+        /// that hold visible samples. The rest of the stride is left alone.
+        plane_rows: [usize; 4],
+        row_bytes: [usize; 4],
+    }
+
+    impl<'a> FrameKernel<'a> {
+        fn new(
+            info: &gst_video::VideoInfo,
+            buffer: &gst::BufferRef,
+            settings: &Settings,
+            state: &'a State,
+        ) -> Self {
+            let format_info = info.format_info();
+            let (width, height) = (info.width() as usize, info.height() as usize);
+            let components = components(info);
+            let depth = format_info.depth()[0];
+            let v210 = format_info.format() == VideoFormat::V210;
+            let sample_bytes = if depth > 8 { 2 } else { 1 };
+
+            let mut plane_rows = [0; 4];
+            let mut row_bytes = [0; 4];
+            for comp in components.iter().filter(|comp| comp.width > 0) {
+                let plane = comp.plane as usize;
+                let bytes = if v210 {
+                    // Whole blocks of six pixels.
+                    width.div_ceil(6) * 16
+                } else {
+                    comp.poffset + (comp.width - 1) * comp.pixel_stride + sample_bytes
+                };
+                plane_rows[plane] = plane_rows[plane].max(comp.height);
+                row_bytes[plane] = row_bytes[plane].max(bytes);
+            }
+
+            Self {
+                components,
+                roi: Roi::new(settings, Rect::crop(buffer, width, height)),
+                luts: &state.luts,
+                steps: state.steps,
+                depth,
+                little_endian: format_info.is_le(),
+                v210,
+                plane_rows,
+                row_bytes,
+            }
+        }
+
+        /// Offset the samples of `line`, row `row` of `plane`, that are in the region
+        /// of interest.
+        fn offset_row(&self, plane: u32, row: usize, line: &mut [u8]) {
+            let line = &mut line[..self.row_bytes[plane as usize]];
+            if self.v210 {
+                let spans = self
+                    .components
+                    .iter()
+                    .map(|comp| self.roi.spans(row, comp))
+                    .collect::<Vec<_>>();
+                offset_row_v210(line, &self.components, &spans, self.luts);
+                return;
+            }
+
+            // Semi-planar and packed formats interleave several components in one
+            // plane, so offset each of the components that live in this one.
+            for (index, comp) in self.components.iter().enumerate() {
+                if comp.plane != plane {
+                    continue;
+                }
+                for span in self.roi.spans(row, comp) {
+                    if self.depth > 8 {
+                        offset_row_u16(line, comp, span, &self.luts[index], self.little_endian);
+                    } else {
+                        offset_row_u8(line, comp, span, &self.luts[index], self.steps[index]);
+                    }
+                }
+            }
+        }
+    }
 
     impl VideoFilterImpl for YuvOffset {
         fn transform_frame(
@@ -197,20 +1159,82 @@ mod imp {
             out_frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
         ) -> Result<gst::FlowSuccess, gst::FlowError> {
             let settings = *self.settings.lock().unwrap();
+            let state = self.update_state(&settings, in_frame.format_info().depth()[0]);
+            let kernel = FrameKernel::new(in_frame.info(), in_frame.buffer(), &settings, state.as_ref().unwrap());
+            let n_threads = kernels::thread_count(settings.n_threads);
 
-            for plane in 0..in_frame.n_planes() {
-                let offset = match plane {
-                    0 => settings.y,
-                    1 => settings.u,
-                    2 => settings.v,
-                    _ => unreachable!(),
-                };
-                let in_plane = in_frame.plane_data(plane).unwrap();
-                let out_plane = out_frame.plane_data_mut(plane).unwrap();
-                assert_eq!(in_plane.len(), out_plane.len());
-                out_plane.iter_mut().enumerate().for_each(|(i, x)| {
-                    *x = (in_plane[i] as i32).wrapping_add(offset) as u8;
+            // Every plane is handed to the same threads, so the frame only starts
+            // them once.
+            let in_strides = in_frame.plane_stride();
+            let out_strides = out_frame.plane_stride().to_vec();
+            let planes = out_frame
+                .planes_data_mut()
+                .into_iter()
+                .take(in_frame.n_planes() as usize)
+                .enumerate()
+                .map(|(plane, output)| kernels::RowPairs {
+                    input: in_frame.plane_data(plane as u32).unwrap(),
+                    in_stride: in_strides[plane] as usize,
+                    output,
+                    out_stride: out_strides[plane] as usize,
+                    rows: kernel.plane_rows[plane],
+                })
+                .collect();
+            kernels::for_each_plane_row_pair(planes, n_threads, |plane, row, in_line, out_line| {
+                // Anything outside the region of interest passes through as it is,
+                // but the padding of the output is never written.
+                let row_bytes = kernel.row_bytes[plane];
+                out_line[..row_bytes].copy_from_slice(&in_line[..row_bytes]);
+                kernel.offset_row(plane as u32, row, out_line);
+            });
+            drop(state);
+
+            if settings.post_metrics {
+                self.post_frame_metrics(in_frame, out_frame);
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+
+        fn transform_frame_ip(
+            &self,
+            frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let settings = *self.settings.lock().unwrap();
+
+            // The metrics need the original samples, so hang on to a copy of them.
+            let input = if settings.post_metrics {
+                let buffer = frame.buffer().copy_deep().map_err(|_| gst::FlowError::Error)?;
+                let input = gst_video::VideoFrame::from_buffer_readable(buffer, frame.info())
+                    .map_err(|_| gst::FlowError::Error)?;
+                Some(input)
+            } else {
+                None
+            };
+
+            let state = self.update_state(&settings, frame.format_info().depth()[0]);
+            let kernel = FrameKernel::new(frame.info(), frame.buffer(), &settings, state.as_ref().unwrap());
+            let n_threads = kernels::thread_count(settings.n_threads);
+
+            let strides = frame.plane_stride().to_vec();
+            let n_planes = frame.n_planes() as usize;
+            let planes = frame
+                .planes_data_mut()
+                .into_iter()
+                .take(n_planes)
+                .enumerate()
+                .map(|(plane, data)| kernels::Rows {
+                    data,
+                    stride: strides[plane] as usize,
+                    rows: kernel.plane_rows[plane],
                 })
+                .collect();
+            kernels::for_each_plane_row(planes, n_threads, |plane, row, line| {
+                kernel.offset_row(plane as u32, row, line);
+            });
+            drop(state);
+
+            if let Some(input) = input {
+                self.post_frame_metrics(&input.as_video_frame_ref(), frame);
             }
             Ok(gst::FlowSuccess::Ok)
         }
@@ -221,17 +1245,19 @@ mod imp {
         use super::*;
         use test::Bencher;
 
-        fn setup_benchmarks(width: u32, height: u32, b: &mut Bencher) {
+        fn setup_benchmarks(width: u32, height: u32, n_threads: u32, b: &mut Bencher) {
             let _ = gst::init();
             let settings = test::black_box(Settings {
                 y: 10,
                 u: 10,
                 v: 10,
+                n_threads,
+                ..Default::default()
             });
             
             let plugin = YuvOffset {
                 settings: Mutex::new(settings),
-                state: Mutex::new(None),
+                ..Default::default()
             };
 
             let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::I420, width, height)
@@ -253,19 +1279,1042 @@ mod imp {
             });
         }
 
+        const LUMA: [u8; 8] = [0, 100, 200, 250, 5, 128, 240, 255];
+
+        /// Offset a 4x2 I420 frame with the given luma and neutral chroma.
+        fn run_plugin_i420_tests(
+            settings: Settings,
+            luma: [u8; 8],
+        ) -> (
+            gst_video::VideoFrame<gst_video::video_frame::Readable>,
+            gst_video::VideoFrame<gst_video::video_frame::Writable>,
+        ) {
+            let _ = gst::init();
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2)
+                .build()
+                .unwrap();
+            let mut data_inframe = vec![128; info.size()];
+            data_inframe[..8].copy_from_slice(&luma);
+            run_plugin_tests(settings, &info, data_inframe)
+        }
+
+        fn run_plugin_tests(
+            settings: Settings,
+            info: &gst_video::VideoInfo,
+            data_inframe: Vec<u8>,
+        ) -> (
+            gst_video::VideoFrame<gst_video::video_frame::Readable>,
+            gst_video::VideoFrame<gst_video::video_frame::Writable>,
+        ) {
+            let _ = gst::init();
+
+            let plugin = YuvOffset {
+                settings: Mutex::new(settings),
+                ..Default::default()
+            };
+
+            let buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            let inframe = gst_video::VideoFrame::from_buffer_readable(buffer_inframe, info).unwrap();
+
+            let buffer_outframe = gst::Buffer::from_slice(vec![0; info.size()]);
+            let mut outframe = gst_video::VideoFrame::from_buffer_writable(buffer_outframe, info).unwrap();
+
+            plugin
+                .transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+                .unwrap();
+            (inframe, outframe)
+        }
+
+        /// Mean squared error over the visible samples of a plane.
+        fn plane_mse(
+            inframe: &gst_video::VideoFrame<gst_video::video_frame::Readable>,
+            outframe: &gst_video::VideoFrame<gst_video::video_frame::Writable>,
+            plane: u32,
+        ) -> f64 {
+            let width = inframe.comp_width(plane as u8) as usize;
+            let height = inframe.comp_height(plane as u8) as usize;
+            let in_stride = inframe.plane_stride()[plane as usize] as usize;
+            let out_stride = outframe.plane_stride()[plane as usize] as usize;
+            let in_data = inframe.plane_data(plane).unwrap();
+            let out_data = outframe.plane_data(plane).unwrap();
+
+            let mut sum = 0.0;
+            for row in 0..height {
+                for col in 0..width {
+                    let diff = out_data[row * out_stride + col] as f64 - in_data[row * in_stride + col] as f64;
+                    sum += diff * diff;
+                }
+            }
+            sum / (width * height) as f64
+        }
+
+        fn luma_settings(overflow: Overflow) -> Settings {
+            Settings {
+                y: 10,
+                overflow,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_overflow_wrap_mse() {
+            let (inframe, outframe) = run_plugin_i420_tests(luma_settings(Overflow::Wrap), LUMA);
+            assert_eq!(&outframe.plane_data(0).unwrap()[..8], [10, 110, 210, 4, 15, 138, 250, 9]);
+            assert_eq!(plane_mse(&inframe, &outframe, 0), 15204.0);
+            assert_eq!(plane_mse(&inframe, &outframe, 1), 0.0);
+            assert_eq!(plane_mse(&inframe, &outframe, 2), 0.0);
+        }
+
+        #[test]
+        fn test_overflow_saturate_legal_mse() {
+            let (inframe, outframe) = run_plugin_i420_tests(luma_settings(Overflow::SaturateLegal), LUMA);
+            assert_eq!(&outframe.plane_data(0).unwrap()[..8], [16, 110, 210, 235, 16, 138, 235, 235]);
+            assert_eq!(plane_mse(&inframe, &outframe, 0), 165.875);
+            assert_eq!(plane_mse(&inframe, &outframe, 1), 0.0);
+            assert_eq!(plane_mse(&inframe, &outframe, 2), 0.0);
+        }
+
+        #[test]
+        fn test_overflow_saturate_full_mse() {
+            let (inframe, outframe) = run_plugin_i420_tests(luma_settings(Overflow::SaturateFull), LUMA);
+            assert_eq!(&outframe.plane_data(0).unwrap()[..8], [10, 110, 210, 255, 15, 138, 250, 255]);
+            assert_eq!(plane_mse(&inframe, &outframe, 0), 78.125);
+            assert_eq!(plane_mse(&inframe, &outframe, 1), 0.0);
+            assert_eq!(plane_mse(&inframe, &outframe, 2), 0.0);
+        }
+
+        #[test]
+        fn test_overflow_reflect_mse() {
+            let (inframe, outframe) = run_plugin_i420_tests(luma_settings(Overflow::Reflect), LUMA);
+            assert_eq!(&outframe.plane_data(0).unwrap()[..8], [10, 110, 210, 250, 15, 138, 250, 245]);
+            assert_eq!(plane_mse(&inframe, &outframe, 0), 87.5);
+            assert_eq!(plane_mse(&inframe, &outframe, 1), 0.0);
+            assert_eq!(plane_mse(&inframe, &outframe, 2), 0.0);
+        }
+
+        #[test]
+        fn test_overflow_reflect_negative() {
+            let settings = Settings {
+                y: -10,
+                overflow: Overflow::Reflect,
+                ..Default::default()
+            };
+            let (_, outframe) = run_plugin_i420_tests(settings, LUMA);
+            assert_eq!(&outframe.plane_data(0).unwrap()[..8], [10, 90, 190, 240, 5, 118, 230, 245]);
+        }
+
+        /// Offset a blank 4x2 frame by y=1, u=2, v=3 and return the output.
+        fn run_plugin_format_tests(
+            format: VideoFormat,
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let _ = gst::init();
+            let settings = Settings {
+                y: 1,
+                u: 2,
+                v: 3,
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(format, 4, 2).build().unwrap();
+            let (_, outframe) = run_plugin_tests(settings, &info, vec![0; info.size()]);
+            outframe
+        }
+
+        #[test]
+        fn test_format_nv12() {
+            let outframe = run_plugin_format_tests(VideoFormat::Nv12);
+            assert_eq!(outframe.plane_data(0).unwrap(), [1; 8]);
+            assert_eq!(outframe.plane_data(1).unwrap(), [2, 3, 2, 3]);
+        }
+
+        #[test]
+        fn test_format_nv21() {
+            let outframe = run_plugin_format_tests(VideoFormat::Nv21);
+            assert_eq!(outframe.plane_data(0).unwrap(), [1; 8]);
+            assert_eq!(outframe.plane_data(1).unwrap(), [3, 2, 3, 2]);
+        }
+
+        #[test]
+        fn test_format_yv12() {
+            let outframe = run_plugin_format_tests(VideoFormat::Yv12);
+            assert_eq!(outframe.plane_data(0).unwrap(), [1; 8]);
+            assert!(outframe.plane_data(1).unwrap().iter().all(|&x| x == 3));
+            assert!(outframe.plane_data(2).unwrap().iter().all(|&x| x == 2));
+        }
+
+        #[test]
+        fn test_format_planar() {
+            for format in [VideoFormat::Y41b, VideoFormat::Y42b, VideoFormat::Y444] {
+                let outframe = run_plugin_format_tests(format);
+                assert_eq!(outframe.plane_data(0).unwrap(), [1; 8]);
+                assert!(outframe.plane_data(1).unwrap().iter().all(|&x| x == 2));
+                assert!(outframe.plane_data(2).unwrap().iter().all(|&x| x == 3));
+            }
+        }
+
+        #[test]
+        fn test_format_i420_10le() {
+            let settings = Settings {
+                y: 10,
+                overflow: Overflow::SaturateFull,
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::I42010le, 4, 2).build().unwrap();
+            let mut data_inframe = vec![0; info.size()];
+            data_inframe[..4].copy_from_slice(&1020u16.to_le_bytes().repeat(2));
+            let (_, outframe) = run_plugin_tests(settings, &info, data_inframe);
+            let luma = outframe.plane_data(0).unwrap();
+            assert_eq!(luma[..8], [0xff, 0x03, 0xff, 0x03, 10, 0, 10, 0]);
+        }
+
+        #[test]
+        fn test_format_i420_10be_wrap() {
+            let settings = Settings {
+                y: 10,
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::I42010be, 4, 2).build().unwrap();
+            let mut data_inframe = vec![0; info.size()];
+            data_inframe[..2].copy_from_slice(&1020u16.to_be_bytes());
+            let (_, outframe) = run_plugin_tests(settings, &info, data_inframe);
+            let luma = outframe.plane_data(0).unwrap();
+            assert_eq!(luma[..4], [0, 6, 0, 10]);
+        }
+
+        #[test]
+        fn test_format_p010_10le() {
+            let outframe = run_plugin_format_tests(VideoFormat::P01010le);
+            assert!(outframe.plane_data(0).unwrap().chunks_exact(2).all(|x| x == [1 << 6, 0]));
+            assert_eq!(outframe.plane_data(1).unwrap()[..8], [2 << 6, 0, 3 << 6, 0, 2 << 6, 0, 3 << 6, 0]);
+        }
+
+        #[test]
+        fn test_format_y444_12le_saturate_legal() {
+            let settings = Settings {
+                overflow: Overflow::SaturateLegal,
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::Y44412le, 4, 2).build().unwrap();
+            let data_inframe = 4095u16.to_le_bytes().repeat(info.size() / 2);
+            let (_, outframe) = run_plugin_tests(settings, &info, data_inframe);
+            let legal_luma = (235u16 << 4).to_le_bytes();
+            let legal_chroma = (240u16 << 4).to_le_bytes();
+            assert!(outframe.plane_data(0).unwrap().chunks_exact(2).all(|x| x == legal_luma));
+            assert!(outframe.plane_data(1).unwrap().chunks_exact(2).all(|x| x == legal_chroma));
+            assert!(outframe.plane_data(2).unwrap().chunks_exact(2).all(|x| x == legal_chroma));
+        }
+
+        #[test]
+        fn test_format_yuy2() {
+            let outframe = run_plugin_format_tests(VideoFormat::Yuy2);
+            assert_eq!(outframe.plane_data(0).unwrap()[..8], [1, 2, 1, 3, 1, 2, 1, 3]);
+        }
+
+        #[test]
+        fn test_format_uyvy() {
+            let outframe = run_plugin_format_tests(VideoFormat::Uyvy);
+            assert_eq!(outframe.plane_data(0).unwrap()[..8], [2, 1, 3, 1, 2, 1, 3, 1]);
+        }
+
+        #[test]
+        fn test_format_v210() {
+            let outframe = run_plugin_format_tests(VideoFormat::V210);
+            let words = outframe.plane_data(0).unwrap()[..16]
+                .chunks_exact(4)
+                .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                words,
+                [
+                    2 | (1 << 10) | (3 << 20),
+                    1 | (2 << 10) | (1 << 20),
+                    3 | (1 << 10) | (2 << 20),
+                    1 | (3 << 10) | (1 << 20),
+                ]
+            );
+        }
+
+        fn convert(
+            info: &gst_video::VideoInfo,
+            data: Vec<u8>,
+            format: VideoFormat,
+        ) -> (gst_video::VideoInfo, Vec<u8>) {
+            let out_info = gst_video::VideoInfo::builder(format, info.width(), info.height())
+                .build()
+                .unwrap();
+            let mut config = gst_video::VideoConverterConfig::new();
+            config.set_dither_method(gst_video::VideoDitherMethod::None);
+            let converter = gst_video::VideoConverter::new(info, &out_info, Some(config)).unwrap();
+
+            let inframe = gst_video::VideoFrame::from_buffer_readable(gst::Buffer::from_slice(data), info).unwrap();
+            let buffer_outframe = gst::Buffer::with_size(out_info.size()).unwrap();
+            let mut outframe = gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &out_info).unwrap();
+            converter.frame(&inframe, &mut outframe);
+            let data = outframe.into_buffer().map_readable().unwrap().to_vec();
+            (out_info, data)
+        }
+
+        /// Offset a frame in a packed `format` and then convert it to I420, and
+        /// compare that with converting it to I420 first and offsetting afterwards.
+        /// Returns the largest difference between any two visible samples.
+        fn packed_vs_i420_max_diff(format: VideoFormat, packed: Settings, i420: Settings) -> i32 {
+            let _ = gst::init();
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 8, 4).build().unwrap();
+            let data = (0..info.size()).map(|i| 40 + (i * 7 % 160) as u8).collect();
+            let (packed_info, packed_data) = convert(&info, data, format);
+
+            let (_, outframe) = run_plugin_tests(packed, &packed_info, packed_data.clone());
+            let out_data = outframe.into_buffer().map_readable().unwrap().to_vec();
+            let (_, expected) = convert(&packed_info, out_data, VideoFormat::I420);
+
+            let (i420_info, i420_data) = convert(&packed_info, packed_data, VideoFormat::I420);
+            let (_, outframe) = run_plugin_tests(i420, &i420_info, i420_data);
+            let actual = outframe.into_buffer().map_readable().unwrap().to_vec();
+
+            let mut max_diff = 0;
+            for plane in 0..3 {
+                let offset = i420_info.offset()[plane];
+                let stride = i420_info.stride()[plane] as usize;
+                for row in 0..i420_info.comp_height(plane as u8) as usize {
+                    for col in 0..i420_info.comp_width(plane as u8) as usize {
+                        let i = offset + row * stride + col;
+                        max_diff = max_diff.max((expected[i] as i32 - actual[i] as i32).abs());
+                    }
+                }
+            }
+            max_diff
+        }
+
+        #[test]
+        fn test_yuy2_matches_i420() {
+            let settings = Settings {
+                y: 12,
+                u: -7,
+                v: 5,
+                overflow: Overflow::SaturateFull,
+                ..Default::default()
+            };
+            assert!(packed_vs_i420_max_diff(VideoFormat::Yuy2, settings, settings) <= 1);
+        }
+
+        #[test]
+        fn test_uyvy_matches_i420() {
+            let settings = Settings {
+                y: -9,
+                u: 4,
+                v: -11,
+                overflow: Overflow::SaturateFull,
+                ..Default::default()
+            };
+            assert!(packed_vs_i420_max_diff(VideoFormat::Uyvy, settings, settings) <= 1);
+        }
+
+        #[test]
+        fn test_v210_matches_i420() {
+            let packed = Settings {
+                y: 48,
+                u: -20,
+                v: 12,
+                overflow: Overflow::SaturateFull,
+                ..Default::default()
+            };
+            let i420 = Settings {
+                y: 12,
+                u: -5,
+                v: 3,
+                overflow: Overflow::SaturateFull,
+                ..Default::default()
+            };
+            assert!(packed_vs_i420_max_diff(VideoFormat::V210, packed, i420) <= 1);
+        }
+
+        fn roi_settings(invert: bool) -> Settings {
+            Settings {
+                y: 10,
+                u: 20,
+                v: 30,
+                roi_x: 2,
+                roi_y: 2,
+                roi_width: 4,
+                roi_height: 2,
+                roi_invert: invert,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_roi_i420() {
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 8, 4).build().unwrap();
+            let (_, outframe) = run_plugin_tests(roi_settings(false), &info, vec![0; info.size()]);
+            let mut luma = [0; 32];
+            for row in 2..4 {
+                luma[8 * row + 2..8 * row + 6].fill(10);
+            }
+            assert_eq!(outframe.plane_data(0).unwrap(), luma);
+            assert_eq!(outframe.plane_data(1).unwrap(), [0, 0, 0, 0, 0, 20, 20, 0]);
+            assert_eq!(outframe.plane_data(2).unwrap(), [0, 0, 0, 0, 0, 30, 30, 0]);
+        }
+
+        #[test]
+        fn test_roi_i420_invert() {
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 8, 4).build().unwrap();
+            let (_, outframe) = run_plugin_tests(roi_settings(true), &info, vec![0; info.size()]);
+            let mut luma = [10; 32];
+            for row in 2..4 {
+                luma[8 * row + 2..8 * row + 6].fill(0);
+            }
+            assert_eq!(outframe.plane_data(0).unwrap(), luma);
+            assert_eq!(outframe.plane_data(1).unwrap(), [20, 20, 20, 20, 20, 0, 0, 20]);
+            assert_eq!(outframe.plane_data(2).unwrap(), [30, 30, 30, 30, 30, 0, 0, 30]);
+        }
+
+        #[test]
+        fn test_roi_nv12_odd_edges() {
+            // Odd luma edges widen to cover every chroma sample they touch.
+            let settings = Settings {
+                roi_x: 3,
+                roi_y: 1,
+                roi_width: 2,
+                roi_height: 1,
+                ..roi_settings(false)
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::Nv12, 8, 4).build().unwrap();
+            let (_, outframe) = run_plugin_tests(settings, &info, vec![0; info.size()]);
+            let mut luma = [0; 32];
+            luma[8 + 3..8 + 5].fill(10);
+            assert_eq!(outframe.plane_data(0).unwrap(), luma);
+            assert_eq!(outframe.plane_data(1).unwrap(), [0, 0, 20, 30, 20, 30, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        }
+
+        #[test]
+        fn test_roi_yuy2_to_edge() {
+            let settings = Settings {
+                roi_x: 2,
+                roi_width: 0,
+                roi_y: 0,
+                roi_height: 0,
+                ..roi_settings(false)
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::Yuy2, 4, 1).build().unwrap();
+            let (_, outframe) = run_plugin_tests(settings, &info, vec![0; info.size()]);
+            assert_eq!(outframe.plane_data(0).unwrap(), [0, 0, 0, 0, 10, 20, 10, 30]);
+        }
+
+        #[test]
+        fn test_gain() {
+            let settings = Settings {
+                y_gain: 2.0,
+                u_gain: 0.5,
+                overflow: Overflow::SaturateFull,
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::Y444, 4, 1).build().unwrap();
+            let mut data_inframe = vec![0; info.size()];
+            data_inframe[..4].copy_from_slice(&[100, 128, 200, 20]);
+            data_inframe[4..8].copy_from_slice(&[28, 128, 228, 255]);
+            data_inframe[8..12].copy_from_slice(&[28, 128, 228, 255]);
+            let (_, outframe) = run_plugin_tests(settings, &info, data_inframe);
+            assert_eq!(outframe.plane_data(0).unwrap(), [72, 128, 255, 0]);
+            assert_eq!(outframe.plane_data(1).unwrap(), [78, 128, 178, 192]);
+            assert_eq!(outframe.plane_data(2).unwrap(), [28, 128, 228, 255]);
+        }
+
+        #[test]
+        fn test_gain_then_offset() {
+            let settings = Settings {
+                y: 5,
+                y_gain: 0.5,
+                ..Default::default()
+            };
+            let (_, outframe) = run_plugin_i420_tests(settings, LUMA);
+            assert_eq!(&outframe.plane_data(0).unwrap()[..8], [69, 119, 169, 194, 72, 133, 189, 197]);
+        }
+
+        #[test]
+        fn test_gamma_only_affects_luma() {
+            let settings = Settings {
+                y_gamma: 2.0,
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::Y444, 4, 1).build().unwrap();
+            let data_inframe = [0, 64, 128, 255].repeat(3);
+            let (_, outframe) = run_plugin_tests(settings, &info, data_inframe);
+            assert_eq!(outframe.plane_data(0).unwrap(), [0, 128, 181, 255]);
+            assert_eq!(outframe.plane_data(1).unwrap(), [0, 64, 128, 255]);
+            assert_eq!(outframe.plane_data(2).unwrap(), [0, 64, 128, 255]);
+        }
+
+        #[test]
+        fn test_gain_10bit_pivot() {
+            let settings = Settings {
+                y_gain: 0.5,
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::I42010le, 4, 2).build().unwrap();
+            let (_, outframe) = run_plugin_tests(settings, &info, vec![0; info.size()]);
+            assert!(outframe.plane_data(0).unwrap().chunks_exact(2).all(|x| x == 256u16.to_le_bytes()));
+        }
+
+        #[test]
+        fn test_default_lut_is_identity() {
+            for depth in [8, 10, 12, 16] {
+                let lut = build_lut(0, DEFAULT_GAIN, DEFAULT_GAMMA, LEGAL_LUMA, depth, DEFAULT_OVERFLOW);
+                assert!(lut.iter().enumerate().all(|(i, &x)| i == x as usize));
+            }
+        }
+
+        #[test]
+        fn test_frame_errors() {
+            let _ = gst::init();
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2).build().unwrap();
+            let mut data_inframe = vec![128; info.size()];
+            data_inframe[..8].copy_from_slice(&LUMA);
+            let mut data_outframe = data_inframe.clone();
+            data_outframe[..8].copy_from_slice(&[10, 110, 210, 4, 15, 138, 250, 9]);
+            data_outframe[8] = 131;
+
+            let inframe = gst_video::VideoFrame::from_buffer_readable(gst::Buffer::from_slice(data_inframe), &info).unwrap();
+            let mut outframe =
+                gst_video::VideoFrame::from_buffer_writable(gst::Buffer::from_slice(data_outframe), &info).unwrap();
+            let components = (0..3)
+                .map(|comp| Component {
+                    plane: comp,
+                    poffset: 0,
+                    pixel_stride: 1,
+                    shift: 0,
+                    w_sub: 0,
+                    h_sub: 0,
+                    width: inframe.comp_width(comp as u8) as usize,
+                    height: inframe.comp_height(comp as u8) as usize,
+                })
+                .collect::<Vec<_>>();
+            let errors = frame_errors(
+                &inframe.as_video_frame_ref(),
+                &outframe.as_mut_video_frame_ref(),
+                &components,
+            );
+
+            assert_eq!(errors[0].mse(), 15204.0);
+            assert_eq!(errors[0].mae(), 69.0);
+            assert!((errors[0].psnr(255.0) - 6.3112).abs() < 1e-3);
+            assert_eq!(errors[1].mse(), 4.5);
+            assert_eq!(errors[1].mae(), 1.5);
+            assert_eq!(errors[2].samples, 2);
+            assert_eq!(errors[2].psnr(255.0), f64::INFINITY);
+        }
+
+        #[test]
+        fn test_component_samples() {
+            let _ = gst::init();
+            let info = gst_video::VideoInfo::builder(VideoFormat::P01010le, 4, 2).build().unwrap();
+            let (offset, stride) = (info.offset(), info.stride());
+            let mut data = vec![0; info.size()];
+            for (i, value) in [0u16, 100, 200, 1023, 4, 5, 6, 7].into_iter().enumerate() {
+                let at = (i / 4) * stride[0] as usize + (i % 4) * 2;
+                data[at..at + 2].copy_from_slice(&(value << 6).to_le_bytes());
+            }
+            for (i, value) in [64u16, 512, 960, 1000].into_iter().enumerate() {
+                let at = offset[1] + i * 2;
+                data[at..at + 2].copy_from_slice(&(value << 6).to_le_bytes());
+            }
+
+            let frame = gst_video::VideoFrame::from_buffer_readable(gst::Buffer::from_slice(data), &info).unwrap();
+            assert_eq!(super::super::component_samples(&frame, 0), [0, 100, 200, 1023, 4, 5, 6, 7]);
+            assert_eq!(super::super::component_samples(&frame, 1), [64, 960]);
+            assert_eq!(super::super::component_samples(&frame, 2), [512, 1000]);
+        }
+
+        #[test]
+        fn test_metrics_posted_on_bus() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::YuvOffset>();
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+            element.set_property("y", 10);
+            element.set_property("post-metrics", true);
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2).build().unwrap();
+            let mut data_inframe = vec![128; info.size()];
+            data_inframe[..8].copy_from_slice(&LUMA);
+            let mut buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            buffer_inframe.get_mut().unwrap().set_offset(3);
+            let inframe = gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap();
+            let buffer_outframe = gst::Buffer::from_slice(vec![0; info.size()]);
+            let mut outframe = gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap();
+            for _ in 0..2 {
+                element
+                    .imp()
+                    .transform_frame(&inframe.as_video_frame_ref(), &mut outframe.as_mut_video_frame_ref())
+                    .unwrap();
+            }
+            element.imp().sink_event(gst::event::Eos::new());
+
+            let messages = std::iter::from_fn(|| bus.pop_filtered(&[gst::MessageType::Element]))
+                .map(|message| message.structure().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            assert_eq!(messages.len(), 3);
+            for frame in &messages[..2] {
+                assert_eq!(frame.name(), "yuvoffset-metrics");
+                assert_eq!(frame.get::<u64>("offset").unwrap(), 3);
+                assert_eq!(frame.get::<f64>("y-mse").unwrap(), 15204.0);
+                assert_eq!(frame.get::<f64>("u-mse").unwrap(), 0.0);
+            }
+            let summary = &messages[2];
+            assert_eq!(summary.name(), "yuvoffset-summary");
+            assert_eq!(summary.get::<u64>("frames").unwrap(), 2);
+            assert_eq!(summary.get::<f64>("y-mse").unwrap(), 15204.0);
+            assert_eq!(summary.get::<f64>("y-mae").unwrap(), 69.0);
+            assert_eq!(summary.get::<f64>("v-psnr").unwrap(), f64::INFINITY);
+        }
+
+        #[test]
+        fn test_no_summary_without_frames() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::YuvOffset>();
+            let bus = gst::Bus::new();
+            element.set_bus(Some(&bus));
+            element.set_property("post-metrics", true);
+            element.imp().sink_event(gst::event::Eos::new());
+            assert!(bus.pop_filtered(&[gst::MessageType::Element]).is_none());
+        }
+
+        /// Offset an awkwardly sized frame of deterministic noise, returning every
+        /// byte of the output.
+        fn run_plugin_noise_tests(format: VideoFormat, settings: Settings) -> Vec<u8> {
+            let info = gst_video::VideoInfo::builder(format, 67, 41).build().unwrap();
+            let data = (0..info.size()).map(|i| (i * 97 + 13) as u8).collect();
+            let (_, outframe) = run_plugin_tests(settings, &info, data);
+            outframe.buffer().map_readable().unwrap().to_vec()
+        }
+
+        #[test]
+        fn test_threads_match_single_thread() {
+            let settings = Settings {
+                y: 7,
+                u: -40,
+                v: 300,
+                y_gain: 1.25,
+                overflow: Overflow::Reflect,
+                roi_x: 5,
+                roi_y: 3,
+                roi_width: 40,
+                ..Default::default()
+            };
+            for format in [
+                VideoFormat::I420,
+                VideoFormat::Nv12,
+                VideoFormat::Y444,
+                VideoFormat::I42010le,
+                VideoFormat::P01010be,
+                VideoFormat::Yuy2,
+                VideoFormat::V210,
+            ] {
+                let expected = run_plugin_noise_tests(format, settings);
+                for n_threads in [0, 2, 3, 8, 64] {
+                    let threaded = run_plugin_noise_tests(format, Settings { n_threads, ..settings });
+                    assert!(threaded == expected, "{format:?} on {n_threads} threads");
+                }
+            }
+        }
+
+        #[test]
+        fn test_simd_matches_scalar() {
+            // Y and V go through the tables and U through the SIMD offset; check
+            // every sample against the tables applied one at a time.
+            let settings = Settings {
+                y: 20,
+                u: -20,
+                v_gain: 0.5,
+                y_gamma: 2.2,
+                n_threads: 4,
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 67, 41).build().unwrap();
+            let data = (0..info.size()).map(|i| (i * 97 + 13) as u8).collect();
+            let (inframe, outframe) = run_plugin_tests(settings, &info, data);
+
+            let state = State::new(&settings, 8);
+            for plane in 0..3 {
+                let width = inframe.comp_width(plane as u8) as usize;
+                let stride = inframe.plane_stride()[plane as usize] as usize;
+                let in_data = inframe.plane_data(plane).unwrap();
+                let out_data = outframe.plane_data(plane).unwrap();
+                for (in_line, out_line) in in_data.chunks(stride).zip(out_data.chunks(stride)) {
+                    let mut expected = in_line[..width].to_vec();
+                    kernels::lut_u8(&mut expected, &state.luts[plane as usize]);
+                    assert_eq!(out_line[..width], expected);
+                }
+            }
+        }
+
+        #[test]
+        fn test_step_matches_lut() {
+            // A plain offset skips the table, so check it gives what the table would.
+            let comp = Component {
+                plane: 0,
+                poffset: 0,
+                pixel_stride: 1,
+                shift: 0,
+                w_sub: 0,
+                h_sub: 0,
+                width: 256,
+                height: 1,
+            };
+            for overflow in [Overflow::Wrap, Overflow::SaturateLegal, Overflow::SaturateFull] {
+                for offset in [-300, -20, 0, 20, 300] {
+                    let lut = build_lut(offset, 1.0, 1.0, LEGAL_CHROMA, 8, overflow);
+                    let step = Step::new(offset, 1.0, 1.0, LEGAL_CHROMA, overflow);
+                    assert!(step.is_some());
+                    let mut line = (0..=255).collect::<Vec<u8>>();
+                    let expected = line.iter().map(|&x| lut[x as usize] as u8).collect::<Vec<_>>();
+                    offset_row_u8(&mut line, &comp, 0..256, &lut, step);
+                    assert_eq!(line, expected, "{overflow:?} by {offset}");
+                }
+            }
+            assert!(Step::new(20, 1.0, 1.0, LEGAL_LUMA, Overflow::Reflect).is_none());
+            assert!(Step::new(20, 0.5, 1.0, LEGAL_LUMA, Overflow::Wrap).is_none());
+        }
+
+        /// Info for a frame whose planes are `strides` apart, one after another.
+        /// Plane `n` must hold component `n`. Also returns the size of the frame.
+        fn padded_info(
+            format: VideoFormat,
+            width: u32,
+            height: u32,
+            strides: &[i32],
+        ) -> (gst_video::VideoInfo, usize) {
+            let tight = gst_video::VideoInfo::builder(format, width, height).build().unwrap();
+            let mut offsets = vec![];
+            let mut size = 0;
+            for (plane, stride) in strides.iter().enumerate() {
+                offsets.push(size);
+                size += *stride as usize * tight.comp_height(plane as u8) as usize;
+            }
+            let info = gst_video::VideoInfo::builder(format, width, height)
+                .stride(strides)
+                .offset(&offsets)
+                .build()
+                .unwrap();
+            (info, size)
+        }
+
+        #[test]
+        fn test_padded_strides() {
+            let _ = gst::init();
+            let settings = Settings {
+                y: 9,
+                u: -5,
+                v: 3,
+                roi_x: 2,
+                ..Default::default()
+            };
+            let plugin = YuvOffset {
+                settings: Mutex::new(settings),
+                ..Default::default()
+            };
+
+            // The bytes of each row that hold visible samples of a 10 pixel wide frame.
+            for (format, row_bytes) in [
+                (VideoFormat::I420, &[10, 5, 5][..]),
+                (VideoFormat::Nv12, &[10, 10]),
+                (VideoFormat::Yuy2, &[20]),
+                (VideoFormat::I42010le, &[20, 10, 10]),
+                (VideoFormat::V210, &[32]),
+            ] {
+                let tight = gst_video::VideoInfo::builder(format, 10, 6).build().unwrap();
+                let data = (0..tight.size()).map(|i| (i * 97 + 13) as u8).collect::<Vec<_>>();
+                let (_, expected) = run_plugin_tests(settings, &tight, data.clone());
+
+                let in_strides = row_bytes.iter().map(|b| *b as i32 + 12).collect::<Vec<_>>();
+                let out_strides = row_bytes.iter().map(|b| *b as i32 + 4).collect::<Vec<_>>();
+                let (in_info, in_size) = padded_info(format, 10, 6, &in_strides);
+                let (out_info, out_size) = padded_info(format, 10, 6, &out_strides);
+
+                // Copy the visible samples in, and mark the padding around them.
+                let mut data_inframe = vec![0xee; in_size];
+                for (plane, bytes) in row_bytes.iter().enumerate() {
+                    for row in 0..tight.comp_height(plane as u8) as usize {
+                        let from = tight.offset()[plane] + row * tight.stride()[plane] as usize;
+                        let to = in_info.offset()[plane] + row * in_strides[plane] as usize;
+                        data_inframe[to..to + bytes].copy_from_slice(&data[from..from + bytes]);
+                    }
+                }
+
+                let buffer_inframe = gst::Buffer::from_slice(data_inframe.clone());
+                let inframe = gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &in_info).unwrap();
+                let buffer_outframe = gst::Buffer::from_slice(vec![0x55; out_size]);
+                let mut outframe = gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &out_info).unwrap();
+                plugin
+                    .transform_frame(
+                        &inframe.as_video_frame_ref(),
+                        &mut outframe.as_mut_video_frame_ref(),
+                    )
+                    .unwrap();
+
+                let buffer_ip = gst::Buffer::from_slice(data_inframe);
+                let mut frame_ip = gst_video::VideoFrame::from_buffer_writable(buffer_ip, &in_info).unwrap();
+                plugin.transform_frame_ip(&mut frame_ip.as_mut_video_frame_ref()).unwrap();
+
+                for (plane, bytes) in row_bytes.iter().enumerate() {
+                    let expected_lines = expected
+                        .plane_data(plane as u32)
+                        .unwrap()
+                        .chunks(expected.plane_stride()[plane] as usize);
+                    let out_lines = outframe
+                        .plane_data(plane as u32)
+                        .unwrap()
+                        .chunks(out_strides[plane] as usize);
+                    let ip_lines = frame_ip
+                        .plane_data(plane as u32)
+                        .unwrap()
+                        .chunks(in_strides[plane] as usize);
+                    for ((expected_line, out_line), ip_line) in
+                        expected_lines.zip(out_lines).zip(ip_lines)
+                    {
+                        assert_eq!(out_line[..*bytes], expected_line[..*bytes], "{format:?}");
+                        assert_eq!(ip_line[..*bytes], expected_line[..*bytes], "{format:?}");
+                        assert!(out_line[*bytes..].iter().all(|b| *b == 0x55), "{format:?}");
+                        assert!(ip_line[*bytes..].iter().all(|b| *b == 0xee), "{format:?}");
+                    }
+                }
+            }
+        }
+
+        /// Offset an 8x4 I420 frame, with luma of 100 and neutral chroma, that has
+        /// been cropped to `rect`.
+        fn run_plugin_crop_tests(
+            settings: Settings,
+            rect: (u32, u32, u32, u32),
+        ) -> gst_video::VideoFrame<gst_video::video_frame::Writable> {
+            let _ = gst::init();
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 8, 4).build().unwrap();
+            let mut data_inframe = vec![128; info.size()];
+            data_inframe[..32].fill(100);
+            let mut buffer_inframe = gst::Buffer::from_slice(data_inframe);
+            gst_video::VideoCropMeta::add(buffer_inframe.get_mut().unwrap(), rect);
+            let inframe = gst_video::VideoFrame::from_buffer_readable(buffer_inframe, &info).unwrap();
+
+            let plugin = YuvOffset {
+                settings: Mutex::new(settings),
+                ..Default::default()
+            };
+            let buffer_outframe = gst::Buffer::from_slice(vec![0; info.size()]);
+            let mut outframe = gst_video::VideoFrame::from_buffer_writable(buffer_outframe, &info).unwrap();
+            plugin
+                .transform_frame(
+                    &inframe.as_video_frame_ref(),
+                    &mut outframe.as_mut_video_frame_ref(),
+                )
+                .unwrap();
+            outframe
+        }
+
+        #[test]
+        fn test_crop_meta() {
+            let settings = Settings {
+                y: 10,
+                u: 10,
+                ..Default::default()
+            };
+            let outframe = run_plugin_crop_tests(settings, (2, 1, 4, 2));
+            #[rustfmt::skip]
+            let luma = [
+                100, 100, 100, 100, 100, 100, 100, 100,
+                100, 100, 110, 110, 110, 110, 100, 100,
+                100, 100, 110, 110, 110, 110, 100, 100,
+                100, 100, 100, 100, 100, 100, 100, 100,
+            ];
+            assert_eq!(outframe.plane_data(0).unwrap(), luma);
+            assert_eq!(outframe.plane_data(1).unwrap(), [128, 138, 138, 128, 128, 138, 138, 128]);
+            assert_eq!(outframe.plane_data(2).unwrap(), [128; 8]);
+        }
+
+        #[test]
+        fn test_roi_relative_to_crop() {
+            let settings = Settings {
+                y: 10,
+                roi_x: 1,
+                roi_width: 2,
+                roi_invert: true,
+                ..Default::default()
+            };
+            let outframe = run_plugin_crop_tests(settings, (2, 1, 4, 2));
+            #[rustfmt::skip]
+            let luma = [
+                100, 100, 100, 100, 100, 100, 100, 100,
+                100, 100, 110, 100, 100, 110, 100, 100,
+                100, 100, 110, 100, 100, 110, 100, 100,
+                100, 100, 100, 100, 100, 100, 100, 100,
+            ];
+            assert_eq!(outframe.plane_data(0).unwrap(), luma);
+        }
+
+        #[test]
+        fn test_in_place_matches_copy() {
+            let _ = gst::init();
+            let settings = Settings {
+                y: 10,
+                u: -3,
+                y_gain: 1.5,
+                roi_x: 1,
+                ..Default::default()
+            };
+            let (_, expected) = run_plugin_i420_tests(settings, LUMA);
+
+            let plugin = YuvOffset {
+                settings: Mutex::new(settings),
+                ..Default::default()
+            };
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2).build().unwrap();
+            let mut data = vec![128; info.size()];
+            data[..8].copy_from_slice(&LUMA);
+            let mut frame =
+                gst_video::VideoFrame::from_buffer_writable(gst::Buffer::from_slice(data), &info)
+                    .unwrap();
+            plugin.transform_frame_ip(&mut frame.as_mut_video_frame_ref()).unwrap();
+
+            for plane in 0..3 {
+                assert_eq!(
+                    frame.plane_data(plane).unwrap(),
+                    expected.plane_data(plane).unwrap()
+                );
+            }
+        }
+
+        #[test]
+        fn test_writable_buffer_offset_in_place() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::YuvOffset>();
+            element.set_property("y", 10);
+            assert!(element.is_in_place());
+
+            let outputs = std::sync::Arc::new(Mutex::new(Vec::new()));
+            let src = gst::Pad::builder(gst::PadDirection::Src).build();
+            let sink = gst::Pad::builder(gst::PadDirection::Sink)
+                .chain_function({
+                    let outputs = outputs.clone();
+                    move |_, _, buffer| {
+                        outputs.lock().unwrap().push(buffer);
+                        Ok(gst::FlowSuccess::Ok)
+                    }
+                })
+                .build();
+            src.link(&element.static_pad("sink").unwrap()).unwrap();
+            element.static_pad("src").unwrap().link(&sink).unwrap();
+            src.set_active(true).unwrap();
+            sink.set_active(true).unwrap();
+            element.set_state(gst::State::Playing).unwrap();
+
+            let info = gst_video::VideoInfo::builder(VideoFormat::I420, 4, 2).build().unwrap();
+            assert!(src.push_event(gst::event::StreamStart::new("yuvoffset-test")));
+            assert!(src.push_event(gst::event::Caps::new(&info.to_caps().unwrap())));
+            let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+            assert!(src.push_event(gst::event::Segment::new(&segment)));
+
+            let buffer = gst::Buffer::from_mut_slice(vec![128; info.size()]);
+            let input = buffer.as_ptr();
+            assert_eq!(src.push(buffer), Ok(gst::FlowSuccess::Ok));
+            element.set_state(gst::State::Null).unwrap();
+
+            let outputs = outputs.lock().unwrap();
+            assert_eq!(outputs.len(), 1);
+            assert_eq!(outputs[0].as_ptr(), input);
+            let data = outputs[0].map_readable().unwrap();
+            assert_eq!(data[..8], [138; 8]);
+            assert_eq!(data[8..], vec![128; info.size() - 8]);
+        }
+
+        #[test]
+        fn test_passthrough_follows_settings() {
+            let _ = gst::init();
+            let element = glib::Object::new::<super::super::YuvOffset>();
+            assert!(element.is_passthrough());
+            element.set_property("y", 5);
+            assert!(!element.is_passthrough());
+            element.set_property("y", 0);
+            assert!(element.is_passthrough());
+            element.set_property("y-gain", 0.5);
+            assert!(!element.is_passthrough());
+            element.set_property("y-gain", 1.0);
+            element.set_property("overflow", Overflow::SaturateLegal);
+            assert!(!element.is_passthrough());
+            element.set_property("overflow", Overflow::Reflect);
+            assert!(element.is_passthrough());
+            element.set_property("post-metrics", true);
+            assert!(!element.is_passthrough());
+        }
+
+        fn setup_benchmarks_ip(width: u32, height: u32, b: &mut Bencher) {
+            let _ = gst::init();
+            let settings = test::black_box(Settings {
+                y: 10,
+                u: 10,
+                v: 10,
+                ..Default::default()
+            });
+
+            let plugin = YuvOffset {
+                settings: Mutex::new(settings),
+                ..Default::default()
+            };
+
+            let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::I420, width, height)
+                .build()
+                .unwrap();
+            let data_frame = vec![0; (width * height * 3) as usize];
+            let buffer_frame = gst::Buffer::from_slice(data_frame);
+            let mut frame = test::black_box(
+                gst_video::VideoFrame::from_buffer_writable(buffer_frame, &info).unwrap(),
+            );
+
+            b.iter(|| plugin.transform_frame_ip(&mut frame.as_mut_video_frame_ref()));
+        }
+
+        #[bench]
+        fn bench_plugin_4k_ip(b: &mut Bencher) {
+            setup_benchmarks_ip(4096, 2160, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p_ip(b: &mut Bencher) {
+            setup_benchmarks_ip(1920, 1080, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p_ip(b: &mut Bencher) {
+            setup_benchmarks_ip(1280, 720, b);
+        }
+
+        #[bench]
+        fn bench_plugin_4k_threads(b: &mut Bencher) {
+            setup_benchmarks(4096, 2160, 0, b);
+        }
+
+        #[bench]
+        fn bench_plugin_1080p_threads(b: &mut Bencher) {
+            setup_benchmarks(1920, 1080, 0, b);
+        }
+
+        #[bench]
+        fn bench_plugin_720p_threads(b: &mut Bencher) {
+            setup_benchmarks(1280, 720, 0, b);
+        }
+
         #[bench]
         fn bench_plugin_4k(b: &mut Bencher) {
-            setup_benchmarks(4096, 2160, b);
+            setup_benchmarks(4096, 2160, 1, b);
         }
 
         #[bench]
         fn bench_plugin_1080p(b: &mut Bencher) {
-            setup_benchmarks(1920, 1080, b);
+            setup_benchmarks(1920, 1080, 1, b);
         }
 
         #[bench]
         fn bench_plugin_720p(b: &mut Bencher) {
-            setup_benchmarks(1280, 720, b);
+            setup_benchmarks(1280, 720, 1, b);
         }
     }
 }